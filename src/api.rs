@@ -0,0 +1,108 @@
+//! HTTP access to the challenge evaluator
+
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use serde_json::Value;
+use std::error::Error;
+
+/// Evaluator endpoint and credentials. Holds one `reqwest::Client` so
+/// connections are pooled across requests; clones share the same pool.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_code: String,
+}
+
+impl ApiClient {
+    pub fn new(url: &str, port: u16, api_code: &str) -> Self {
+        ApiClient {
+            http: reqwest::Client::new(),
+            base_url: format!("{}:{}", url.trim_end_matches('/'), port),
+            api_code: api_code.to_string(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Full URL for an API path such as `/request`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn headers(&self) -> Result<HeaderMap, Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-API-Code", HeaderValue::from_str(&self.api_code)?);
+        Ok(headers)
+    }
+
+    pub async fn get(&self, path: &str) -> Result<String, Box<dyn Error>> {
+        let headers = self.headers()?;
+        let resp = self.http.get(self.url(path)).headers(headers).send().await?;
+
+        let status = resp.status();
+        let text = resp.text().await?;
+
+        if !status.is_success() {
+            Err(format!(
+                "Error - something went wrong when requesting [CODE: {}]: {}",
+                status, text
+            ))?
+        } else {
+            Ok(text)
+        }
+    }
+
+    pub async fn post(&self, path: &str, data: &Value, idempotency_key: Option<&str>) -> Result<String, Box<dyn Error>> {
+        let mut headers = self.headers()?;
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Some(key) = idempotency_key {
+            headers.insert("Idempotency-Key", HeaderValue::from_str(key)?);
+        }
+
+        let resp = self.http.post(self.url(path)).headers(headers).json(data).send().await?;
+
+        let status = resp.status();
+        let text = resp.text().await?;
+
+        if !status.is_success() {
+            Err(format!(
+                "Error - something went wrong when requesting [CODE: {}]: {}",
+                status, text
+            ))?
+        } else {
+            Ok(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_joins_host_port_and_path() {
+        let api = ApiClient::new("http://staging.example.com/", 9000, "code");
+        assert_eq!(api.base_url(), "http://staging.example.com:9000");
+        assert_eq!(api.url("/request"), "http://staging.example.com:9000/request");
+        assert_eq!(ApiClient::new("http://www.prism-challenge.com", 8082, "code").url("/submit"), "http://www.prism-challenge.com:8082/submit");
+    }
+
+    #[tokio::test]
+    async fn requests_share_one_pooled_connection() {
+        let server = crate::mock_server::MockServer::start(|_| (200, "{}".to_string())).await;
+        let api = ApiClient::new(server.host(), server.port(), "code");
+        let clone = api.clone();
+        for _ in 0..3 {
+            api.get("/request").await.unwrap();
+        }
+        clone.post("/submit", &serde_json::json!([]), None).await.unwrap();
+        assert_eq!(server.requests().len(), 4);
+        assert_eq!(server.connections(), 1);
+
+        // A separate client has its own pool
+        ApiClient::new(server.host(), server.port(), "code").get("/request").await.unwrap();
+        assert_eq!(server.connections(), 2);
+    }
+}
@@ -0,0 +1,324 @@
+//! Offline replay of logged contexts against the current allocation code
+
+use crate::investor::{CompanyLookup, InvestorProfile};
+use crate::points::SharedPointsStore;
+use crate::portfolio::PortfolioConfig;
+use crate::stocks::{Stock, fetch_historical_returns_offline};
+use crate::{complete_investment_period, handle_request, period_dates, PlannedPortfolio, RequestOutcome};
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use tracing::{info, warn};
+
+/// Fields of a `request_trace.jsonl` line needed for a replay
+#[derive(Deserialize)]
+struct LoggedRequest {
+    #[serde(default)]
+    submission_id: Option<String>,
+    raw_context: String,
+    #[serde(default)]
+    portfolio: Vec<Value>,
+    #[serde(default)]
+    allocated_cost: f64,
+}
+
+/// Counts and cost totals from one backtest run
+#[derive(Debug, Default)]
+pub struct BacktestSummary {
+    pub entries: usize,
+    pub unreadable: usize,
+    pub unparseable_profiles: usize,
+    pub skipped: usize,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub logged_cost: f64,
+    pub replayed_cost: f64,
+}
+
+impl BacktestSummary {
+    pub fn log(&self) {
+        info!("[BACKTEST] {} entries: {} unchanged, {} changed, {} skipped, {} unparseable profiles, {} unreadable lines",
+              self.entries, self.unchanged, self.changed, self.skipped, self.unparseable_profiles, self.unreadable);
+        info!("[BACKTEST] Allocated cost: logged ${:.2} vs replayed ${:.2}", self.logged_cost, self.replayed_cost);
+    }
+}
+
+/// Sorted (ticker, quantity) pairs so portfolios compare independent of order
+fn normalized(positions: impl Iterator<Item = (String, f64)>) -> Vec<(String, f64)> {
+    let mut v: Vec<(String, f64)> = positions.collect();
+    v.sort_by(|a, b| a.0.cmp(&b.0));
+    v
+}
+
+impl LoggedRequest {
+    fn positions(&self) -> Vec<(String, f64)> {
+        normalized(self.portfolio.iter().filter_map(|p| {
+            Some((p.get("ticker")?.as_str()?.to_string(), p.get("quantity")?.as_f64()?))
+        }))
+    }
+}
+
+/// Result of re-running parsing and planning for one logged context
+enum Replayed {
+    UnparseableProfile,
+    Skipped { name: String, reason: String },
+    Built { name: String, positions: Vec<(String, f64)>, cost: f64 },
+}
+
+/// Parse the logged context and plan a portfolio from cached prices only
+fn replay(
+    logged: &LoggedRequest,
+    stock_metadata: &[Stock],
+    companies: &CompanyLookup,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+) -> Replayed {
+    let Ok(mut profile) = InvestorProfile::from_context(&logged.raw_context, companies) else {
+        return Replayed::UnparseableProfile;
+    };
+    complete_investment_period(&mut profile, config);
+
+    let mut all_stocks = stock_metadata.to_vec();
+    if let Some((start, end)) = period_dates(&profile) {
+        match fetch_historical_returns_offline(&mut all_stocks, &start, &end, config.gap_bounds()) {
+            Ok(true) => {}
+            Ok(false) => warn!("[BACKTEST] No cached history for {} to {} - ranking without returns", start, end),
+            Err(e) => warn!("[BACKTEST] Could not read cached history: {}", e),
+        }
+    }
+
+    match handle_request(&profile, &all_stocks, config, points) {
+        RequestOutcome::Ready { portfolio, cost, .. } => {
+            let positions = match portfolio {
+                PlannedPortfolio::Whole(p) => normalized(p.into_iter().map(|(t, q)| (t, q as f64))),
+                PlannedPortfolio::Fractional(p) => normalized(p.into_iter()),
+            };
+            Replayed::Built { name: profile.name, positions, cost }
+        }
+        RequestOutcome::Skipped { reason, .. } => Replayed::Skipped { name: profile.name, reason },
+    }
+}
+
+fn read_trace(trace_path: &str) -> Result<String, Box<dyn Error>> {
+    Ok(std::fs::read_to_string(trace_path)
+        .map_err(|e| format!("Failed to read trace '{}': {}", trace_path, e))?)
+}
+
+/// Rebuild a portfolio for every logged context using only cached prices and
+/// compare it to the logged allocation. Nothing is submitted.
+pub fn run_backtest(
+    trace_path: &str,
+    stock_metadata: &[Stock],
+    companies: &CompanyLookup,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+) -> Result<BacktestSummary, Box<dyn Error>> {
+    let contents = read_trace(trace_path)?;
+    let mut summary = BacktestSummary::default();
+
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        summary.entries += 1;
+        let Ok(logged) = serde_json::from_str::<LoggedRequest>(line) else {
+            summary.unreadable += 1;
+            continue;
+        };
+        let (name, replayed) = match replay(&logged, stock_metadata, companies, config, points) {
+            Replayed::UnparseableProfile => {
+                summary.unparseable_profiles += 1;
+                continue;
+            }
+            Replayed::Skipped { name, reason } => {
+                info!("[BACKTEST] {} skipped: {}", name, reason);
+                summary.skipped += 1;
+                continue;
+            }
+            Replayed::Built { name, positions, cost } => {
+                summary.replayed_cost += cost;
+                (name, positions)
+            }
+        };
+        summary.logged_cost += logged.allocated_cost;
+
+        let previous = logged.positions();
+        if previous == replayed {
+            summary.unchanged += 1;
+        } else {
+            info!("[BACKTEST] {}: {:?} -> {:?}", name, previous, replayed);
+            summary.changed += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Logged vs rebuilt portfolio for one traced context
+#[derive(Debug)]
+pub struct ReplayComparison {
+    pub submission_id: Option<String>,
+    /// Client name, or why the context could not be replayed
+    pub label: String,
+    pub logged: Vec<(String, f64)>,
+    /// `None` when the replay produced no portfolio
+    pub replayed: Option<Vec<(String, f64)>>,
+}
+
+impl ReplayComparison {
+    pub fn changed(&self) -> bool {
+        self.replayed.as_ref() != Some(&self.logged)
+    }
+
+    /// Side-by-side table of logged and replayed quantities; differing rows
+    /// are marked with `*`
+    pub fn render(&self) -> String {
+        let mut out = format!("{} [{}]{}\n",
+            self.label,
+            self.submission_id.as_deref().unwrap_or("no id"),
+            if self.changed() { " CHANGED" } else { "" });
+        out.push_str(&format!("    {:<8} {:>12} {:>12}\n", "ticker", "logged", "replayed"));
+        let empty = Vec::new();
+        let replayed = self.replayed.as_ref().unwrap_or(&empty);
+        let mut tickers: Vec<&str> = self.logged.iter().chain(replayed).map(|(t, _)| t.as_str()).collect();
+        tickers.sort();
+        tickers.dedup();
+        let qty = |side: &[(String, f64)], t: &str| side.iter().find(|(s, _)| s == t).map(|(_, q)| *q);
+        let cell = |q: Option<f64>| q.map(|q| format!("{}", q)).unwrap_or_else(|| "-".to_string());
+        for t in tickers {
+            let (before, after) = (qty(&self.logged, t), qty(replayed, t));
+            let marker = if before == after && self.replayed.is_some() { ' ' } else { '*' };
+            out.push_str(&format!("  {} {:<8} {:>12} {:>12}\n", marker, t, cell(before), cell(after)));
+        }
+        out
+    }
+}
+
+/// Re-run parsing and planning for the last `last` contexts in the trace
+/// and pair each rebuilt portfolio with the logged one
+pub fn replay_last(
+    trace_path: &str,
+    last: usize,
+    stock_metadata: &[Stock],
+    companies: &CompanyLookup,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+) -> Result<Vec<ReplayComparison>, Box<dyn Error>> {
+    let contents = read_trace(trace_path)?;
+    let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+    let tail = &lines[lines.len().saturating_sub(last)..];
+
+    let mut comparisons = Vec::with_capacity(tail.len());
+    for line in tail {
+        let Ok(logged) = serde_json::from_str::<LoggedRequest>(line) else {
+            warn!("[REPLAY] Skipping unreadable trace line");
+            continue;
+        };
+        let (label, replayed) = match replay(&logged, stock_metadata, companies, config, points) {
+            Replayed::UnparseableProfile => ("(unparseable profile)".to_string(), None),
+            Replayed::Skipped { name, reason } => (format!("{} (skipped: {})", name, reason), None),
+            Replayed::Built { name, positions, .. } => (name, Some(positions)),
+        };
+        comparisons.push(ReplayComparison {
+            submission_id: logged.submission_id.clone(),
+            label,
+            logged: logged.positions(),
+            replayed,
+        });
+    }
+    Ok(comparisons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::points::PointsStore;
+    use serde_json::json;
+
+    fn scratch_trace(name: &str, lines: &[String]) -> String {
+        let dir = std::env::temp_dir().join(format!("quant_proj_backtest_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("request_trace.jsonl");
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn stocks() -> Vec<Stock> {
+        [("BKTA", 40.0, "Healthcare"), ("BKTB", 25.0, "Energy"), ("BKTC", 60.0, "Utilities"), ("BKTD", 90.0, "Technology")]
+            .iter()
+            .map(|(ticker, price, sector)| serde_json::from_value(json!({
+                "ticker": ticker, "price": price, "sector": sector, "volatility": 0.01,
+                "first_trading_date": "1990-01-02",
+            })).unwrap())
+            .collect()
+    }
+
+    fn points() -> SharedPointsStore {
+        std::sync::Arc::new(std::sync::RwLock::new(PointsStore::default()))
+    }
+
+    fn entry(message: &str, portfolio: Value, cost: f64) -> String {
+        json!({
+            "submission_id": "abc",
+            "raw_context": json!({ "message": message }).to_string(),
+            "portfolio": portfolio,
+            "allocated_cost": cost,
+        }).to_string()
+    }
+
+    const CLIENT: &str = "Ann Lee is 30 years old with a budget of $10,000.";
+
+    #[test]
+    fn backtest_summary_counts_each_outcome() {
+        let (stocks, config) = (stocks(), PortfolioConfig::default());
+        let companies = CompanyLookup::from_stocks(&stocks);
+
+        // Replay once to learn what the current code builds for the client
+        let probe = scratch_trace("probe", &[entry(CLIENT, json!([]), 0.0)]);
+        let built = replay_last(&probe, 1, &stocks, &companies, &config, &points()).unwrap().remove(0).replayed.unwrap();
+        let same: Vec<Value> = built.iter().map(|(t, q)| json!({ "ticker": t, "quantity": q })).collect();
+
+        let trace = scratch_trace("summary", &[
+            entry(CLIENT, json!(same), 1_000.0),
+            entry(CLIENT, json!([{ "ticker": "BKTA", "quantity": 1 }]), 40.0),
+            entry("no age or budget here", json!([]), 0.0),
+            entry(&format!("{} Avoid healthcare, energy, utilities and technology.", CLIENT), json!([]), 0.0),
+            "not json".to_string(),
+        ]);
+        let summary = run_backtest(&trace, &stocks, &companies, &config, &points()).unwrap();
+        assert_eq!(summary.entries, 5);
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(summary.changed, 1);
+        assert_eq!(summary.unparseable_profiles, 1);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.unreadable, 1);
+        assert_eq!(summary.logged_cost, 1_040.0);
+        assert!(summary.replayed_cost > 0.0);
+    }
+
+    #[test]
+    fn replay_last_compares_only_the_tail() {
+        let (stocks, config) = (stocks(), PortfolioConfig::default());
+        let companies = CompanyLookup::from_stocks(&stocks);
+        let client = |name: &str| format!("{} is 30 years old with a budget of $10,000.", name);
+        let trace = scratch_trace("replay_last", &[
+            entry(&client("Old Entry"), json!([]), 0.0),
+            entry(&client("Ann Lee"), json!([{ "ticker": "BKTA", "quantity": 1 }]), 40.0),
+            entry(&client("Bo Park"), json!([{ "ticker": "BKTB", "quantity": 2 }]), 50.0),
+            entry(&client("Cy Diaz"), json!([{ "ticker": "BKTC", "quantity": 3 }]), 180.0),
+        ]);
+
+        let comparisons = replay_last(&trace, 3, &stocks, &companies, &config, &points()).unwrap();
+        assert_eq!(comparisons.len(), 3);
+        let labels: Vec<&str> = comparisons.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["Ann Lee", "Bo Park", "Cy Diaz"]);
+        assert_eq!(comparisons[2].logged, vec![("BKTC".to_string(), 3.0)]);
+        for comparison in &comparisons {
+            assert!(comparison.replayed.is_some());
+            // A one-share log never matches a rebuilt $10,000 portfolio
+            assert!(comparison.changed());
+            assert!(comparison.render().contains(" CHANGED"));
+        }
+
+        // Asking for more than the trace holds replays all of it
+        assert_eq!(replay_last(&trace, 10, &stocks, &companies, &config, &points()).unwrap().len(), 4);
+    }
+}
@@ -1,32 +1,256 @@
-use serde::Deserialize;
+use crate::stocks::Stock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 
 #[derive(Debug, Deserialize)]
 pub struct ContextResponse {
     pub message: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct InvestorProfile {
     pub name: String,
     pub age: u32,
     pub budget: f64,
     pub excluded_sectors: Vec<String>,
+    /// Individual companies the brief says to avoid, resolved to tickers
+    pub excluded_tickers: Vec<String>,
     pub risk_tolerance: RiskLevel,
     pub start_year: Option<u32>,
     pub end_year: Option<u32>,
+    /// Number of holdings the brief asks for ("keep it to 5 names")
+    pub target_positions: Option<usize>,
+    /// Sectors the brief limits rather than bans ("keep technology under
+    /// 20%"), as a fraction of the portfolio
+    pub sector_caps: HashMap<String, f64>,
+    /// Dividend income the brief asks for ("needs $2,000/month in dividends")
+    pub income_target: Option<IncomeTarget>,
+}
+
+/// Why a context could not be parsed into an `InvestorProfile`
+#[derive(Debug)]
+pub enum ParseProfileError {
+    /// The context is not `{"message": ...}` JSON
+    InvalidContext(serde_json::Error),
+    MissingAge,
+    MissingBudget,
+    /// Parsed budget outside the plausible range, usually a regex
+    /// mis-capture such as a year
+    ImplausibleBudget { budget: f64 },
+}
+
+impl fmt::Display for ParseProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseProfileError::InvalidContext(e) => write!(f, "invalid context: {}", e),
+            ParseProfileError::MissingAge => write!(f, "no age"),
+            ParseProfileError::MissingBudget => write!(f, "no budget"),
+            ParseProfileError::ImplausibleBudget { budget } => write!(f, "implausible budget ${:.2}", budget),
+        }
+    }
+}
+
+impl Error for ParseProfileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseProfileError::InvalidContext(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Income requirement from a brief
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncomeTarget {
+    /// Dollars of dividends per year
+    Annual(f64),
+    /// Portfolio dividend yield as a fraction (0.04 = 4%)
+    Yield(f64),
+}
+
+impl IncomeTarget {
+    /// Annual dividend dollars needed from a portfolio worth `budget`
+    pub fn annual_income(self, budget: f64) -> f64 {
+        match self {
+            IncomeTarget::Annual(income) => income,
+            IncomeTarget::Yield(fraction) => fraction * budget,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum RiskLevel {
     Conservative,  // Age 60+: 25% stocks
     Moderate,      // Age 40-59: 65% stocks  
     Aggressive,    // Age <40: 85% stocks
 }
 
+impl RiskLevel {
+    /// Fraction of the budget this risk level puts into equities
+    pub fn equity_fraction(self) -> f64 {
+        match self {
+            RiskLevel::Conservative => 0.25,
+            RiskLevel::Moderate => 0.65,
+            RiskLevel::Aggressive => 0.85,
+        }
+    }
+}
+
+/// How loosely excluded sectors are matched against a stock
+/// (see `InvestorProfile::should_exclude_sector_extended`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionStrictness {
+    Exact,
+    Substring,
+    #[default]
+    Synonym,
+}
+
+/// Parsed budgets outside this range are treated as regex mis-captures
+/// (e.g. "$2008" picked up from a date) and the profile is rejected
+const MIN_PLAUSIBLE_BUDGET: f64 = 100.0;
+const MAX_PLAUSIBLE_BUDGET: f64 = 1e9;
+
+/// Brief keywords mapped to standardized sector names, most specific first
+const SECTOR_KEYWORDS: &[(&str, &str)] = &[
+    ("crypto assets", "Crypto"),
+    ("crypto", "Crypto"),
+    ("cryptocurrency", "Crypto"),
+    ("real estate", "Real Estate"),
+    ("construction", "Construction"),
+    ("manufacturing", "Manufacturing"),
+    ("industrials", "Industrials"),
+    ("technology", "Technology"),
+    ("tech", "Technology"),
+    ("healthcare", "Healthcare"),
+    ("health", "Healthcare"),
+    ("financials", "Financials"),
+    ("finance", "Financials"),
+    ("banking", "Financials"),
+    ("energy", "Energy"),
+    ("utilities", "Utilities"),
+    ("consumer", "Consumer"),
+];
+
+/// Phrases saying the client has no sectors to exclude
+const NO_EXCLUSION_PHRASES: &[&str] = &[
+    "no sectors to avoid",
+    "no sector to avoid",
+    "nothing to avoid",
+    "no sector restrictions",
+    "happy to invest in anything",
+    "open to any sector",
+];
+
+/// Phrases capping a sector's share: "keep technology under 20%", "limit
+/// energy to 15%", "no more than 10% in financials"
+const SECTOR_CAP_PATTERNS: &[&str] = &[
+    r"(?:keep|limit|cap)\s+(?:exposure to\s+|the\s+)?(?P<sector>[a-z]+(?: [a-z]+){0,2}?)\s+(?:under|below|to|at|within)\s+(?:at most\s+|no more than\s+)?(?P<pct>\d+(?:\.\d+)?)\s*%",
+    r"(?:no more than|at most|up to|maximum of)\s+(?P<pct>\d+(?:\.\d+)?)\s*%\s+(?:of the portfolio\s+)?(?:in|into|on)\s+(?P<sector>[a-z]+(?: [a-z]+)?)",
+];
+
+/// Trailing words dropped from company names before matching
+const COMPANY_SUFFIXES: &[&str] = &[
+    "inc", "incorporated", "corp", "corporation", "company", "co", "ltd", "plc",
+    "holdings", "group", "the", "class", "sa", "nv", "ag",
+];
+
+/// Words that may start a company name but are too generic to identify one
+/// on their own (sector words from briefs, "General", "American", ...)
+const GENERIC_NAME_WORDS: &[&str] = &[
+    "general", "american", "united", "first", "national", "international", "global",
+    "energy", "technology", "tech", "health", "healthcare", "financial", "consumer",
+    "real", "crypto", "industrial", "industrials", "utilities", "construction", "manufacturing",
+];
+
+fn normalize_company_name(name: &str) -> String {
+    let cleaned: String = name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '&' { c } else { ' ' })
+        .collect();
+    let mut words: Vec<&str> = cleaned.split_whitespace().collect();
+    while words.len() > 1 && COMPANY_SUFFIXES.contains(words.last().unwrap()) {
+        words.pop();
+    }
+    words.join(" ")
+}
+
+/// Company name -> ticker lookup built from the stock cache, used to resolve
+/// companies named in a brief
+#[derive(Debug, Default)]
+pub struct CompanyLookup {
+    /// (normalized full name, ticker)
+    names: Vec<(String, String)>,
+    /// Distinctive first word of a name -> ticker (only when unique)
+    first_words: HashMap<String, String>,
+    tickers: Vec<String>,
+}
+
+impl CompanyLookup {
+    pub fn from_stocks(stocks: &[Stock]) -> Self {
+        let names: Vec<(String, String)> = stocks.iter()
+            .map(|s| (normalize_company_name(&s.name), s.ticker.clone()))
+            .filter(|(n, _)| !n.is_empty())
+            .collect();
+
+        let mut first_word_counts: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, ticker) in &names {
+            if let Some(first) = name.split_whitespace().next() {
+                first_word_counts.entry(first.to_string()).or_default().push(ticker.clone());
+            }
+        }
+        let first_words = first_word_counts.into_iter()
+            .filter(|(word, tickers)| {
+                tickers.len() == 1 && word.len() >= 3 && !GENERIC_NAME_WORDS.contains(&word.as_str())
+            })
+            .map(|(word, mut tickers)| (word, tickers.remove(0)))
+            .collect();
+
+        CompanyLookup {
+            names,
+            first_words,
+            tickers: stocks.iter().map(|s| s.ticker.clone()).collect(),
+        }
+    }
+
+    /// Tickers of companies named (or listed by ticker) in `clause`
+    fn resolve(&self, clause: &str) -> Vec<String> {
+        let mut found: Vec<String> = Vec::new();
+        let mut push = |t: &str| {
+            if !found.iter().any(|f| f == t) {
+                found.push(t.to_string());
+            }
+        };
+
+        // Explicit tickers written in capitals ("avoid TSLA")
+        for token in clause.split(|c: char| !c.is_ascii_alphanumeric() && c != '.') {
+            if token.len() >= 2 && token.chars().all(|c| c.is_ascii_uppercase() || c == '.') && self.tickers.iter().any(|t| t == token) {
+                push(token);
+            }
+        }
+
+        let normalized = format!(" {} ", normalize_company_name(clause));
+        for (name, ticker) in &self.names {
+            if normalized.contains(&format!(" {} ", name)) {
+                push(ticker);
+            }
+        }
+        for word in normalized.split_whitespace() {
+            if let Some(ticker) = self.first_words.get(word) {
+                push(ticker);
+            }
+        }
+        found
+    }
+}
+
 impl InvestorProfile {
-    pub fn from_context(context_json: &str) -> Result<Self, Box<dyn Error>> {
-        let ctx: ContextResponse = serde_json::from_str(context_json)?;
+    pub fn from_context(context_json: &str, companies: &CompanyLookup) -> Result<Self, ParseProfileError> {
+        let ctx: ContextResponse = serde_json::from_str(context_json).map_err(ParseProfileError::InvalidContext)?;
         let msg = &ctx.message;
         let msg_lower = msg.to_lowercase();
 
@@ -34,13 +258,15 @@ impl InvestorProfile {
         // If no age is provided, default to 45 (moderate risk)
         let age = Self::extract_number(&msg_lower, r"(\d+)-year-old")
             .or_else(|| Self::extract_number(&msg_lower, r"(\d+)\s+years?\s+old"))
-            .ok_or("no age")?;
+            .or_else(|| Self::extract_age_range(&msg_lower))
+            .or_else(|| Self::extract_age_decade(&msg_lower))
+            .ok_or(ParseProfileError::MissingAge)?;
 
-        // Extract budget - pattern: "budget of $X" or "$X"
-        let budget = Self::extract_money(&msg_lower, r"budget of \$([0-9,]+)")
-            .or_else(|| Self::extract_money(&msg_lower, r"total budget of \$([0-9,]+)"))
-            .or_else(|| Self::extract_money(&msg_lower, r"\$([0-9,]+)"))
-            .ok_or("no")?;
+        // Extract budget - the dollar figure nearest "budget" (see `extract_budget`)
+        let budget = Self::extract_budget(&msg_lower).ok_or(ParseProfileError::MissingBudget)?;
+        if !(MIN_PLAUSIBLE_BUDGET..=MAX_PLAUSIBLE_BUDGET).contains(&budget) {
+            return Err(ParseProfileError::ImplausibleBudget { budget });
+        }
 
         // Extract name (first two capitalized words)
         let name = msg
@@ -49,8 +275,13 @@ impl InvestorProfile {
             .collect::<Vec<_>>()
             .join(" ");
 
+        // Sector caps first; a capped sector is allowed, so its phrase must
+        // not also count as an exclusion
+        let (sector_caps, uncapped_text) = Self::extract_sector_caps(&msg_lower);
+
         // Extract excluded sectors
-        let excluded_sectors = Self::extract_excluded_sectors(&msg_lower);
+        let excluded_sectors = Self::extract_excluded_sectors(&uncapped_text);
+        let excluded_tickers = Self::extract_excluded_tickers(msg, companies);
 
         // Extract investment dates
         // Try multiple patterns to catch "start date is 2008-08-22" or "start 2008"
@@ -60,6 +291,14 @@ impl InvestorProfile {
         let end_year = Self::extract_year(&msg_lower, r"end.*?date.*?(\d{4})")
             .or_else(|| Self::extract_year(&msg_lower, r"end.*?(\d{4})"));
 
+        // Requested number of holdings - "portfolio of about 20 stocks", "5 names"
+        let target_positions = Self::extract_number(&msg_lower, r"\b(\d+)\s+(?:different\s+|individual\s+)?(?:stocks|names|holdings|positions|companies)\b")
+            .filter(|n| *n > 0)
+            .map(|n| n as usize);
+
+        // Dividend income requirement - "$2,000/month in dividends", "4% yield"
+        let income_target = Self::extract_income_target(&msg_lower);
+
         // Determine risk level
         let risk_tolerance = match age {
             0..=39 => RiskLevel::Aggressive,
@@ -72,12 +311,38 @@ impl InvestorProfile {
             age,
             budget,
             excluded_sectors,
+            excluded_tickers,
             risk_tolerance,
             start_year,
             end_year,
+            target_positions,
+            sector_caps,
+            income_target,
         })
     }
 
+    /// "aged 40 to 50" / "age 40-50" -> midpoint of the range
+    fn extract_age_range(text: &str) -> Option<u32> {
+        let caps = regex::Regex::new(r"\baged?\s+(\d+)\s*(?:to|-)\s*(\d+)").ok()?.captures(text)?;
+        let low: u32 = caps.get(1)?.as_str().parse().ok()?;
+        let high: u32 = caps.get(2)?.as_str().parse().ok()?;
+        Some((low + high) / 2)
+    }
+
+    /// "in her 30s" -> 35, "early 40s" -> 42, "mid 40s" -> 45, "late 50s" -> 58.
+    /// A bare decade ("grew up in the 90s") is not an age, so either the
+    /// possessive or the early/mid/late qualifier is required.
+    fn extract_age_decade(text: &str) -> Option<u32> {
+        let caps = regex::Regex::new(r"\b(?:in (?:his|her|their) (?:(early|mid|late)[\s-]+)?|(early|mid|late)[\s-]+)(\d)0'?s\b").ok()?.captures(text)?;
+        let decade: u32 = caps.get(3)?.as_str().parse::<u32>().ok()? * 10;
+        let offset = match caps.get(1).or_else(|| caps.get(2)).map(|m| m.as_str()) {
+            Some("early") => 2,
+            Some("late") => 8,
+            _ => 5,
+        };
+        Some(decade + offset)
+    }
+
     fn extract_number(text: &str, pattern: &str) -> Option<u32> {
         regex::Regex::new(pattern)
             .ok()?
@@ -88,6 +353,35 @@ impl InvestorProfile {
             .ok()
     }
 
+    /// Pick the budget among all "$X" figures in `text`. Briefs may also
+    /// mention salaries or savings, so prefer "budget of/is $X", then the
+    /// figure closest to the word "budget", then closest to "invest", and
+    /// only then the first figure.
+    fn extract_budget(text: &str) -> Option<f64> {
+        if let Some(budget) = Self::extract_money(text, r"budget (?:of|is) \$([0-9,]+)") {
+            return Some(budget);
+        }
+
+        let amounts: Vec<(usize, f64)> = regex::Regex::new(r"\$([0-9,]+)").ok()?
+            .captures_iter(text)
+            .filter_map(|c| {
+                let m = c.get(1)?;
+                Some((m.start(), m.as_str().replace(",", "").parse().ok()?))
+            })
+            .collect();
+
+        for anchor in ["budget", "invest"] {
+            let anchors: Vec<usize> = text.match_indices(anchor).map(|(i, _)| i).collect();
+            if anchors.is_empty() {
+                continue;
+            }
+            return amounts.iter()
+                .min_by_key(|(pos, _)| anchors.iter().map(|a| a.abs_diff(*pos)).min().unwrap_or(usize::MAX))
+                .map(|(_, amount)| *amount);
+        }
+        amounts.first().map(|(_, amount)| *amount)
+    }
+
     fn extract_money(text: &str, pattern: &str) -> Option<f64> {
         regex::Regex::new(pattern)
             .ok()?
@@ -109,38 +403,30 @@ impl InvestorProfile {
             .ok()
     }
 
+    /// Sectors named in "avoid(s) ..." clauses. A clause runs to the end of
+    /// the sentence or a contrasting conjunction ("but", "while", ...);
+    /// negated avoidance ("doesn't avoid technology") is ignored.
     fn extract_excluded_sectors(text: &str) -> Vec<String> {
         let mut sectors = Vec::new();
-        
+
+        // Briefs that explicitly rule out any exclusion
+        if NO_EXCLUSION_PHRASES.iter().any(|p| text.contains(p)) {
+            return sectors;
+        }
+        let text = Self::without_negated_avoidance(text);
+
         // Look for "avoids" keyword
-        if !text.contains("avoids") && !text.contains("avoid") {
+        if !text.contains("avoid") {
             return sectors;
         }
+        let Ok(clause_re) = regex::Regex::new(r"avoid\w*\s+([^.;\n]+)") else {
+            return sectors;
+        };
 
-        // Map keywords to standardized sector names
-        let sector_map = [
-            ("crypto assets", "Crypto"),
-            ("crypto", "Crypto"),
-            ("cryptocurrency", "Crypto"),
-            ("real estate", "Real Estate"),
-            ("construction", "Construction"),
-            ("manufacturing", "Manufacturing"),
-            ("industrials", "Industrials"),
-            ("technology", "Technology"),
-            ("tech", "Technology"),
-            ("healthcare", "Healthcare"),
-            ("health", "Healthcare"),
-            ("financials", "Financials"),
-            ("finance", "Financials"),
-            ("banking", "Financials"),
-            ("energy", "Energy"),
-            ("utilities", "Utilities"),
-            ("consumer", "Consumer"),
-        ];
-
-        for (keyword, sector) in sector_map {
-            if text.contains(keyword) {
-                if !sectors.contains(&sector.to_string()) {
+        for cap in clause_re.captures_iter(&text) {
+            let clause = Self::until_clause_stop(&cap[1]);
+            for &(keyword, sector) in SECTOR_KEYWORDS {
+                if clause.contains(keyword) && !sectors.contains(&sector.to_string()) {
                     sectors.push(sector.to_string());
                 }
             }
@@ -149,20 +435,103 @@ impl InvestorProfile {
         sectors
     }
 
+    /// "$2,000/month in dividends", "$24,000 a year of income" -> annual
+    /// dollars; "a 4% dividend yield", "yield of at least 4%" -> yield
+    fn extract_income_target(text: &str) -> Option<IncomeTarget> {
+        let income_re = regex::Regex::new(r"\$([0-9,]+(?:\.\d+)?)\s*(?:/|per|a|an|each)\s*(month|year|annum)\s+(?:in|of)\s+(?:dividends?|income)").ok()?;
+        if let Some(cap) = income_re.captures(text) {
+            let amount: f64 = cap[1].replace(",", "").parse().ok()?;
+            let per_year = if &cap[2] == "month" { 12.0 } else { 1.0 };
+            return Some(IncomeTarget::Annual(amount * per_year));
+        }
+        let yield_re = regex::Regex::new(r"yield\s+of\s+(?:at least\s+)?(\d+(?:\.\d+)?)\s*%|(\d+(?:\.\d+)?)\s*%\s+(?:dividend\s+)?yield").ok()?;
+        let cap = yield_re.captures(text)?;
+        let pct: f64 = cap.get(1).or_else(|| cap.get(2))?.as_str().parse().ok()?;
+        Some(IncomeTarget::Yield(pct / 100.0))
+    }
+
+    /// Sector caps named in `text` (see `SECTOR_CAP_PATTERNS`), plus `text`
+    /// with the matched phrases blanked out
+    fn extract_sector_caps(text: &str) -> (HashMap<String, f64>, String) {
+        let mut caps = HashMap::new();
+        let mut remaining = text.to_string();
+        for pattern in SECTOR_CAP_PATTERNS {
+            let Ok(re) = regex::Regex::new(pattern) else { continue };
+            for cap in re.captures_iter(text) {
+                let phrase = cap.name("sector").map_or("", |m| m.as_str());
+                let Some(&(_, sector)) = SECTOR_KEYWORDS.iter().find(|(keyword, _)| phrase.contains(keyword)) else {
+                    continue;
+                };
+                let Some(pct) = cap.name("pct").and_then(|m| m.as_str().parse::<f64>().ok()) else {
+                    continue;
+                };
+                caps.insert(sector.to_string(), (pct / 100.0).clamp(0.0, 1.0));
+                remaining = remaining.replace(&cap[0], " ");
+            }
+        }
+        (caps, remaining)
+    }
+
+    /// `text` with negated avoidance ("doesn't avoid tech", "no need to
+    /// avoid anything") blanked out through the end of its clause
+    fn without_negated_avoidance(text: &str) -> std::borrow::Cow<'_, str> {
+        match regex::Regex::new(r"(?i)(?:doesn't|does not|don't|do not|won't|never|no need to)\s+(?:need to\s+|want to\s+)?avoid\w*[^.;\n]*") {
+            Ok(negated_re) => negated_re.replace_all(text, " "),
+            Err(_) => text.into(),
+        }
+    }
+
+    /// `clause` up to the first word that turns it around ("tesla but happy
+    /// with apple" -> "tesla ")
+    fn until_clause_stop(clause: &str) -> &str {
+        match regex::Regex::new(r"(?i)\b(?:but|while|although|though|however|whereas|except)\b") {
+            Ok(stop_re) => stop_re.find(clause).map_or(clause, |m| &clause[..m.start()]),
+            Err(_) => clause,
+        }
+    }
+
+    /// Companies named after "do not invest in" / "avoid" that match the cache
+    fn extract_excluded_tickers(msg: &str, companies: &CompanyLookup) -> Vec<String> {
+        let Ok(clause_re) = regex::Regex::new(r"(?i)(?:do not|don't|never) invest in\s+([^.;]+)|avoid(?:s|ing)?\s+([^.;]+)|stay away from\s+([^.;]+)") else {
+            return Vec::new();
+        };
+        let text = Self::without_negated_avoidance(msg);
+        let mut tickers: Vec<String> = Vec::new();
+        for cap in clause_re.captures_iter(&text) {
+            let clause = Self::until_clause_stop(cap.iter().skip(1).flatten().map(|m| m.as_str()).next().unwrap_or(""));
+            for ticker in companies.resolve(clause) {
+                if !tickers.contains(&ticker) {
+                    tickers.push(ticker);
+                }
+            }
+        }
+        tickers
+    }
+
     pub fn should_exclude_sector(&self, sector: &str) -> bool {
         self.excluded_sectors
             .iter()
             .any(|s| s.eq_ignore_ascii_case(sector))
     }
 
-    /// Extended exclusion check: matches by exact sector, substrings, stock name,
-    /// and a small synonym map so "Technology" will match "Software", "Internet",
-    /// "Semiconductors", etc. This is conservative: if any excluded term appears
-    /// in the stock sector or name we treat it as excluded.
-    pub fn should_exclude_sector_extended(&self, sector: &str, stock_name: &str) -> bool {
+    /// Exclusion check at the given strictness:
+    /// - `Exact`: one of the stock's comma-separated sectors equals an
+    ///   excluded sector (case-insensitive)
+    /// - `Substring`: an excluded term also appears in the sector or name
+    /// - `Synonym`: additionally a small synonym map, so "Technology" matches
+    ///   "Software", "Internet", "Semiconductors", etc.
+    ///
+    /// Looser modes over-exclude (any name containing "tech") but catch more.
+    pub fn should_exclude_sector_extended(&self, sector: &str, stock_name: &str, strictness: ExclusionStrictness) -> bool {
         if self.excluded_sectors.is_empty() {
             return false;
         }
+        if sector.split(',').any(|s| self.should_exclude_sector(s.trim())) {
+            return true;
+        }
+        if strictness == ExclusionStrictness::Exact {
+            return false;
+        }
 
         let sector_low = sector.to_ascii_lowercase();
         let name_low = stock_name.to_ascii_lowercase();
@@ -170,48 +539,182 @@ impl InvestorProfile {
         for ex in &self.excluded_sectors {
             let ex_low = ex.to_ascii_lowercase();
 
-            // Exact match or case-insensitive equality
-            if sector_low == ex_low || ex_low == name_low {
+            // Exact name match, or substring match in sector or stock name
+            if ex_low == name_low || sector_low.contains(&ex_low) || name_low.contains(&ex_low) {
                 return true;
             }
-
-            // Substring match in sector or stock name
-            if sector_low.contains(&ex_low) || name_low.contains(&ex_low) {
-                return true;
+            if strictness == ExclusionStrictness::Substring {
+                continue;
             }
 
             // Small synonyms map for common sector aliases
-            match ex_low.as_str() {
+            let synonym = match ex_low.as_str() {
                 "technology" | "tech" => {
-                    if sector_low.contains("software")
+                    sector_low.contains("software")
                         || sector_low.contains("semicon")
                         || sector_low.contains("internet")
                         || sector_low.contains("hardware")
                         || sector_low.contains("electronic")
                         || name_low.contains("tech")
-                    {
-                        return true;
-                    }
                 }
                 "manufacturing" => {
-                    if sector_low.contains("industrial")
+                    sector_low.contains("industrial")
                         || sector_low.contains("manufactur")
-                    {
-                        return true;
-                    }
                 }
                 "crypto" | "crypto assets" | "cryptocurrency" => {
-                    if sector_low.contains("crypto")
+                    sector_low.contains("crypto")
                         || sector_low.contains("blockchain")
                         || name_low.contains("coin")
-                    {
-                        return true;
-                    }
                 }
-                _ => {}
+                _ => false,
+            };
+            if synonym {
+                return true;
             }
         }
 
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(message: &str) -> Result<InvestorProfile, ParseProfileError> {
+        let context = serde_json::json!({ "message": message }).to_string();
+        InvestorProfile::from_context(&context, &CompanyLookup::default())
+    }
+
+    #[test]
+    fn age_from_decade_phrases() {
+        assert_eq!(InvestorProfile::extract_age_decade("she is in her 30s"), Some(35));
+        assert_eq!(InvestorProfile::extract_age_decade("a retiree in his late 50s"), Some(58));
+        assert_eq!(InvestorProfile::extract_age_decade("in their mid-40s"), Some(45));
+        assert_eq!(InvestorProfile::extract_age_decade("an engineer in her early 40s"), Some(42));
+        assert_eq!(InvestorProfile::extract_age_decade("grew up in the 90s"), None);
+        assert_eq!(InvestorProfile::extract_age_decade("saving since the 80s"), None);
+    }
+
+    #[test]
+    fn age_from_range() {
+        assert_eq!(InvestorProfile::extract_age_range("aged 40 to 50"), Some(45));
+        assert_eq!(InvestorProfile::extract_age_range("age 30-40"), Some(35));
+        assert_eq!(InvestorProfile::extract_age_range("usage 2 to 3"), None);
+        assert_eq!(InvestorProfile::extract_age_range("they manage 10-20 accounts"), None);
+    }
+
+    #[test]
+    fn decade_age_drives_risk_level() {
+        let profile = parse("Jo Park, in her 30s, has a budget of $10,000.").unwrap();
+        assert_eq!(profile.age, 35);
+        assert!(matches!(profile.risk_tolerance, RiskLevel::Aggressive));
+    }
+
+    #[test]
+    fn tiny_budget_is_implausible() {
+        let err = parse("Ann Lee is 30 years old with a budget of $5.").unwrap_err();
+        assert!(matches!(err, ParseProfileError::ImplausibleBudget { budget } if budget == 5.0));
+    }
+
+    #[test]
+    fn huge_budget_is_implausible() {
+        let err = parse("Ann Lee is 30 years old with a budget of $5,000,000,000.").unwrap_err();
+        assert!(matches!(err, ParseProfileError::ImplausibleBudget { budget } if budget == 5e9));
+    }
+
+    #[test]
+    fn plausible_budget_parses() {
+        assert_eq!(parse("Ann Lee is 30 years old with a budget of $25,000.").unwrap().budget, 25_000.0);
+    }
+
+    #[test]
+    fn budget_wins_over_salary() {
+        let budget = |msg: &str| parse(msg).unwrap().budget;
+        assert_eq!(budget("Ann Lee is 30 years old, earns $120,000 a year, with a budget of $40,000."), 40_000.0);
+        assert_eq!(budget("Ann Lee is 30 years old. Her salary is $95,000 and her budget: $15,000."), 15_000.0);
+        assert_eq!(budget("Ann Lee is 30 years old with $80,000 in savings. She wants to invest $20,000."), 20_000.0);
+        // A budget phrase beats an "investment is" figure further away
+        assert_eq!(
+            budget("Ann Lee is 30 years old. Her total investment is $250,000 across accounts; this budget: $30,000."),
+            30_000.0
+        );
+    }
+
+    #[test]
+    fn negated_avoidance_excludes_no_sectors() {
+        let excluded = |msg: &str| parse(msg).unwrap().excluded_sectors;
+        assert!(excluded("Ann Lee is 30 years old with a budget of $10,000. She is comfortable and doesn't avoid technology.").is_empty());
+        assert!(excluded("Ann Lee is 30 years old with a budget of $10,000. There are no sectors to avoid.").is_empty());
+        assert!(excluded("Ann Lee is 30 years old with a budget of $10,000. She is happy to invest in anything.").is_empty());
+        // The clause ends at "but", so healthcare is not excluded
+        assert_eq!(
+            excluded("Ann Lee is 30 years old with a budget of $10,000. She avoids energy but likes healthcare."),
+            vec!["Energy".to_string()]
+        );
+        assert_eq!(
+            excluded("Ann Lee is 30 years old with a budget of $10,000. She avoids energy and healthcare."),
+            vec!["Healthcare".to_string(), "Energy".to_string()]
+        );
+    }
+
+    fn companies() -> (Vec<Stock>, CompanyLookup) {
+        let stocks: Vec<Stock> = [
+            ("TSLA", "Tesla, Inc.", "Consumer Discretionary"),
+            ("XOM", "Exxon Mobil Corporation", "Energy"),
+            ("AAPL", "Apple Inc.", "Technology"),
+            ("JNJ", "Johnson & Johnson", "Healthcare"),
+        ].iter().map(|(ticker, name, sector)| serde_json::from_value(serde_json::json!({
+            "ticker": ticker, "name": name, "price": 100.0, "sector": sector, "volatility": 0.01,
+            "first_trading_date": "1990-01-02",
+        })).unwrap()).collect();
+        let lookup = CompanyLookup::from_stocks(&stocks);
+        (stocks, lookup)
+    }
+
+    fn excluded_tickers(message: &str, lookup: &CompanyLookup) -> Vec<String> {
+        let context = serde_json::json!({ "message": message }).to_string();
+        InvestorProfile::from_context(&context, lookup).unwrap().excluded_tickers
+    }
+
+    #[test]
+    fn named_companies_are_excluded() {
+        let (stocks, lookup) = companies();
+        let message = "Ann Lee is 30 years old with a budget of $10,000. Avoid Tesla and Exxon.";
+        assert_eq!(excluded_tickers(message, &lookup), vec!["TSLA", "XOM"]);
+
+        let context = serde_json::json!({ "message": message }).to_string();
+        let profile = InvestorProfile::from_context(&context, &lookup).unwrap();
+        let config = crate::portfolio::PortfolioConfig::default();
+        let kept: Vec<String> = crate::portfolio::filter_stocks_by_profile(&stocks, &profile, &config)
+            .into_iter().map(|s| s.ticker).collect();
+        assert_eq!(kept, vec!["AAPL", "JNJ"]);
+    }
+
+    #[test]
+    fn negated_or_contrasted_companies_are_kept() {
+        let (_, lookup) = companies();
+        assert!(excluded_tickers("Ann Lee is 30 years old with a budget of $10,000. She doesn't avoid Tesla.", &lookup).is_empty());
+        assert_eq!(
+            excluded_tickers("Ann Lee is 30 years old with a budget of $10,000. Avoid Tesla but happy with Apple.", &lookup),
+            vec!["TSLA"],
+        );
+        assert_eq!(excluded_tickers("Ann Lee is 30 years old with a budget of $10,000. Do not invest in XOM.", &lookup), vec!["XOM"]);
+    }
+
+    #[test]
+    fn income_targets_from_dividend_phrases() {
+        let monthly = parse("Ruth Ames is 68 years old with a budget of $600,000 and needs $2,000/month in dividends.").unwrap();
+        assert_eq!(monthly.income_target, Some(IncomeTarget::Annual(24_000.0)));
+        assert_eq!(monthly.income_target.unwrap().annual_income(monthly.budget), 24_000.0);
+
+        assert_eq!(InvestorProfile::extract_income_target("wants $15,000 a year of income"), Some(IncomeTarget::Annual(15_000.0)));
+        let yield_target = InvestorProfile::extract_income_target("looking for a 4% dividend yield").unwrap();
+        assert_eq!(yield_target, IncomeTarget::Yield(0.04));
+        assert!((yield_target.annual_income(50_000.0) - 2_000.0).abs() < 1e-9);
+        assert_eq!(InvestorProfile::extract_income_target("a yield of at least 3.5%"), Some(IncomeTarget::Yield(0.035)));
+
+        assert_eq!(parse("Ben Cole is 30 years old with a budget of $10,000.").unwrap().income_target, None);
+        assert_eq!(InvestorProfile::extract_income_target("expects 10% growth a year"), None);
+    }
+}
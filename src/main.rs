@@ -2,235 +2,914 @@ mod investor;
 mod stocks;
 mod portfolio;
 mod points;
+mod api;
+mod backtest;
+#[cfg(test)]
+mod mock_server;
 
-use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use serde_json::{json, Value};
 use std::error::Error;
 
-use investor::InvestorProfile;
-use stocks::{Stock, prefetch_all_stocks, fetch_historical_returns};
-use portfolio::{filter_stocks_by_profile, build_portfolio, BUDGET_SPEND_FRACTION};
+use api::ApiClient;
+use clap::{Parser, Subcommand};
+use investor::{CompanyLookup, IncomeTarget, InvestorProfile, ParseProfileError};
+use points::{MergeMode, PointsStore, SharedPointsStore, evaluation_deltas};
+use stocks::{Stock, cache_age, prefetch_all_stocks, fetch_historical_returns, latest_cached_year, update_monthly_cache, MonthlyUpdateOptions, YAHOO_CHART_URL};
+use portfolio::{filter_stocks_by_profile, build_portfolio, cmp_f64, fnv1a, fractionalize, load_rank_quantities, portfolio_stats, universe_features, PortfolioConfig, PortfolioPlan, PortfolioStats, VolThresholds, VOL_BUCKETS};
+use serde::Serialize;
+use std::fmt::Display;
 use std::collections::{HashMap, HashSet};
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
 use regex::Regex;
+use tracing::{debug, error, info, warn};
 
+// Defaults for the --url/--port flags (see `Cli`)
 const URL: &str = "http://www.prism-challenge.com";
 const PORT: u16 = 8082;
-const TEAM_API_CODE: &str = "f7f47b3680640b753e6cccfd14bbca89";
-
-// API Functions
-async fn send_get_request(path: &str) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert("X-API-Code", HeaderValue::from_str(TEAM_API_CODE)?);
-    let url = format!("{URL}:{PORT}{path}");
-    let resp = client.get(&url).headers(headers).send().await?;
-
-    let status = resp.status();
-    let text = resp.text().await?;
-
-    if !status.is_success() {
-        Err(format!(
-            "Error - something went wrong when requesting [CODE: {}]: {}",
-            status, text
-        ))?
-    } else {
-        Ok(text)
+// Fallbacks for the team API code when --api-code/PRISM_API_CODE is unset
+// (see `resolve_api_code`)
+const API_CODE_ENV: &str = "TEAM_API_CODE";
+const API_CODE_FILE: &str = ".api_code";
+// Number of top-scoring tickers/sectors from the points store logged at startup
+const LOG_TOP_POINTS: usize = 5;
+// Contexts fetched ahead of the one currently being processed
+const CONTEXT_PREFETCH: usize = 1;
+// Upper bound on any single submitted quantity; guards cost math against a
+// runaway allocation
+const MAX_SUBMIT_QUANTITY: f64 = 1_000_000.0;
+// Submit attempts when the connection fails before any response is received
+const SUBMIT_CONNECT_RETRIES: u32 = 3;
+const REJECTED_TICKERS_PATH: &str = "rejected_tickers.jsonl";
+// Plain one-ticker-per-line list used before the JSONL format; migrated at startup
+const LEGACY_REJECTED_TICKERS_PATH: &str = "rejected_tickers.txt";
+// Per-submission position snapshots for later attribution (see `write_snapshot`)
+const SNAPSHOTS_PATH: &str = "portfolio_snapshots.jsonl";
+// Optional JSON object mapping alias tickers to the symbol the evaluator
+// accepts, e.g. {"BRK.B": "BRK-B"}
+const TICKER_ALIASES_PATH: &str = "ticker_aliases.json";
+
+async fn get_context(api: &ApiClient, path: &str) -> Result<String, Box<dyn Error>> {
+    // Retry logic for network issues
+    for attempt in 1..=3 {
+        // Keep only the message across the sleep so this future stays Send
+        let err = match api.get(path).await {
+            Ok(response) => return Ok(response),
+            Err(e) => e.to_string(),
+        };
+        if attempt < 3 {
+            warn!("Network error (attempt {}): {}. Retrying...", attempt, err);
+            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        } else {
+            return Err(err.into());
+        }
     }
+    Err("Failed after 3 attempts".into())
 }
 
-async fn send_post_request(path: &str, data: &Value) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert("X-API-Code", HeaderValue::from_str(TEAM_API_CODE)?);
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-
-    let url = format!("{URL}:{PORT}{path}");
-    let resp = client.post(&url).headers(headers).json(data).send().await?;
-
-    let status = resp.status();
-    let text = resp.text().await?;
+/// Fetch up to `n` contexts in one request. Servers that support batching
+/// answer `?count=n` with a JSON array of contexts; anything else is treated
+/// as a single context, so this falls back to one-at-a-time fetching.
+async fn get_contexts(api: &ApiClient, n: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    if n <= 1 {
+        return Ok(vec![get_context(api, "/request").await?]);
+    }
+    let body = get_context(api, &format!("/request?count={}", n)).await?;
+    Ok(split_context_batch(body))
+}
 
-    if !status.is_success() {
-        Err(format!(
-            "Error - something went wrong when requesting [CODE: {}]: {}",
-            status, text
-        ))?
-    } else {
-        Ok(text)
+/// Split a batch response into individual context JSON strings
+fn split_context_batch(body: String) -> Vec<String> {
+    match serde_json::from_str::<Vec<Value>>(&body) {
+        Ok(batch) if !batch.is_empty() => batch.iter().map(|c| c.to_string()).collect(),
+        _ => vec![body],
     }
 }
 
-async fn get_context() -> Result<String, Box<dyn Error>> {
-    // Retry logic for network issues
-    for attempt in 1..=3 {
-        match send_get_request("/request").await {
-            Ok(response) => return Ok(response),
-            Err(e) => {
-                if attempt < 3 {
-                    eprintln!("[WARN] Network error (attempt {}): {}. Retrying...", attempt, e);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                } else {
-                    return Err(e);
+/// Fetch contexts on a background task so the next one is already waiting
+/// while earlier portfolios are built and submitted (see `run_pipeline`).
+/// Each context carries the instant it was fetched, so deadlines include time
+/// spent waiting in the channel. The producer stops after the first fetch
+/// failure.
+fn spawn_context_producer(api: ApiClient, batch_size: usize) -> tokio::sync::mpsc::Receiver<Result<(String, Instant), String>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(CONTEXT_PREFETCH);
+    tokio::spawn(async move {
+        loop {
+            let batch = match get_contexts(&api, batch_size).await.map_err(|e| e.to_string()) {
+                Ok(batch) => batch,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            };
+            let fetched_at = Instant::now();
+            for context in batch {
+                if tx.send(Ok((context, fetched_at))).await.is_err() {
+                    return;
                 }
             }
         }
-    }
-    Err("Failed after 3 attempts".into())
+    });
+    rx
 }
 
-async fn send_portfolio(weighted_stocks: Vec<(&str, i32)>) -> Result<String, Box<dyn Error>> {
-    // Submit the portfolio once. Avoid retrying POSTs because retries can
-    // trigger race conditions on the server (e.g., 403 after a late retry).
+/// Build the JSON body for a submission. Without `meta` this is the bare
+/// `[{ticker, quantity}, ...]` array; with `meta` the array is wrapped in a
+/// `{"portfolio": [...], "meta": {...}}` envelope.
+fn submission_body<Q: Serialize>(weighted_stocks: &[(&str, Q)], meta: Option<&Value>) -> Value {
     let data: Vec<Value> = weighted_stocks
-        .into_iter()
+        .iter()
         .map(|(ticker, quantity)| json!({ "ticker": ticker, "quantity": quantity }))
         .collect();
 
-    send_post_request("/submit", &json!(data)).await
+    match meta {
+        Some(meta) => json!({ "portfolio": data, "meta": meta }),
+        None => json!(data),
+    }
+}
+
+/// Metadata describing how the portfolio was built, sent with the submission
+/// when `PortfolioConfig::include_meta` is set
+fn submission_meta(profile: &InvestorProfile, config: &PortfolioConfig, alloc_budget: f64) -> Option<Value> {
+    config.include_meta.then(|| json!({
+        "risk_level": format!("{:?}", profile.risk_tolerance),
+        "allocation_mode": config.strategy.resolve(alloc_budget).name(),
+    }))
+}
+
+/// Stable key for one submission, derived from the context and the body, so
+/// a resent POST can be recognised as a duplicate. Doubles as the
+/// `submission_id` stamped on every log line written for the request.
+fn idempotency_key(raw_context: &str, body: &Value) -> String {
+    format!("{:016x}", fnv1a(&[raw_context.as_bytes(), body.to_string().as_bytes()]))
+}
+
+/// Hash of the parsed fields that identify a client, so re-served contexts
+/// that differ only in wording or whitespace map to the same key
+fn profile_fingerprint(profile: &InvestorProfile) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut sectors = profile.excluded_sectors.clone();
+    sectors.sort();
+    let mut tickers = profile.excluded_tickers.clone();
+    tickers.sort();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    profile.name.to_lowercase().split_whitespace().collect::<Vec<_>>().hash(&mut hasher);
+    profile.age.hash(&mut hasher);
+    profile.budget.to_bits().hash(&mut hasher);
+    (profile.start_year, profile.end_year, profile.target_positions).hash(&mut hasher);
+    (sectors, tickers).hash(&mut hasher);
+    let mut caps: Vec<(&String, u64)> = profile.sector_caps.iter().map(|(s, c)| (s, c.to_bits())).collect();
+    caps.sort();
+    caps.hash(&mut hasher);
+    match profile.income_target {
+        Some(IncomeTarget::Annual(income)) => (0u8, income.to_bits()).hash(&mut hasher),
+        Some(IncomeTarget::Yield(fraction)) => (1u8, fraction.to_bits()).hash(&mut hasher),
+        None => {}
+    }
+    hasher.finish()
+}
+
+/// Fingerprints of recently processed contexts, oldest first
+struct RecentContexts {
+    entries: std::collections::VecDeque<(u64, Instant)>,
+    window: Duration,
+    capacity: usize,
+}
+
+impl RecentContexts {
+    fn new(window: Duration, capacity: usize) -> Self {
+        RecentContexts { entries: std::collections::VecDeque::new(), window, capacity: capacity.max(1) }
+    }
+
+    /// True if `key` was seen within `window` of `now`; otherwise remembers
+    /// it, evicting expired and excess entries
+    fn check_and_insert(&mut self, key: u64, now: Instant) -> bool {
+        let window = self.window;
+        self.entries.retain(|(_, at)| now.saturating_duration_since(*at) <= window);
+        if self.entries.iter().any(|(k, _)| *k == key) {
+            return true;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, now));
+        false
+    }
+}
+
+/// True when no response was received: the connect failed, or the
+/// connection was reset before the server replied. A resend carries the same
+/// idempotency key, so the server can drop it if the first one got through.
+fn is_pre_response_failure(e: &(dyn Error + 'static)) -> bool {
+    let Some(re) = e.downcast_ref::<reqwest::Error>() else { return false };
+    if re.is_connect() {
+        return true;
+    }
+    if re.is_timeout() || re.status().is_some() {
+        return false;
+    }
+    let mut source = re.source();
+    while let Some(cause) = source {
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(io.kind(),
+                std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe);
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// Share quantity type accepted by `send_portfolio`
+trait SubmitQuantity: Copy + Serialize + Into<f64> {
+    /// `self` limited to at most `max` shares
+    fn capped(self, max: f64) -> Self;
+}
+
+impl SubmitQuantity for i32 {
+    fn capped(self, max: f64) -> Self {
+        self.min(max as i32)
+    }
 }
 
+impl SubmitQuantity for f64 {
+    fn capped(self, max: f64) -> Self {
+        self.min(max)
+    }
+}
+
+/// Drop non-positive (or non-finite) quantities and cap the rest at
+/// `MAX_SUBMIT_QUANTITY`. Errors if nothing is left to submit.
+fn sanitize_quantities<Q: SubmitQuantity>(weighted_stocks: Vec<(&str, Q)>) -> Result<Vec<(&str, Q)>, Box<dyn Error>> {
+    let mut cleaned = Vec::with_capacity(weighted_stocks.len());
+    for (ticker, qty) in weighted_stocks {
+        let value: f64 = qty.into();
+        if !value.is_finite() || value <= 0.0 {
+            warn!("[VALIDATOR] Dropping {} with invalid quantity {}", ticker, value);
+            continue;
+        }
+        if value > MAX_SUBMIT_QUANTITY {
+            warn!("[VALIDATOR] Capping {} quantity {} at {}", ticker, value, MAX_SUBMIT_QUANTITY);
+        }
+        cleaned.push((ticker, qty.capped(MAX_SUBMIT_QUANTITY)));
+    }
+    if cleaned.is_empty() {
+        return Err("No positions with a valid quantity to submit".into());
+    }
+    Ok(cleaned)
+}
+
+/// Quantities are whole shares (`i32`) by default, or `f64` in fractional-share mode.
+async fn send_portfolio<Q: SubmitQuantity>(api: &ApiClient, weighted_stocks: Vec<(&str, Q)>, meta: Option<&Value>, submission_id: &str) -> Result<String, Box<dyn Error>> {
+    let weighted_stocks = sanitize_quantities(weighted_stocks)?;
+    // Retrying after the server has responded can trigger race conditions
+    // (e.g., 403 after a late retry), so only failures before any response
+    // (connect errors and resets) are retried
+    let body = submission_body(&weighted_stocks, meta);
+    for attempt in 1..=SUBMIT_CONNECT_RETRIES {
+        match api.post("/submit", &body, Some(submission_id)).await {
+            Err(e) if attempt < SUBMIT_CONNECT_RETRIES && is_pre_response_failure(e.as_ref()) => {
+                warn!("Submit got no response (attempt {}): {}. Retrying...", attempt, e);
+                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+            }
+            result => return result,
+        }
+    }
+    Err("Failed to submit portfolio".into())
+}
+
+
+/// Connection settings for the evaluator. Each flag can also be set from the
+/// environment; the defaults target the live challenge server.
+#[derive(Parser, Debug)]
+#[command(about = "Build and submit portfolios for evaluator contexts")]
+struct Cli {
+    /// Evaluator base URL (scheme and host)
+    #[arg(long, env = "PRISM_URL", default_value = URL)]
+    url: String,
+    /// Evaluator port
+    #[arg(long, env = "PRISM_PORT", default_value_t = PORT)]
+    port: u16,
+    /// Team API code sent as X-API-Code
+    #[arg(long = "api-code", env = "PRISM_API_CODE", hide_env_values = true)]
+    api_code: Option<String>,
+    /// Print one JSON line per submission to stdout (logs stay on stderr)
+    #[arg(long = "json-output")]
+    json_output: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replay contexts from a request trace offline and compare the rebuilt
+    /// portfolios with the logged ones (nothing is submitted)
+    Backtest {
+        /// JSONL trace to replay
+        #[arg(long, default_value = "request_trace.jsonl")]
+        trace: String,
+    },
+    /// Rebuild the portfolios for the last N traced contexts offline and
+    /// print them side by side with the logged ones
+    DebugReplay {
+        /// Number of trailing trace entries to replay
+        #[arg(long, default_value_t = 1)]
+        last: usize,
+        /// JSONL trace to read
+        #[arg(long, default_value = "request_trace.jsonl")]
+        trace: String,
+    },
+    /// Merge another points store (e.g. from a second machine) into the
+    /// local one and save it
+    Merge {
+        /// Points store to merge in
+        other: String,
+        /// Points store to update
+        #[arg(long, default_value = "points_store.json")]
+        into: String,
+        /// How to combine scores present in both stores
+        #[arg(long, value_enum, default_value_t = MergeMode::Sum)]
+        mode: MergeMode,
+    },
+    /// Fetch only the months missing from the monthly price cache and
+    /// rewrite it
+    UpdateCache {
+        #[arg(long, default_value = "stocks_cache_monthly.json")]
+        cache: String,
+        /// Tickers requested per batch
+        #[arg(long, default_value_t = 50)]
+        batch_size: usize,
+        /// Pause between batches in milliseconds
+        #[arg(long, default_value_t = 200)]
+        batch_delay_ms: u64,
+        /// Requests in flight at once
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+        /// Per-request timeout in seconds
+        #[arg(long, default_value_t = 10)]
+        timeout_secs: u64,
+    },
+}
+
+/// `merge` subcommand: fold the store at `other` into the one at `into`
+fn merge_points_stores(other: &str, into: &str, mode: MergeMode) -> Result<(), Box<dyn Error>> {
+    if !std::path::Path::new(other).exists() {
+        return Err(format!("Points store '{}' not found", other).into());
+    }
+    let mut store = PointsStore::load(into);
+    let incoming = PointsStore::load(other);
+    info!("[POINTS] Merging {} tickers from {} into {} ({} tickers, {:?})",
+             incoming.scores.len(), other, into, store.scores.len(), mode);
+    store.merge(&incoming, mode);
+    store.save();
+    info!("[POINTS] {} now has {} tickers and {} sectors", into, store.scores.len(), store.sector_scores.len());
+    Ok(())
+}
+
+/// Log to stderr at the level given by `RUST_LOG` (default `info`)
+fn init_logging() {
+    use tracing_subscriber::util::SubscriberInitExt;
+    log_subscriber().init();
+}
+
+fn log_subscriber() -> impl tracing::Subscriber + Send + Sync {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .finish()
+}
+
+/// Team API code from, in order: `--api-code`/`PRISM_API_CODE`, the
+/// `TEAM_API_CODE` env var, or the first line of `file`. Blank values are
+/// ignored; an error if none is set.
+fn resolve_api_code(flag: Option<&str>, env: Option<String>, file: &str) -> Result<String, Box<dyn Error>> {
+    let from_file = std::fs::read_to_string(file).ok()
+        .and_then(|s| s.lines().next().map(str::to_string));
+    flag.map(str::to_string)
+        .into_iter()
+        .chain(env)
+        .chain(from_file)
+        .map(|code| code.trim().to_string())
+        .find(|code| !code.is_empty())
+        .ok_or_else(|| format!("No API code: pass --api-code, set PRISM_API_CODE or {}, or write it to {}", API_CODE_ENV, file).into())
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    init_logging();
+    let cli = Cli::parse();
+    if let Some(Command::Merge { other, into, mode }) = &cli.command {
+        return merge_points_stores(other, into, *mode);
+    }
+    if let Some(Command::UpdateCache { cache, batch_size, batch_delay_ms, concurrency, timeout_secs }) = &cli.command {
+        let options = MonthlyUpdateOptions {
+            batch_size: *batch_size,
+            batch_delay: Duration::from_millis(*batch_delay_ms),
+            concurrency: *concurrency,
+            request_timeout: Duration::from_secs(*timeout_secs),
+            chart_url: YAHOO_CHART_URL.to_string(),
+        };
+        let appended = update_monthly_cache(cache, options).await?;
+        info!("[CACHE] {} is up to date ({} months added)", cache, appended);
+        return Ok(());
+    }
+    // Backtests never contact the evaluator, so only live runs need a code
+    let api_code = match cli.command {
+        Some(Command::Backtest { .. } | Command::DebugReplay { .. } | Command::Merge { .. } | Command::UpdateCache { .. }) => None,
+        None => Some(resolve_api_code(cli.api_code.as_deref(), std::env::var(API_CODE_ENV).ok(), API_CODE_FILE)?),
+    };
+
     // Load initial stock data from cache (metadata + structure)
-    println!("[LOAD] Loading initial stock data...");
+    info!("[LOAD] Loading initial stock data...");
     let stock_metadata = prefetch_all_stocks().await?;
     
-    println!("[INFO] Loaded {} stocks from cache\n", stock_metadata.len());
+    info!("Loaded {} stocks from cache", stock_metadata.len());
+    if let Some(age) = cache_age() {
+        info!("Cache age: {} days {} hours", age.num_days(), age.num_hours() % 24);
+    }
+    let companies = CompanyLookup::from_stocks(&stock_metadata);
 
-    loop {
-        // Get and parse context
-        let context = get_context().await?;
-        println!("Context provided: {}", context);
-        
-        if let Ok(profile) = InvestorProfile::from_context(&context) {
-
-            println!("\n[PROFILE] Investor Profile:");
-            println!("  Name: {}", profile.name);
-            println!("  Age: {} ({:?})", profile.age, profile.risk_tolerance);
-            println!("  Budget: ${:.2}", profile.budget);
-            println!("  Excluded: {:?}", profile.excluded_sectors);
-            println!("  Investment Period: {:?} to {:?}", profile.start_year, profile.end_year);
-        
-            // Clone stock metadata for this request
-            let mut all_stocks = stock_metadata.clone();
-            
-            // PHASE 1: Fetch historical returns for ranking/selection (uses interpolation)
-            if let (Some(start_year), Some(end_year)) = (profile.start_year, profile.end_year) {
-                // Construct date strings from the profile
-                let start = format!("{}-01-01", start_year);
-                let end = format!("{}-12-31", end_year);
-                
-                println!("[PHASE1] Fetching historical data for ranking ({} to {})...", start, end);
-                if let Err(e) = fetch_historical_returns(&mut all_stocks, &start, &end).await {
-                    eprintln!("[WARN] Could not fetch historical returns: {}", e);
+    let portfolio_config = PortfolioConfig {
+        rank_quantities: load_rank_quantities("rank_quantities.json"),
+        ..PortfolioConfig::load("portfolio_config.json")
+    }.with_submit_margin_override(std::env::var("SUBMIT_MARGIN").ok());
+    // Load learned points once and share the handle across requests
+    let points = PointsStore::load_shared("points_store.json");
+    {
+        let store = points.read().unwrap_or_else(|e| e.into_inner());
+        info!("[POINTS] Top learned tickers: {:?}", store.top_tickers_overall(LOG_TOP_POINTS));
+        for bucket in VOL_BUCKETS {
+            info!("[POINTS] Top learned {}-volatility tickers: {:?}", bucket, store.top_tickers(bucket, LOG_TOP_POINTS));
+        }
+        info!("[POINTS] Top learned sectors: {:?}", store.top_sectors(LOG_TOP_POINTS));
+    }
+
+    if let Some(Command::Backtest { trace }) = &cli.command {
+        // Replays must not feed back into the persisted points
+        let detached = std::sync::Arc::new(std::sync::RwLock::new(
+            points.read().unwrap_or_else(|e| e.into_inner()).detached(),
+        ));
+        let summary = backtest::run_backtest(trace, &stock_metadata, &companies, &portfolio_config, &detached)?;
+        summary.log();
+        return Ok(());
+    }
+    if let Some(Command::DebugReplay { last, trace }) = &cli.command {
+        let detached = std::sync::Arc::new(std::sync::RwLock::new(
+            points.read().unwrap_or_else(|e| e.into_inner()).detached(),
+        ));
+        let comparisons = backtest::replay_last(trace, *last, &stock_metadata, &companies, &portfolio_config, &detached)?;
+        for comparison in &comparisons {
+            println!("{}", comparison.render());
+        }
+        let changed = comparisons.iter().filter(|c| c.changed()).count();
+        info!("[REPLAY] {} of {} replayed contexts changed", changed, comparisons.len());
+        return Ok(());
+    }
+
+    migrate_legacy_rejected_tickers(LEGACY_REJECTED_TICKERS_PATH, REJECTED_TICKERS_PATH);
+    let api = ApiClient::new(&cli.url, cli.port, &api_code.unwrap_or_default());
+    info!("[CONFIG] Using evaluator at {}", api.base_url());
+
+    let mut contexts = spawn_context_producer(api.clone(), portfolio_config.context_batch_size);
+    let mut shutdown = spawn_shutdown_listener();
+    let mut recent = RecentContexts::new(
+        Duration::from_secs(portfolio_config.duplicate_context_window_secs),
+        portfolio_config.duplicate_context_capacity,
+    );
+    let shared_config = Arc::new(portfolio_config.clone());
+
+    // Parse each context as it arrives and plan it on its own task
+    let plan = |context: String, fetched_at: Instant| -> tokio::task::JoinHandle<PlannedRequest> {
+        info!("Context provided: {}", context);
+
+        let mut profile = match InvestorProfile::from_context(&context, &companies) {
+            Ok(profile) => profile,
+            Err(e) => {
+                warn!("error in profile skipping: {}", e);
+                let reason = match e {
+                    ParseProfileError::ImplausibleBudget { .. } => "implausible_budget",
+                    _ => "profile_parse_error",
+                };
+                return tokio::spawn(async move { PlannedRequest::Unparsed { context, reason } });
+            }
+        };
+
+        complete_investment_period(&mut profile, &portfolio_config);
+
+        info!("[PROFILE] Investor Profile:");
+        info!("  Name: {}", profile.name);
+        info!("  Age: {} ({:?})", profile.age, profile.risk_tolerance);
+        info!("  Budget: ${:.2}", profile.budget);
+        info!("  Excluded: {:?}", profile.excluded_sectors);
+        if !profile.excluded_tickers.is_empty() {
+            info!("  Excluded companies: {:?}", profile.excluded_tickers);
+        }
+        info!("  Investment Period: {:?} to {:?}", profile.start_year, profile.end_year);
+        if let Some(n) = profile.target_positions {
+            info!("  Requested holdings: {}", n);
+        }
+        if !profile.sector_caps.is_empty() {
+            info!("  Sector caps: {:?}", profile.sector_caps);
+        }
+        if let Some(target) = profile.income_target {
+            info!("  Income target: {:?}", target);
+        }
+
+        if recent.check_and_insert(profile_fingerprint(&profile), fetched_at) {
+            warn!("[SKIP] Duplicate of a context processed in the last {}s - not resubmitting", portfolio_config.duplicate_context_window_secs);
+            let outcome = RequestOutcome::Skipped { reason: "duplicate_context".to_string(), eligible_count: 0 };
+            return tokio::spawn(async move { PlannedRequest::Parsed { context, profile, outcome } });
+        }
+
+        // Clone stock metadata for this request
+        let all_stocks = stock_metadata.clone();
+        let (config, points) = (shared_config.clone(), points.clone());
+        tokio::spawn(async move {
+            let deadline = fetched_at + Duration::from_secs(config.request_deadline_secs);
+            let outcome = plan_request(&profile, all_stocks, config, points, deadline).await;
+            PlannedRequest::Parsed { context, profile, outcome }
+        })
+    };
+
+    // Submit or skip each planned request in the order its context arrived
+    let finish = async |planned: PlannedRequest| -> Result<(), Box<dyn Error>> {
+        let (context, profile, outcome) = match planned {
+            PlannedRequest::Unparsed { context, reason } => {
+                append_trace(&TraceEntry::skipped(&context, None, 0, reason));
+                return Ok(());
+            }
+            PlannedRequest::Parsed { context, profile, outcome } => (context, profile, outcome),
+        };
+        match outcome {
+            RequestOutcome::Ready { portfolio, cost, eligible, notes } => {
+                info!("Planned portfolio cost ${:.2}", cost);
+                // Pass the raw context so the logger can record it
+                match portfolio {
+                    PlannedPortfolio::Whole(p) => {
+                        print_portfolio_and_submit(&api, &p, &eligible, &notes, &profile, &portfolio_config, &points, &context, cli.json_output).await
+                    }
+                    PlannedPortfolio::Fractional(p) => {
+                        print_portfolio_and_submit(&api, &p, &eligible, &notes, &profile, &portfolio_config, &points, &context, cli.json_output).await
+                    }
                 }
             }
-            
-            // Filter by investor profile
-            let eligible_stocks = filter_stocks_by_profile(&all_stocks, &profile);
-            println!("[FILTER] Eligible stocks after filtering: {} (from {} total)", eligible_stocks.len(), all_stocks.len());
-            
-            if eligible_stocks.is_empty() {
-                return Err("No eligible stocks found!".into());
+            RequestOutcome::Skipped { reason, eligible_count } => {
+                info!("[SKIP] Skipping request: {}", reason);
+                append_trace(&TraceEntry::skipped(&context, Some(&profile), eligible_count, &reason));
+                Ok(())
             }
-        
-            // Build portfolio based on interpolated/cached data
-            let portfolio = build_portfolio(
-                &eligible_stocks,
-                profile.budget,
-                profile.risk_tolerance
-            );
-            
-            // Debug: Show selected stocks and their IPO info
-            println!("\n[DEBUG] Selected stocks for portfolio:");
-            for (ticker, _) in &portfolio {
-                if let Some(stock) = eligible_stocks.iter().find(|s| &s.ticker == ticker) {
-                    println!("  {} - IPO: {} (return: {:.1}%)", 
-                            ticker, 
-                            stock.first_trading_date.as_ref().unwrap_or(&"unknown".to_string()),
-                            stock.historical_return.unwrap_or(0.0));
+        }
+    };
+
+    let handled = run_pipeline(&mut contexts, &mut shutdown, portfolio_config.planning_workers, plan, finish).await?;
+
+    // Persist learned points before exiting so an interrupted session keeps them
+    points.write().unwrap_or_else(|e| e.into_inner()).save();
+    info!("[SHUTDOWN] Stopped after handling {} requests", handled);
+    Ok(())
+}
+
+/// A context after planning, waiting for its turn to be submitted
+#[allow(clippy::large_enum_variant)]
+enum PlannedRequest {
+    /// The context did not parse into a profile; skipped for `reason`
+    Unparsed { context: String, reason: &'static str },
+    Parsed { context: String, profile: InvestorProfile, outcome: RequestOutcome },
+}
+
+/// Hand each received context to `plan`, keeping up to `workers` plans in
+/// flight, and pass the finished plans to `finish` strictly in the order the
+/// contexts arrived. Submission stays on this single consumer, so each
+/// context is submitted at most once and traces stay ordered; only planning
+/// runs concurrently. Stops taking contexts on shutdown or a producer error,
+/// finishes the plans already in flight, and returns the number of contexts
+/// handled (or the producer error).
+async fn run_pipeline<T: Send + 'static>(
+    contexts: &mut tokio::sync::mpsc::Receiver<Result<(String, Instant), String>>,
+    shutdown: &mut tokio::sync::watch::Receiver<bool>,
+    workers: usize,
+    mut plan: impl FnMut(String, Instant) -> tokio::task::JoinHandle<T>,
+    mut finish: impl AsyncFnMut(T) -> Result<(), Box<dyn Error>>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut in_flight: std::collections::VecDeque<tokio::task::JoinHandle<T>> = std::collections::VecDeque::new();
+    let mut stopped: Option<Result<(), Box<dyn Error>>> = None;
+    let mut handled = 0;
+    loop {
+        let accepting = stopped.is_none() && in_flight.len() < workers.max(1);
+        tokio::select! {
+            biased;
+            Ok(()) = shutdown.changed(), if accepting => stopped = Some(Ok(())),
+            planned = async { in_flight.front_mut().expect("guarded by is_empty").await }, if !in_flight.is_empty() => {
+                in_flight.pop_front();
+                finish(planned?).await?;
+            }
+            received = contexts.recv(), if accepting => match received {
+                Some(Ok((context, fetched_at))) => {
+                    handled += 1;
+                    in_flight.push_back(plan(context, fetched_at));
                 }
+                Some(Err(e)) => stopped = Some(Err(e.into())),
+                None => stopped = Some(Err("Context producer stopped".into())),
+            },
+            else => break,
+        }
+    }
+    stopped.unwrap_or(Ok(())).map(|()| handled)
+}
+
+/// Fetch historical returns for `profile` and plan it, giving up at `deadline`
+async fn plan_request(
+    profile: &InvestorProfile,
+    mut all_stocks: Vec<Stock>,
+    config: Arc<PortfolioConfig>,
+    points: SharedPointsStore,
+    deadline: Instant,
+) -> RequestOutcome {
+    let profile = profile.clone();
+    let planning = async move {
+        // PHASE 1: Fetch historical returns for ranking/selection (uses interpolation)
+        if let Some((start, end)) = period_dates(&profile) {
+            info!("[PHASE1] Fetching historical data for ranking ({} to {})...", start, end);
+            if let Err(e) = fetch_historical_returns(&mut all_stocks, &start, &end, config.gap_bounds()).await {
+                warn!("Could not fetch historical returns: {}", e);
             }
-            println!();
-            
-            // PHASE 2: DISABLED - Just use interpolated prices
-            // Phase 2 (exact pricing via API) was causing issues with:
-            // - Ticker changes (BKNG was PCLN)
-            // - API rate limiting
-            // - Inconsistent data availability
-            // Interpolated prices from Phase 1 are accurate enough (within 2-3%)
-            println!("[INFO] Using interpolated prices from cached data (Phase 2 disabled)");
-            
-            // Submit portfolio with interpolated prices
-                // Validate/clean portfolio before the single allowed submit
-                let cleaned = pre_submit_validate(&portfolio, &eligible_stocks, profile.budget);
-                // Pass the raw context and original budget so the logger can record both
-                print_portfolio_and_submit(&cleaned, &eligible_stocks, &profile, &context, profile.budget).await?;
-        } else {
-            println!("error in profile skipping")
         }
+        // Planning is synchronous; on the blocking pool it can't hold up the
+        // deadline timer
+        tokio::task::spawn_blocking(move || handle_request(&profile, &all_stocks, &config, &points))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    };
+    plan_by_deadline(planning, deadline).await
+}
+
+/// Await `planning`, skipping the request as `deadline_exceeded` if it is
+/// not ready by `deadline` rather than submitting stale work. A plan that
+/// only finishes after the deadline is skipped as well.
+async fn plan_by_deadline(planning: impl std::future::Future<Output = RequestOutcome>, deadline: Instant) -> RequestOutcome {
+    match tokio::time::timeout_at(deadline, planning).await {
+        Ok(outcome) if Instant::now() < deadline => outcome,
+        _ => RequestOutcome::Skipped { reason: "deadline_exceeded".to_string(), eligible_count: 0 },
     }
-    
-    // Unreachable: loop runs forever until externally terminated
-    #[allow(unreachable_code)]
-    Ok(())
 }
 
-async fn print_portfolio_and_submit(
-    portfolio: &[(String, i32)],
+/// Watch channel that flips to true on the first Ctrl-C, letting the main
+/// loop finish its current request and shut down. A second Ctrl-C exits
+/// immediately.
+fn spawn_shutdown_listener() -> tokio::sync::watch::Receiver<bool> {
+    let (tx, rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("[SHUTDOWN] Ctrl-C received - finishing the current request (press again to force quit)");
+        let _ = tx.send(true);
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    rx
+}
+
+/// Portfolio ready for submission, in whole or fractional shares
+enum PlannedPortfolio {
+    Whole(Vec<(String, i32)>),
+    Fractional(Vec<(String, f64)>),
+}
+
+/// Diagnostics gathered while planning a request, recorded in its trace
+struct PlanNotes {
+    plan: PortfolioPlan,
+    validation: ValidationReport,
+    /// No eligible stock had a historical return, so ranking fell back
+    no_historical_data: bool,
+}
+
+/// Outcome of planning a single request. No submission I/O has happened yet.
+enum RequestOutcome {
+    Skipped { reason: String, eligible_count: usize },
+    Ready { portfolio: PlannedPortfolio, cost: f64, eligible: Vec<Stock>, notes: PlanNotes },
+}
+
+/// Filter, allocate and validate a portfolio for one parsed profile.
+/// `all_stocks` should already carry historical returns for the profile's period.
+fn handle_request(
+    profile: &InvestorProfile,
+    all_stocks: &[Stock],
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+) -> RequestOutcome {
+    // Filter by investor profile
+    let mut eligible_stocks = filter_stocks_by_profile(all_stocks, profile, config);
+    info!("[FILTER] Eligible stocks after filtering: {} (from {} total)", eligible_stocks.len(), all_stocks.len());
+
+    // The price-gap check is a data-quality heuristic rather than a client
+    // constraint, so relax it before giving up on the request
+    if eligible_stocks.is_empty() && all_stocks.iter().any(|s| s.price_gap_flagged) {
+        warn!("[FILTER] No eligible stocks - retrying without the price-gap filter");
+        let relaxed: Vec<Stock> = all_stocks.iter()
+            .cloned()
+            .map(|mut s| { s.price_gap_flagged = false; s })
+            .collect();
+        eligible_stocks = filter_stocks_by_profile(&relaxed, profile, config);
+    }
+
+    if eligible_stocks.is_empty() {
+        return RequestOutcome::Skipped { reason: "no_eligible_stocks".to_string(), eligible_count: 0 };
+    }
+
+    // Sector caps from the brief tighten the configured ones for this request
+    let capped_config;
+    let config = if profile.sector_caps.is_empty() {
+        config
+    } else {
+        capped_config = config.with_sector_caps(&profile.sector_caps);
+        &capped_config
+    };
+    // An income requirement switches this request to yield-first allocation
+    let income_config;
+    let config = match profile.income_target {
+        Some(target) => {
+            income_config = config.with_income_target(target.annual_income(config.alloc_budget(profile.budget, profile.risk_tolerance)));
+            &income_config
+        }
+        None => config,
+    };
+
+    // Without any history (no cache and the API unreachable) ranking can
+    // only use the fallback order, which should never go unnoticed
+    let no_historical_data = eligible_stocks.iter().all(|s| s.historical_return.is_none());
+    if no_historical_data {
+        error!("[DATA] No historical returns for any of {} eligible stocks - ranking by {}",
+                  eligible_stocks.len(), if config.market_cap_fallback { "market cap" } else { "volatility" });
+    }
+    let features = universe_features(&eligible_stocks, &points.read().unwrap_or_else(|e| e.into_inner()));
+    info!("[FILTER] Universe: {} stocks, avg volatility {:.4}, avg log cap {}, avg points {:.2}",
+          features.count, features.avg_vol,
+          features.avg_logcap.map_or("unknown".to_string(), |c| format!("{:.2}", c)),
+          features.avg_pts_score);
+
+    // Build portfolio based on interpolated/cached data
+    let period_years = match (profile.start_year, profile.end_year) {
+        (Some(start), Some(end)) if end >= start => Some((end - start + 1) as f64),
+        _ => None,
+    };
+    let plan = build_portfolio(
+        &eligible_stocks,
+        profile.budget,
+        profile.risk_tolerance,
+        config,
+        points,
+        period_years,
+        profile.target_positions,
+    );
+    info!("[PLAN] {} positions via {}", plan.positions().len(), plan.strategy);
+
+    // Debug: Show selected stocks and their IPO info
+    debug!("Selected stocks for portfolio:");
+    for (ticker, _) in plan.positions() {
+        if let Some(stock) = eligible_stocks.iter().find(|s| &s.ticker == ticker) {
+            debug!("  {} - IPO: {} (return: {:.1}%)",
+                    ticker,
+                    stock.first_trading_date.as_ref().unwrap_or(&"unknown".to_string()),
+                    stock.historical_return.unwrap_or(0.0));
+        }
+    }
+
+    // PHASE 2: DISABLED - Just use interpolated prices
+    // Phase 2 (exact pricing via API) was causing issues with:
+    // - Ticker changes (BKNG was PCLN)
+    // - API rate limiting
+    // - Inconsistent data availability
+    // Interpolated prices from Phase 1 are accurate enough (within 2-3%)
+    info!("Using interpolated prices from cached data (Phase 2 disabled)");
+
+    // Validate/clean portfolio before the single allowed submit
+    let rejected = load_rejected_tickers(REJECTED_TICKERS_PATH, config.rejected_ticker_ttl_days);
+    let (cleaned, validation) = pre_submit_validate(plan.positions(), &eligible_stocks, ticker_aliases(), &rejected, profile.budget, config);
+    if cleaned.is_empty() {
+        return RequestOutcome::Skipped { reason: "empty_portfolio".to_string(), eligible_count: eligible_stocks.len() };
+    }
+    // Later lookups go by submitted symbol, so follow the validator's renames
+    for renamed in &validation.renamed {
+        if let Some(stock) = eligible_stocks.iter_mut().find(|s| s.ticker == renamed.from) {
+            stock.ticker = renamed.to.clone();
+        }
+    }
+
+    let price_of = |ticker: &str| {
+        eligible_stocks.iter().find(|s| s.ticker == ticker).map(|s| s.get_current_price()).unwrap_or(0.0)
+    };
+    let (portfolio, cost) = if config.fractional_shares {
+        let alloc_budget = config.alloc_budget(profile.budget, profile.risk_tolerance);
+        let fractional = fractionalize(&cleaned, &eligible_stocks, alloc_budget, config);
+        let cost = fractional.iter().map(|(t, q)| price_of(t) * q).sum();
+        (PlannedPortfolio::Fractional(fractional), cost)
+    } else {
+        let cost = cleaned.iter().map(|(t, q)| price_of(t) * *q as f64).sum();
+        (PlannedPortfolio::Whole(cleaned), cost)
+    };
+
+    RequestOutcome::Ready { portfolio, cost, eligible: eligible_stocks, notes: PlanNotes { plan, validation, no_historical_data } }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn print_portfolio_and_submit<Q: SubmitQuantity + Display>(
+    api: &ApiClient,
+    portfolio: &[(String, Q)],
     eligible_stocks: &[Stock],
+    notes: &PlanNotes,
     profile: &InvestorProfile,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
     raw_context: &str,
-    original_budget: f64,
+    json_output: bool,
 ) -> Result<(), Box<dyn Error>> {
+    let original_budget = profile.budget;
     let mut total_cost = 0.0;
     for (ticker, qty) in portfolio {
         let stock = eligible_stocks.iter().find(|s| s.ticker == *ticker).unwrap();
         // Use current market price for displayed/submitted cost so it matches evaluator
         let current_price = stock.get_current_price();
-        let cost = current_price * (*qty as f64);
+        let cost = current_price * (*qty).into();
         total_cost += cost;
 
         // Show current price and historical start price (if available)
         if let Some(hist_price) = stock.historical_start_price {
-            println!(
+            info!(
                 "  {} x{} @ ${:.2} current (${:.2} historical → {:.1}% return) = ${:.2}",
                 ticker, qty, current_price, hist_price,
                 stock.historical_return.unwrap_or(0.0), cost
             );
         } else {
-            println!(
+            info!(
                 "  {} x{} @ ${:.2} = ${:.2}",
                 ticker, qty, current_price, cost
             );
         }
     }
-    println!("  Total: ${:.2} / ${:.2}", total_cost, profile.budget);
+    info!("  Total: ${:.2} / ${:.2}", total_cost, profile.budget);
+    let alloc_budget = config.alloc_budget(original_budget, profile.risk_tolerance);
+    let (intended_buffer, unspent_cash) = cash_buffer(original_budget, alloc_budget, total_cost);
+    info!(
+        "  Cash: ${:.2} unspent (${:.2} intended buffer, ${:.2} undeployed)",
+        unspent_cash,
+        intended_buffer,
+        unspent_cash - intended_buffer
+    );
+    let stats = portfolio_stats(portfolio, eligible_stocks);
+    match stats.expected_return {
+        Some(ret) => info!("  Expected return: {:.1}%, volatility: {:.4}", ret, stats.volatility),
+        None => info!("  Expected return: unknown, volatility: {:.4}", stats.volatility),
+    }
 
     // Convert to required format
-    let portfolio_refs: Vec<(&str, i32)> = portfolio
+    let portfolio_refs: Vec<(&str, Q)> = portfolio
         .iter()
         .map(|(t, q)| (t.as_str(), *q))
         .collect();
 
+    let meta = submission_meta(profile, config, alloc_budget);
+
+    // One id for this request, shared by the snapshot, the submit request,
+    // any rejected tickers and the trace entry
+    let submission_id = idempotency_key(raw_context, &submission_body(&portfolio_refs, meta.as_ref()));
+
+    // Snapshot what was submitted so points can later be attributed to picks
+    if let Err(e) = write_snapshot(SNAPSHOTS_PATH, &submission_id, portfolio, eligible_stocks) {
+        warn!("Failed to write portfolio snapshot: {}", e);
+    }
+
+    // What the points store expected from these picks, before the evaluator
+    // response is learned from
+    let predicted_points: f64 = {
+        let store = points.read().unwrap_or_else(|e| e.into_inner());
+        portfolio.iter().map(|(t, _)| store.get_score(t)).sum()
+    };
+
     // Submit portfolio and capture the response (or error) for logging
-    let send_result = match send_portfolio(portfolio_refs).await {
+    let send_result = match send_portfolio(api, portfolio_refs, meta.as_ref(), &submission_id).await {
         Ok(response) => {
-            println!("\n[SUCCESS] Evaluation: {}", response);
+            info!("[SUCCESS] Evaluation: {}", response);
             Ok(response)
         }
         Err(e) => {
-            println!("[ERROR] {}", e);
-            // Try to extract problematic tickers from the error message and persist them
-            if let Some(problematic) = parse_problematic_tickers(&e.to_string()) {
+            error!("{}", e);
+            // Try to extract problematic tickers from the error message and persist them,
+            // but only when the evaluator actually rejected tickers (not on 5xx/timeouts)
+            let submitted: Vec<&str> = portfolio.iter().map(|(t, _)| t.as_str()).collect();
+            if !is_ticker_validation_error(&e.to_string()) {
+                warn!("[VALIDATOR] Submit failure is not a ticker rejection - not blacklisting");
+            } else if let Some(problematic) = parse_problematic_tickers(&e.to_string(), &submitted) {
                 if !problematic.is_empty() {
-                    if let Err(err) = append_rejected_tickers(&problematic) {
-                        eprintln!("[VALIDATOR] Failed to append rejected tickers: {}", err);
+                    if let Err(err) = append_rejected_tickers(&problematic, &e.to_string(), &submission_id, config.rejected_ticker_ttl_days) {
+                        warn!("[VALIDATOR] Failed to append rejected tickers: {}", err);
                     } else {
-                        eprintln!("[VALIDATOR] Appended rejected tickers: {:?}", problematic);
+                        warn!("[VALIDATOR] Appended rejected tickers: {:?}", problematic);
                     }
                 }
             }
@@ -238,90 +917,356 @@ async fn print_portfolio_and_submit(
         }
     };
 
-    // Append a compact JSONL trace for debugging/correlation analysis
-    // Fields: timestamp, raw_context, parsed_profile, eligible_count, alloc_budget, portfolio, total_cost, response/error
-    if let Ok(mut f) = OpenOptions::new().create(true).append(true).open("request_trace.jsonl") {
-        use chrono::Utc;
-        let ts = Utc::now().to_rfc3339();
-
-        // Build profile object
-        let profile_obj = json!({
-            "name": profile.name,
-            "age": profile.age,
-            "budget": profile.budget,
-            "excluded_sectors": profile.excluded_sectors,
-            "risk_tolerance": format!("{:?}", profile.risk_tolerance),
-            "start_year": profile.start_year,
-            "end_year": profile.end_year,
-        });
-
-        let alloc_budget = original_budget * BUDGET_SPEND_FRACTION;
-
-        let portfolio_json: Vec<Value> = portfolio.iter().map(|(t, q)| json!({ "ticker": t, "quantity": q })).collect();
-
-        let entry = json!({
-            "ts": ts,
-            "raw_context": raw_context,
-            "parsed_profile": profile_obj,
-            "eligible_count": eligible_stocks.len(),
-            "alloc_budget": alloc_budget,
-            "portfolio": portfolio_json,
-            "allocated_cost": total_cost,
-            "result": match &send_result {
-                Ok(resp) => json!({"ok": true, "response": resp}),
-                Err(err) => json!({"ok": false, "error": err.to_string()}),
+    let result = match &send_result {
+        Ok(resp) => {
+            let evaluation = serde_json::from_str::<Value>(resp).ok();
+            if let Some(eval) = &evaluation {
+                learn_from_evaluation(points, eval, portfolio, eligible_stocks, config.vol_thresholds);
             }
-        });
-
-        if let Ok(line) = serde_json::to_string(&entry) {
-            let _ = f.write_all(line.as_bytes());
-            let _ = f.write_all(b"\n");
+            TraceOutcome::Submitted { response: resp.clone(), evaluation }
         }
+        Err(err) => TraceOutcome::SubmitFailed { error: err.to_string() },
+    };
+    let portfolio_json: Vec<Value> = portfolio.iter().map(|(t, q)| json!({ "ticker": t, "quantity": q })).collect();
+
+    if json_output {
+        // Single line per submission for downstream tools reading stdout
+        println!("{}", submission_summary(profile, &portfolio_json, total_cost, predicted_points, &result));
     }
+
+    append_trace(&TraceEntry {
+        ts: chrono::Utc::now().to_rfc3339(),
+        submission_id,
+        raw_context,
+        parsed_profile: Some(profile),
+        eligible_count: eligible_stocks.len(),
+        plan: Some(&notes.plan),
+        validation: Some(&notes.validation),
+        no_historical_data: notes.no_historical_data,
+        alloc_budget,
+        portfolio: portfolio_json,
+        allocated_cost: total_cost,
+        intended_buffer,
+        unspent_cash,
+        stats: Some(stats),
+        utilization: if original_budget > 0.0 { total_cost / original_budget } else { 0.0 },
+        result,
+    });
     
     Ok(())
 }
 
-// Load rejected tickers from disk (one per line). Missing file results in empty set.
-fn load_rejected_tickers(path: &str) -> HashSet<String> {
+/// Cash left unspent on purpose (`budget - alloc_budget`) and cash actually
+/// left over (`budget - total_cost`); the gap is what the allocator couldn't
+/// deploy
+fn cash_buffer(budget: f64, alloc_budget: f64, total_cost: f64) -> (f64, f64) {
+    (budget - alloc_budget, budget - total_cost)
+}
+
+/// The `--json-output` line for one submission
+fn submission_summary(
+    profile: &InvestorProfile,
+    portfolio: &[Value],
+    total_cost: f64,
+    predicted_points: f64,
+    result: &TraceOutcome,
+) -> String {
+    json!({
+        "profile": profile,
+        "portfolio": portfolio,
+        "total_cost": total_cost,
+        "budget": profile.budget,
+        "predicted_points": predicted_points,
+        "result": result,
+    })
+    .to_string()
+}
+
+/// Feed the evaluator's points back into the points store, per ticker when
+/// the response includes a scoring breakdown (see `evaluation_deltas`), and
+/// into each ticker's volatility bucket
+fn learn_from_evaluation<Q>(
+    points: &SharedPointsStore,
+    evaluation: &Value,
+    portfolio: &[(String, Q)],
+    eligible_stocks: &[Stock],
+    thresholds: VolThresholds,
+) {
+    if let Some(breakdown) = evaluation.get("breakdown") {
+        info!("[EVAL] Scoring breakdown: {}", breakdown);
+    }
+    let tickers: Vec<&str> = portfolio.iter().map(|(t, _)| t.as_str()).collect();
+    let Some(deltas) = evaluation_deltas(evaluation, &tickers) else { return };
+    let mut store = points.write().unwrap_or_else(|e| e.into_inner());
+    for (ticker, delta) in &deltas {
+        store.add_score(ticker, *delta);
+        if let Some(stock) = eligible_stocks.iter().find(|s| &s.ticker == ticker) {
+            store.add_bucket_score(thresholds.bucket(stock.volatility), ticker, *delta);
+        }
+    }
+    store.save();
+}
+
+/// One line of `request_trace.jsonl`, shared by the submit and skip paths
+#[derive(Serialize)]
+struct TraceEntry<'a> {
+    ts: String,
+    /// Links the entry to its line in `portfolio_snapshots.jsonl` and in
+    /// `rejected_tickers.jsonl`
+    submission_id: String,
+    raw_context: &'a str,
+    parsed_profile: Option<&'a InvestorProfile>,
+    eligible_count: usize,
+    /// Allocation path and per-position rationale (submitted requests only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<&'a PortfolioPlan>,
+    /// What the pre-submit validator changed (submitted requests only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<&'a ValidationReport>,
+    /// Ranking had no historical returns to work with
+    no_historical_data: bool,
+    alloc_budget: f64,
+    portfolio: Vec<Value>,
+    allocated_cost: f64,
+    /// Cash deliberately left unspent: budget minus `alloc_budget`
+    intended_buffer: f64,
+    /// Cash actually left unspent: budget minus `allocated_cost`
+    unspent_cash: f64,
+    /// Aggregate return/volatility of the submitted portfolio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<PortfolioStats>,
+    /// Allocated cost as a fraction of the client's budget
+    utilization: f64,
+    result: TraceOutcome,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+enum TraceOutcome {
+    Submitted {
+        response: String,
+        /// `response` parsed as JSON, when it is JSON
+        #[serde(skip_serializing_if = "Option::is_none")]
+        evaluation: Option<Value>,
+    },
+    SubmitFailed { error: String },
+    Skipped { reason: String },
+}
+
+impl<'a> TraceEntry<'a> {
+    /// Trace entry for a request that was not submitted
+    fn skipped(raw_context: &'a str, profile: Option<&'a InvestorProfile>, eligible_count: usize, reason: &str) -> Self {
+        TraceEntry {
+            ts: chrono::Utc::now().to_rfc3339(),
+            // Same derivation as a submission, over an empty portfolio
+            submission_id: idempotency_key(raw_context, &json!([])),
+            raw_context,
+            parsed_profile: profile,
+            eligible_count,
+            plan: None,
+            validation: None,
+            no_historical_data: false,
+            alloc_budget: 0.0,
+            portfolio: Vec::new(),
+            allocated_cost: 0.0,
+            intended_buffer: 0.0,
+            unspent_cash: 0.0,
+            stats: None,
+            utilization: 0.0,
+            result: TraceOutcome::Skipped { reason: reason.to_string() },
+        }
+    }
+}
+
+/// Append one line to `path` recording each submitted position with the
+/// price, return, volatility and sector it was chosen on
+fn write_snapshot<Q: Serialize>(path: &str, submission_id: &str, portfolio: &[(String, Q)], stocks: &[Stock]) -> std::io::Result<()> {
+    let positions: Vec<Value> = portfolio.iter().map(|(ticker, quantity)| {
+        let stock = stocks.iter().find(|s| &s.ticker == ticker);
+        json!({
+            "ticker": ticker,
+            "quantity": quantity,
+            "price": stock.map(|s| s.get_current_price()),
+            "historical_return": stock.and_then(|s| s.historical_return),
+            "volatility": stock.map(|s| s.volatility),
+            "sector": stock.map(|s| s.sector.as_str()),
+        })
+    }).collect();
+    let line = json!({
+        "submission_id": submission_id,
+        "ts": chrono::Utc::now().to_rfc3339(),
+        "positions": positions,
+    });
+    append_lines(path, &[line.to_string()])
+}
+
+/// Append a compact JSONL trace for debugging/correlation analysis
+fn append_trace(entry: &TraceEntry) {
+    if let Ok(line) = serde_json::to_string(entry) {
+        let _ = append_lines("request_trace.jsonl", &[line]);
+    }
+}
+
+/// Append newline-terminated `lines` to `path` with a single `write_all` on
+/// an append-mode handle, so a crash cannot leave a partial line behind
+/// another writer's output.
+pub(crate) fn append_lines(path: &str, lines: &[String]) -> std::io::Result<()> {
+    let mut buf = String::with_capacity(lines.iter().map(|l| l.len() + 1).sum());
+    for line in lines {
+        buf.push_str(line);
+        buf.push('\n');
+    }
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(buf.as_bytes())
+}
+
+/// Fill in a one-sided investment period so we can still rank on history
+fn complete_investment_period(profile: &mut InvestorProfile, config: &PortfolioConfig) {
+    let latest_year = latest_cached_year()
+        .unwrap_or_else(|| chrono::Datelike::year(&chrono::Utc::now()) as u32);
+    (profile.start_year, profile.end_year) =
+        resolve_investment_period(profile.start_year, profile.end_year, latest_year, config.default_horizon_years);
+}
+
+/// Start/end date strings for the profile's investment period, if complete
+fn period_dates(profile: &InvestorProfile) -> Option<(String, String)> {
+    let (start_year, end_year) = (profile.start_year?, profile.end_year?);
+    Some((format!("{}-01-01", start_year), format!("{}-12-31", end_year)))
+}
+
+/// Fill in a missing start or end year. A missing end year defaults to
+/// `latest_year` (most recent cached data); a missing start year defaults to
+/// `horizon_years` before the end. Both missing is left untouched.
+fn resolve_investment_period(
+    start_year: Option<u32>,
+    end_year: Option<u32>,
+    latest_year: u32,
+    horizon_years: u32,
+) -> (Option<u32>, Option<u32>) {
+    match (start_year, end_year) {
+        (Some(start), None) => {
+            let end = latest_year.max(start);
+            info!("[PROFILE] No end year given - defaulting to {}", end);
+            (Some(start), Some(end))
+        }
+        (None, Some(end)) => {
+            let start = end.saturating_sub(horizon_years);
+            info!("[PROFILE] No start year given - defaulting to {} ({} years before end)", start, horizon_years);
+            (Some(start), Some(end))
+        }
+        other => other,
+    }
+}
+
+/// One line of `rejected_tickers.jsonl`
+#[derive(Serialize, serde::Deserialize)]
+struct RejectedTicker {
+    ticker: String,
+    ts: String,
+    reason: String,
+    /// Submission that got the ticker rejected (absent for migrated entries)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    submission_id: Option<String>,
+}
+
+// Move tickers from the legacy plain-text list at `legacy` into the JSONL
+// file at `path`. They are stamped with the migration time, so they expire
+// one TTL from now.
+fn migrate_legacy_rejected_tickers(legacy: &str, path: &str) {
+    let Ok(contents) = std::fs::read_to_string(legacy) else { return };
+    let tickers: Vec<String> = contents.lines()
+        .map(|l| l.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if let Err(e) = write_rejected_entries(path, &tickers, "legacy", None) {
+        warn!("[VALIDATOR] Failed to migrate {}: {}", legacy, e);
+        return;
+    }
+    let _ = std::fs::rename(legacy, format!("{}.migrated", legacy));
+    info!("[VALIDATOR] Migrated {} legacy rejected tickers to {}", tickers.len(), path);
+}
+
+// Load tickers rejected within the last `ttl_days`. Older entries are ignored
+// so a ticker rejected once gets a second chance. Missing file results in empty set.
+fn load_rejected_tickers(path: &str, ttl_days: i64) -> HashSet<String> {
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(ttl_days);
     match std::fs::read_to_string(path) {
         Ok(contents) => contents
             .lines()
-            .map(|l| l.trim().to_string())
-            .filter(|s| !s.is_empty())
+            .filter_map(|l| serde_json::from_str::<RejectedTicker>(l).ok())
+            .filter(|r| {
+                chrono::DateTime::parse_from_rfc3339(&r.ts)
+                    .map(|ts| ts >= cutoff)
+                    .unwrap_or(false)
+            })
+            .map(|r| r.ticker)
             .collect(),
         Err(_) => HashSet::new(),
     }
 }
 
-// Append new rejected tickers to the file (avoids duplicates by checking existing file first)
-fn append_rejected_tickers(tickers: &[String]) -> Result<(), Box<dyn Error>> {
-    let path = "rejected_tickers.txt";
-    let mut existing = load_rejected_tickers(path);
-    let mut new_added = Vec::new();
+fn write_rejected_entries(path: &str, tickers: &[String], reason: &str, submission_id: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let ts = chrono::Utc::now().to_rfc3339();
+    let lines = tickers.iter()
+        .map(|t| serde_json::to_string(&RejectedTicker {
+            ticker: t.clone(),
+            ts: ts.clone(),
+            reason: reason.to_string(),
+            submission_id: submission_id.map(str::to_string),
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+    append_lines(path, &lines)?;
+    Ok(())
+}
 
-    for t in tickers {
-        if !existing.contains(t) {
-            existing.insert(t.clone());
-            new_added.push(t.clone());
-        }
-    }
+// Append new rejected tickers to the file (skips tickers that are still
+// within their TTL)
+fn append_rejected_tickers(tickers: &[String], reason: &str, submission_id: &str, ttl_days: i64) -> Result<(), Box<dyn Error>> {
+    let existing = load_rejected_tickers(REJECTED_TICKERS_PATH, ttl_days);
+    let new_added: Vec<String> = tickers.iter()
+        .filter(|t| !existing.contains(*t))
+        .cloned()
+        .collect();
 
     if new_added.is_empty() {
         return Ok(());
     }
 
-    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
-    for t in new_added {
-        writeln!(f, "{}", t)?;
-    }
+    let reason: String = reason.chars().take(200).collect();
+    write_rejected_entries(REJECTED_TICKERS_PATH, &new_added, &reason, Some(submission_id))
+}
 
-    Ok(())
+// Phrases in an evaluator error that mean specific tickers were rejected
+const TICKER_REJECTION_PHRASES: &[&str] = &[
+    "invalid ticker", "unknown ticker", "ticker not found", "not a valid ticker",
+];
+
+// True only for permanent ticker-validation failures. Network errors, timeouts
+// and 5xx responses are transient and must not shrink the universe.
+fn is_ticker_validation_error(err_text: &str) -> bool {
+    let lower = err_text.to_lowercase();
+    if let Ok(code_re) = Regex::new(r"\[CODE:\s*(\d{3})") {
+        if let Some(cap) = code_re.captures(err_text) {
+            if cap[1].starts_with('5') {
+                return false;
+            }
+        }
+    }
+    if lower.contains("timed out") || lower.contains("timeout") || lower.contains("connection") {
+        return false;
+    }
+    TICKER_REJECTION_PHRASES.iter().any(|p| lower.contains(p))
 }
 
+// Uppercase words that show up in error prose and must never be read as tickers
+const ERROR_STOP_WORDS: &[&str] = &[
+    "ERROR", "CODE", "HTTP", "HTTPS", "JSON", "OK", "BAD", "REQUEST", "FORBIDDEN",
+    "NOT", "FOUND", "INVALID", "TICKER", "TYPE", "INTERNAL", "SERVER", "API", "POST", "GET",
+];
+
 // Try to parse a few common error message shapes to extract problematic tickers.
+// Only tokens that are tickers in the submitted portfolio are returned.
 // Returns None if nothing parsed.
-fn parse_problematic_tickers(err_text: &str) -> Option<Vec<String>> {
+fn parse_problematic_tickers(err_text: &str, submitted: &[&str]) -> Option<Vec<String>> {
     // Use regex-based extraction to handle multiple error formats.
     let mut found: HashSet<String> = HashSet::new();
 
@@ -364,6 +1309,9 @@ fn parse_problematic_tickers(err_text: &str) -> Option<Vec<String>> {
         }
     }
 
+    let submitted: HashSet<String> = submitted.iter().map(|t| t.to_ascii_uppercase()).collect();
+    found.retain(|t| !ERROR_STOP_WORDS.contains(&t.as_str()) && submitted.contains(t));
+
     if found.is_empty() {
         None
     } else {
@@ -371,38 +1319,93 @@ fn parse_problematic_tickers(err_text: &str) -> Option<Vec<String>> {
     }
 }
 
-/// Pre-submit validator: remove unknown tickers and force portfolio within budget.
+static TICKER_ALIASES: std::sync::OnceLock<HashMap<String, String>> = std::sync::OnceLock::new();
+
+/// Read the alias -> canonical ticker map; empty if the file is missing or
+/// malformed
+fn load_ticker_aliases(path: &str) -> HashMap<String, String> {
+    let Ok(contents) = std::fs::read_to_string(path) else { return HashMap::new() };
+    match serde_json::from_str::<HashMap<String, String>>(&contents) {
+        Ok(map) => {
+            info!("[VALIDATOR] Loaded {} ticker aliases from {}", map.len(), path);
+            map.into_iter()
+                .map(|(alias, canonical)| (alias.trim().to_ascii_uppercase(), canonical.trim().to_ascii_uppercase()))
+                .collect()
+        }
+        Err(e) => {
+            warn!("[VALIDATOR] Ignoring malformed {}: {}", path, e);
+            HashMap::new()
+        }
+    }
+}
+
+fn ticker_aliases() -> &'static HashMap<String, String> {
+    TICKER_ALIASES.get_or_init(|| load_ticker_aliases(TICKER_ALIASES_PATH))
+}
+
+/// Symbol the evaluator expects for `ticker`
+fn canonical_ticker<'a>(ticker: &'a str, aliases: &'a HashMap<String, String>) -> &'a str {
+    aliases.get(ticker).map(String::as_str).unwrap_or(ticker)
+}
+
+/// Pre-submit validator: canonicalize aliased tickers, remove unknown and
+/// previously `rejected` ones and force portfolio within budget less
+/// `PortfolioConfig::submit_margin`.
 fn pre_submit_validate(
     portfolio: &[(String, i32)],
     eligible_stocks: &[Stock],
+    aliases: &HashMap<String, String>,
+    rejected: &HashSet<String>,
     budget: f64,
-) -> Vec<(String, i32)> {
+    config: &PortfolioConfig,
+) -> (Vec<(String, i32)>, ValidationReport) {
+    let margin = config.submit_margin;
     // Conservative pre-submit validator.
-    // We apply a small safety margin because the remote evaluator may value
-    // the portfolio using a different snapshot or canonical tickers. This
-    // margin reduces the chance of a single-submission budget-breach.
-    const SUBMIT_MARGIN: f64 = 0.03; // 3% safety margin
-
-    // Build a lookup of current prices
-    let price_map: HashMap<String, f64> = eligible_stocks
-        .iter()
-        .map(|s| (s.ticker.clone(), s.get_current_price()))
-        .collect();
+    // Build a lookup of current prices keyed by canonical symbol; a stock
+    // cached under its canonical symbol wins over one cached under an alias
+    let mut price_map: HashMap<String, f64> = HashMap::with_capacity(eligible_stocks.len());
+    for s in eligible_stocks {
+        let canonical = canonical_ticker(&s.ticker, aliases);
+        if canonical == s.ticker {
+            price_map.insert(s.ticker.clone(), s.get_current_price());
+        } else {
+            price_map.entry(canonical.to_string()).or_insert(s.get_current_price());
+        }
+    }
+    let mut report = ValidationReport { budget, ..Default::default() };
 
-    // Keep only tickers that are in eligible_stocks and have positive qty
-    let mut cleaned: Vec<(String, i32)> = portfolio.iter()
-        .filter(|(t, q)| *q > 0 && price_map.contains_key(t))
-        .cloned()
-        .collect();
+    // Keep only known symbols with positive qty, under their canonical form
+    let mut cleaned: Vec<(String, i32)> = Vec::with_capacity(portfolio.len());
+    for (t, q) in portfolio {
+        let canonical = canonical_ticker(t, aliases);
+        if *q <= 0 {
+            report.remove(t, "non_positive_quantity");
+        } else if !price_map.contains_key(canonical) {
+            report.remove(t, "unknown_symbol");
+        } else if cleaned.iter().any(|(c, _)| c == canonical) {
+            report.remove(t, "duplicate_alias");
+        } else {
+            if canonical != t {
+                info!("[VALIDATOR] Submitting {} as {}", t, canonical);
+                report.renamed.push(RenamedTicker { from: t.clone(), to: canonical.to_string() });
+            }
+            cleaned.push((canonical.to_string(), *q));
+        }
+    }
 
     // Also drop any tickers we've previously seen rejected by the evaluator
-    let rejected = load_rejected_tickers("rejected_tickers.txt");
     if !rejected.is_empty() {
         let before = cleaned.len();
-        cleaned.retain(|(t, _)| !rejected.contains(t));
+        cleaned.retain(|(t, _)| {
+            let keep = !rejected.contains(t);
+            if !keep {
+                report.remove(t, "previously_rejected");
+            }
+            keep
+        });
         let after = cleaned.len();
         if before != after {
-            eprintln!("[VALIDATOR] Removed {} previously-rejected tickers before submit", before - after);
+            warn!("[VALIDATOR] Removed {} previously-rejected tickers before submit", before - after);
         }
     }
 
@@ -418,26 +1421,113 @@ fn pre_submit_validate(
         }
     });
     if !removed_problematic.is_empty() {
-        eprintln!("[VALIDATOR] Dropped problematic tickers (non-canonical forms): {:?}", removed_problematic);
+        warn!("[VALIDATOR] Dropped problematic tickers (non-canonical forms): {:?}", removed_problematic);
+        for t in &removed_problematic {
+            report.remove(t, "non_canonical");
+        }
+    }
+
+    let before_trim = cleaned.clone();
+    let mut cleaned = trim_to_budget(cleaned, &price_map, budget, margin, config.preserve_positions_on_trim);
+    if let Some(cap) = config.max_ticker_fraction {
+        let blocked: HashSet<String> = rejected.iter().cloned().chain(removed_problematic).collect();
+        if let Some(renamed) = cap_position_values(&mut cleaned, &price_map, eligible_stocks, aliases, &blocked, cap, budget * (1.0 - margin)) {
+            info!("[VALIDATOR] Submitting {} as {}", renamed.from, renamed.to);
+            report.renamed.push(renamed);
+        }
     }
 
+    // Record what budget trimming and value capping changed
+    for (t, from) in &before_trim {
+        match cleaned.iter().find(|(c, _)| c == t) {
+            None => report.remove(t, "budget_trim"),
+            Some((_, to)) if to != from => report.resized.push(PositionChange { ticker: t.clone(), from: *from, to: *to }),
+            Some(_) => {}
+        }
+    }
+    for (t, to) in &cleaned {
+        if !before_trim.iter().any(|(b, _)| b == t) {
+            report.resized.push(PositionChange { ticker: t.clone(), from: 0, to: *to });
+        }
+    }
+    report.final_cost = cleaned.iter().map(|(t, q)| price_map[t] * *q as f64).sum();
+    (cleaned, report)
+}
+
+/// What `pre_submit_validate` changed, recorded in the request trace
+#[derive(Debug, Default, Serialize)]
+struct ValidationReport {
+    removed: Vec<RemovedTicker>,
+    /// Aliased tickers submitted under their canonical symbol
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    renamed: Vec<RenamedTicker>,
+    resized: Vec<PositionChange>,
+    final_cost: f64,
+    budget: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct RemovedTicker {
+    ticker: String,
+    reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct RenamedTicker {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PositionChange {
+    ticker: String,
+    from: i32,
+    to: i32,
+}
+
+impl ValidationReport {
+    fn remove(&mut self, ticker: &str, reason: &'static str) {
+        self.removed.push(RemovedTicker { ticker: ticker.to_string(), reason });
+    }
+}
+
+/// Reduce quantities until `cleaned` fits `budget` less `margin`. With
+/// `preserve_positions`, every position is shrunk (down to 1 share each)
+/// before any is eliminated.
+fn trim_to_budget(
+    mut cleaned: Vec<(String, i32)>,
+    price_map: &HashMap<String, f64>,
+    budget: f64,
+    margin: f64,
+    preserve_positions: bool,
+) -> Vec<(String, i32)> {
     // Compute current total cost
     let mut total: f64 = cleaned.iter().map(|(t, q)| price_map.get(t).unwrap() * (*q as f64)).sum();
 
     // Apply safety margin to the effective budget we target
-    let effective_budget = budget * (1.0 - SUBMIT_MARGIN);
+    let effective_budget = budget * (1.0 - margin);
     if total <= effective_budget { return cleaned; }
 
-    eprintln!("[VALIDATOR] Portfolio exceeds safe budget before submit: ${:.2} > ${:.2} (budget ${:.2}, margin {:.1}%) - reducing...", total, effective_budget, budget, SUBMIT_MARGIN*100.0);
+    warn!("[VALIDATOR] Portfolio exceeds safe budget before submit: ${:.2} > ${:.2} (budget ${:.2}, margin {:.1}%) - reducing...", total, effective_budget, budget, margin*100.0);
 
     // Sort positions by price descending (drop most expensive shares first)
     cleaned.sort_by(|a, b| {
         let pa = *price_map.get(&a.0).unwrap_or(&0.0);
         let pb = *price_map.get(&b.0).unwrap_or(&0.0);
-        pb.partial_cmp(&pa).unwrap()
+        cmp_f64(pb, pa)
     });
 
-    // Iteratively reduce quantities from the most expensive position until under effective_budget
+    if preserve_positions {
+        total = shrink_keeping_positions(&mut cleaned, price_map, total, effective_budget);
+        if total <= effective_budget {
+            warn!("[VALIDATOR] Reduced portfolio cost to ${:.2} (target <= ${:.2}) keeping all {} positions", total, effective_budget, cleaned.len());
+            return cleaned;
+        }
+        warn!("[VALIDATOR] Still over budget with 1 share per position - eliminating positions");
+    }
+
+    // Reduce quantities starting from the most expensive position until under
+    // effective_budget, dropping as many shares per step as the gap needs
     let mut idx = 0;
     while total > effective_budget && !cleaned.is_empty() {
         if idx >= cleaned.len() { idx = 0; } // wrap
@@ -445,8 +1535,9 @@ fn pre_submit_validate(
         let (ref ticker, ref mut qty) = cleaned[idx];
         let price = *price_map.get(ticker).unwrap_or(&0.0);
         if *qty > 0 && price > 0.0 {
-            *qty -= 1;
-            total -= price;
+            let drop = (((total - effective_budget) / price).ceil() as i32).clamp(1, *qty);
+            *qty -= drop;
+            total -= price * drop as f64;
             if *qty == 0 {
                 cleaned.remove(idx);
                 // don't increment idx (next element shifted into this index)
@@ -456,10 +1547,892 @@ fn pre_submit_validate(
         } else {
             // remove impossible position
             let removed = cleaned.remove(idx);
-            eprintln!("[VALIDATOR] Removed impossible position: {:?}", removed);
+            warn!("[VALIDATOR] Removed impossible position: {:?}", removed);
         }
     }
 
-    eprintln!("[VALIDATOR] Reduced portfolio cost to ${:.2} (target <= ${:.2})", total, effective_budget);
+    warn!("[VALIDATOR] Reduced portfolio cost to ${:.2} (target <= ${:.2})", total, effective_budget);
     cleaned
-}
\ No newline at end of file
+}
+
+/// Cap each position's value at `cap` of the portfolio value, keeping at
+/// least one share. The freed cash buys the cheapest uncapped holding (or the
+/// cheapest unblocked eligible stock if none), staying within `budget`.
+/// Returns the rename when the new position is an eligible stock cached
+/// under an alias and submitted under its canonical symbol.
+fn cap_position_values(
+    cleaned: &mut Vec<(String, i32)>,
+    price_map: &HashMap<String, f64>,
+    eligible_stocks: &[Stock],
+    aliases: &HashMap<String, String>,
+    blocked: &HashSet<String>,
+    cap: f64,
+    budget: f64,
+) -> Option<RenamedTicker> {
+    let price = |t: &str| *price_map.get(t).unwrap_or(&0.0);
+    let portfolio_value: f64 = cleaned.iter().map(|(t, q)| price(t) * *q as f64).sum();
+    let limit = portfolio_value * cap;
+
+    let mut capped: HashSet<String> = HashSet::new();
+    for (ticker, qty) in cleaned.iter_mut() {
+        let p = price(ticker);
+        if p > 0.0 && p * *qty as f64 > limit {
+            let allowed = ((limit / p).floor() as i32).max(1);
+            if allowed < *qty {
+                warn!("[VALIDATOR] Capping {} from {} to {} shares ({:.0}% max per ticker)", ticker, qty, allowed, cap * 100.0);
+                *qty = allowed;
+                capped.insert(ticker.clone());
+            }
+        }
+    }
+    if capped.is_empty() {
+        return None;
+    }
+
+    let total: f64 = cleaned.iter().map(|(t, q)| price(t) * *q as f64).sum();
+    let free_cash = budget - total;
+    let cheapest = |candidates: &mut dyn Iterator<Item = &String>| -> Option<String> {
+        candidates
+            .filter(|t| !capped.contains(*t) && price(t) > 0.0 && price(t) <= free_cash)
+            .min_by(|a, b| cmp_f64(price(a), price(b)))
+            .cloned()
+    };
+    // New positions come from the eligible stocks themselves, under their
+    // canonical symbol, so the caller can still find the stock afterwards
+    let canonical_of: HashMap<String, &str> = eligible_stocks.iter()
+        .map(|s| (canonical_ticker(&s.ticker, aliases).to_string(), s.ticker.as_str()))
+        .filter(|(canonical, _)| !blocked.contains(canonical) && !canonical.contains(['.', '/', '^', ' ']))
+        .collect();
+    let target = cheapest(&mut cleaned.iter().map(|(t, _)| t))
+        .or_else(|| cheapest(&mut canonical_of.keys()))?;
+
+    let p = price(&target);
+    let held = cleaned.iter().find(|(t, _)| *t == target).map(|(_, q)| *q).unwrap_or(0);
+    let room_under_cap = ((limit / p).floor() as i32 - held).max(0);
+    let extra = ((free_cash / p).floor() as i32).min(room_under_cap);
+    if extra <= 0 {
+        return None;
+    }
+    warn!("[VALIDATOR] Redeployed freed cash into {} x{}", target, extra);
+    match cleaned.iter_mut().find(|(t, _)| *t == target) {
+        Some((_, q)) => {
+            *q += extra;
+            None
+        }
+        None => {
+            cleaned.push((target.clone(), extra));
+            // Only an alias-cached stock needs renaming; when both forms
+            // are cached the canonical one is already findable
+            let cached_as = *canonical_of.get(&target)?;
+            let canonical_cached = eligible_stocks.iter().any(|s| s.ticker == target);
+            (cached_as != target && !canonical_cached).then(|| RenamedTicker { from: cached_as.to_string(), to: target })
+        }
+    }
+}
+
+/// Shrink quantities towards `target` without eliminating any position:
+/// scale all positions down proportionally (floor, minimum 1 share), then
+/// trim single shares from the most expensive positions that still hold more
+/// than one. `portfolio` must be sorted by price descending. Returns the new
+/// total cost, which may still exceed `target` if every position is at 1 share.
+fn shrink_keeping_positions(
+    portfolio: &mut [(String, i32)],
+    price_map: &HashMap<String, f64>,
+    total: f64,
+    target: f64,
+) -> f64 {
+    let price_of = |t: &String| *price_map.get(t).unwrap_or(&0.0);
+    if total <= 0.0 {
+        return total;
+    }
+
+    let factor = target / total;
+    for (_, qty) in portfolio.iter_mut() {
+        *qty = ((*qty as f64 * factor).floor() as i32).max(1);
+    }
+    let mut total: f64 = portfolio.iter().map(|(t, q)| price_of(t) * (*q as f64)).sum();
+
+    // Fine-tune: the 1-share floor can leave us over target. Take as many
+    // shares as the gap needs from each position that has more than one.
+    for (ticker, qty) in portfolio.iter_mut() {
+        let price = price_of(ticker);
+        if total <= target {
+            break;
+        }
+        if *qty > 1 && price > 0.0 {
+            let drop = (((total - target) / price).ceil() as i32).min(*qty - 1);
+            *qty -= drop;
+            total -= price * drop as f64;
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(message: &str) -> InvestorProfile {
+        let context = json!({ "message": message }).to_string();
+        InvestorProfile::from_context(&context, &CompanyLookup::default()).unwrap()
+    }
+
+    #[test]
+    fn meta_envelope_only_when_enabled() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let positions = [("AAPL", 3)];
+
+        let bare = PortfolioConfig::default();
+        let body = submission_body(&positions, submission_meta(&client, &bare, 5000.0).as_ref());
+        assert_eq!(body, json!([{ "ticker": "AAPL", "quantity": 3 }]));
+
+        let with_meta = PortfolioConfig { include_meta: true, ..PortfolioConfig::default() };
+        let body = submission_body(&positions, submission_meta(&client, &with_meta, 5000.0).as_ref());
+        assert_eq!(body["portfolio"], json!([{ "ticker": "AAPL", "quantity": 3 }]));
+        assert_eq!(body["meta"]["risk_level"], "Aggressive");
+        assert!(body["meta"]["allocation_mode"].is_string());
+    }
+
+    #[test]
+    fn missing_end_year_defaults_to_latest_year() {
+        assert_eq!(resolve_investment_period(Some(2015), None, 2024, 5), (Some(2015), Some(2024)));
+        // A start after the latest cached year still yields a usable range
+        assert_eq!(resolve_investment_period(Some(2026), None, 2024, 5), (Some(2026), Some(2026)));
+    }
+
+    #[test]
+    fn missing_start_year_uses_horizon() {
+        assert_eq!(resolve_investment_period(None, Some(2020), 2024, 5), (Some(2015), Some(2020)));
+        assert_eq!(resolve_investment_period(None, Some(2020), 2024, 3), (Some(2017), Some(2020)));
+        assert_eq!(resolve_investment_period(None, None, 2024, 5), (None, None));
+    }
+
+    #[test]
+    fn batch_response_is_split_into_contexts() {
+        let batch = json!([{ "message": "first" }, { "message": "second" }]).to_string();
+        let contexts = split_context_batch(batch);
+        assert_eq!(contexts.len(), 2);
+        assert_eq!(serde_json::from_str::<Value>(&contexts[1]).unwrap()["message"], "second");
+
+        // A server without batching answers with a single context object
+        let single = json!({ "message": "only" }).to_string();
+        assert_eq!(split_context_batch(single.clone()), vec![single]);
+    }
+
+    #[tokio::test]
+    async fn get_contexts_reads_a_batch_from_the_server() {
+        let server = mock_server::MockServer::start(|req| {
+            assert!(req.path.starts_with("/request"));
+            (200, json!([{ "message": "a" }, { "message": "b" }, { "message": "c" }]).to_string())
+        }).await;
+        let api = ApiClient::new(server.host(), server.port(), "code");
+
+        let contexts = get_contexts(&api, 3).await.unwrap();
+        assert_eq!(contexts.len(), 3);
+        let request = &server.requests()[0];
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("GET", "/request?count=3"));
+        assert_eq!(request.header("x-api-code"), Some("code"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn repeated_context_is_skipped_as_duplicate() {
+        let first = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        // Same client, re-served with different whitespace
+        let again = profile("Ann  Lee is 30 years old  with a budget of $10,000.");
+        let other = profile("Bob Ray is 52 years old with a budget of $8,000.");
+
+        let mut recent = RecentContexts::new(Duration::from_secs(600), 8);
+        let now = Instant::now();
+        assert!(!recent.check_and_insert(profile_fingerprint(&first), now));
+        assert!(recent.check_and_insert(profile_fingerprint(&again), now + Duration::from_secs(5)));
+        assert!(!recent.check_and_insert(profile_fingerprint(&other), now + Duration::from_secs(6)));
+        // Outside the window the same client is processed again
+        assert!(!recent.check_and_insert(profile_fingerprint(&first), now + Duration::from_secs(700)));
+    }
+
+    #[test]
+    fn submission_id_is_stable() {
+        let body = json!([{ "ticker": "AAPL", "quantity": 3 }]);
+        let id = idempotency_key("{\"message\":\"hi\"}", &body);
+        // Pinned so a hasher change can't silently break correlation with old logs
+        assert_eq!(id, "7b8ef4a26f4cc837");
+        assert_ne!(id, idempotency_key("{\"message\":\"hi\"}", &json!([])));
+    }
+
+    #[test]
+    fn log_writes_for_one_request_share_the_submission_id() {
+        let dir = scratch_dir("submission_id");
+        let context = json!({ "message": "Ann Lee is 30 years old with a budget of $10,000." }).to_string();
+        let portfolio = vec![("AAPL".to_string(), 3)];
+        let refs: Vec<(&str, i32)> = portfolio.iter().map(|(t, q)| (t.as_str(), *q)).collect();
+        let id = idempotency_key(&context, &submission_body(&refs, None));
+
+        let snapshots = dir.join("snapshots.jsonl");
+        let rejected = dir.join("rejected.jsonl");
+        write_snapshot(snapshots.to_str().unwrap(), &id, &portfolio, &[]).unwrap();
+        write_rejected_entries(rejected.to_str().unwrap(), &["AAPL".to_string()], "invalid ticker", Some(&id)).unwrap();
+
+        let read = |p: &std::path::Path| serde_json::from_str::<Value>(std::fs::read_to_string(p).unwrap().trim()).unwrap();
+        assert_eq!(read(&snapshots)["submission_id"], id.as_str());
+        assert_eq!(read(&rejected)["submission_id"], id.as_str());
+    }
+
+    #[tokio::test]
+    async fn slow_planning_hits_the_deadline() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            RequestOutcome::Skipped { reason: "finished".to_string(), eligible_count: 0 }
+        };
+        let outcome = plan_by_deadline(slow, Instant::now() + Duration::from_millis(20)).await;
+        assert!(matches!(outcome, RequestOutcome::Skipped { ref reason, .. } if reason == "deadline_exceeded"));
+
+        let quick = async { RequestOutcome::Skipped { reason: "finished".to_string(), eligible_count: 0 } };
+        let outcome = plan_by_deadline(quick, Instant::now() + Duration::from_secs(5)).await;
+        assert!(matches!(outcome, RequestOutcome::Skipped { ref reason, .. } if reason == "finished"));
+
+        // Blocking work can't be interrupted, but its late result is dropped
+        let blocking = async {
+            std::thread::sleep(Duration::from_millis(50));
+            RequestOutcome::Skipped { reason: "finished".to_string(), eligible_count: 0 }
+        };
+        let outcome = plan_by_deadline(blocking, Instant::now() + Duration::from_millis(10)).await;
+        assert!(matches!(outcome, RequestOutcome::Skipped { ref reason, .. } if reason == "deadline_exceeded"));
+    }
+
+    #[tokio::test]
+    async fn request_past_its_deadline_is_not_planned() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let config = Arc::new(PortfolioConfig::default());
+        let points: SharedPointsStore = Arc::new(std::sync::RwLock::new(PointsStore::default()));
+        let expired = Instant::now() - Duration::from_secs(1);
+        let outcome = plan_request(&client, synthetic_universe(), config.clone(), points.clone(), expired).await;
+        assert!(matches!(outcome, RequestOutcome::Skipped { ref reason, .. } if reason == "deadline_exceeded"));
+
+        let outcome = plan_request(&client, synthetic_universe(), config, points, Instant::now() + Duration::from_secs(30)).await;
+        assert!(matches!(outcome, RequestOutcome::Ready { .. }));
+    }
+
+    /// Fresh scratch directory for tests that touch the filesystem
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("quant_proj_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rejected_tickers_expire_after_ttl() {
+        let dir = scratch_dir("rejected_ttl");
+        let path = dir.join("rejected.jsonl");
+        let path = path.to_str().unwrap();
+        let old = (chrono::Utc::now() - chrono::Duration::days(10)).to_rfc3339();
+        let recent = (chrono::Utc::now() - chrono::Duration::days(2)).to_rfc3339();
+        append_lines(path, &[
+            json!({ "ticker": "OLD", "ts": old, "reason": "invalid ticker" }).to_string(),
+            json!({ "ticker": "NEW", "ts": recent, "reason": "invalid ticker" }).to_string(),
+        ]).unwrap();
+
+        assert_eq!(load_rejected_tickers(path, 7), HashSet::from(["NEW".to_string()]));
+        assert_eq!(load_rejected_tickers(path, 30).len(), 2);
+    }
+
+    #[test]
+    fn legacy_rejected_list_is_migrated() {
+        let dir = scratch_dir("rejected_legacy");
+        let legacy = dir.join("rejected.txt");
+        let path = dir.join("rejected.jsonl");
+        std::fs::write(&legacy, "ABC\n\nXYZ\n").unwrap();
+
+        migrate_legacy_rejected_tickers(legacy.to_str().unwrap(), path.to_str().unwrap());
+        let loaded = load_rejected_tickers(path.to_str().unwrap(), 7);
+        assert_eq!(loaded, HashSet::from(["ABC".to_string(), "XYZ".to_string()]));
+        assert!(!legacy.exists());
+        assert!(dir.join("rejected.txt.migrated").exists());
+    }
+
+    fn prices(entries: &[(&str, f64)]) -> HashMap<String, f64> {
+        entries.iter().map(|(t, p)| (t.to_string(), *p)).collect()
+    }
+
+    #[test]
+    fn trim_keeps_all_positions_when_preserving() {
+        let price_map = prices(&[("BIG", 500.0), ("MID", 100.0), ("LOW", 20.0)]);
+        let portfolio = vec![("BIG".to_string(), 2), ("MID".to_string(), 5), ("LOW".to_string(), 10)];
+        // $1,700 of positions against a $1,000 budget
+        let trimmed = trim_to_budget(portfolio.clone(), &price_map, 1000.0, 0.0, true);
+        let cost: f64 = trimmed.iter().map(|(t, q)| price_map[t] * *q as f64).sum();
+        assert!(cost <= 1000.0);
+        assert_eq!(trimmed.len(), 3);
+        assert!(trimmed.iter().all(|(_, q)| *q >= 1));
+
+        // Without the guard the most expensive position is dropped entirely
+        let trimmed = trim_to_budget(portfolio, &price_map, 1000.0, 0.0, false);
+        assert!(!trimmed.iter().any(|(t, _)| t == "BIG"));
+    }
+
+    /// Six low-volatility stocks in distinct sectors with known returns
+    fn synthetic_universe() -> Vec<Stock> {
+        [
+            ("AAA", 120.0, "Technology", 14.0),
+            ("BBB", 45.0, "Healthcare", 9.0),
+            ("CCC", 80.0, "Energy", 6.0),
+            ("DDD", 30.0, "Utilities", 4.0),
+            ("EEE", 210.0, "Financials", 11.0),
+            ("FFF", 15.0, "Industrials", 7.5),
+        ].iter().map(|(ticker, price, sector, historical_return)| {
+            let mut stock: Stock = serde_json::from_value(json!({
+                "ticker": ticker, "price": price, "sector": sector, "volatility": 0.01,
+                "first_trading_date": "1990-01-02",
+            })).unwrap();
+            stock.historical_return = Some(*historical_return);
+            stock
+        }).collect()
+    }
+
+    #[test]
+    fn synthetic_request_is_planned_without_network() {
+        let mut client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let config = PortfolioConfig::default();
+        complete_investment_period(&mut client, &config);
+        let points: SharedPointsStore = std::sync::Arc::new(std::sync::RwLock::new(PointsStore::default()));
+        let universe = synthetic_universe();
+        let RequestOutcome::Ready { portfolio: PlannedPortfolio::Whole(planned), cost, eligible, notes } = handle_request(&client, &universe, &config, &points) else {
+            panic!("expected a whole-share portfolio");
+        };
+        assert!(!planned.is_empty());
+        assert_eq!(eligible.len(), universe.len());
+        assert!(!notes.no_historical_data);
+        let priced: f64 = planned.iter()
+            .map(|(t, q)| universe.iter().find(|s| &s.ticker == t).unwrap().price * *q as f64)
+            .sum();
+        assert!((cost - priced).abs() < 1e-6);
+        assert!(cost > 0.0 && cost <= client.budget, "cost {} against budget {}", cost, client.budget);
+    }
+
+    #[test]
+    fn empty_eligible_set_is_a_skip_not_an_error() {
+        let mut client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let config = PortfolioConfig::default();
+        complete_investment_period(&mut client, &config);
+        let points: SharedPointsStore = std::sync::Arc::new(std::sync::RwLock::new(PointsStore::default()));
+        let mut universe = synthetic_universe();
+        for s in &mut universe {
+            s.price = f64::NAN;
+        }
+        let outcome = handle_request(&client, &universe, &config, &points);
+        assert!(matches!(outcome, RequestOutcome::Skipped { ref reason, eligible_count: 0 } if reason == "no_eligible_stocks"));
+
+        // The price-gap heuristic is relaxed before giving up
+        let mut flagged = synthetic_universe();
+        for s in &mut flagged {
+            s.price_gap_flagged = true;
+        }
+        assert!(matches!(handle_request(&client, &flagged, &config, &points), RequestOutcome::Ready { .. }));
+    }
+
+    fn priced_stock(ticker: &str, price: f64) -> Stock {
+        serde_json::from_value(json!({
+            "ticker": ticker, "price": price, "sector": "Industrials", "volatility": 0.01,
+        })).unwrap()
+    }
+
+    #[test]
+    fn dominant_position_is_capped_and_cash_redeployed() {
+        let config = PortfolioConfig { max_ticker_fraction: Some(0.35), submit_margin: 0.0, ..PortfolioConfig::default() };
+        let eligible = vec![priced_stock("CAPBIG", 100.0), priced_stock("CAPMID", 40.0), priced_stock("CAPOLD", 10.0)];
+        let aliases = HashMap::from([("CAPOLD".to_string(), "CAPNEW".to_string())]);
+
+        // 800 of 880 in one ticker: capped to 3 shares, the rest buys the held CAPMID
+        let portfolio = vec![("CAPBIG".to_string(), 8), ("CAPMID".to_string(), 2)];
+        let (cleaned, report) = pre_submit_validate(&portfolio, &eligible, &aliases, &HashSet::new(), 1_000.0, &config);
+        assert_eq!(cleaned, vec![("CAPBIG".to_string(), 3), ("CAPMID".to_string(), 7)]);
+        assert!(report.final_cost <= 1_000.0);
+        assert!(report.renamed.is_empty());
+
+        // With nothing else held the cash goes to the cheapest eligible stock,
+        // which is cached under an alias and submitted as its canonical symbol
+        let (cleaned, report) = pre_submit_validate(&[("CAPBIG".to_string(), 8)], &eligible, &aliases, &HashSet::new(), 1_000.0, &config);
+        assert_eq!(cleaned, vec![("CAPBIG".to_string(), 2), ("CAPNEW".to_string(), 28)]);
+        assert_eq!((report.renamed[0].from.as_str(), report.renamed[0].to.as_str()), ("CAPOLD", "CAPNEW"));
+        assert!(report.final_cost <= 1_000.0);
+    }
+
+    #[test]
+    fn trim_converges_on_a_huge_over_budget_portfolio() {
+        let price_map = prices(&[("BIG", 500.0), ("PENNY", 0.01), ("LOW", 1.0)]);
+        // Billions of dollars over: one share per step would never finish
+        let portfolio = vec![("BIG".to_string(), 3), ("PENNY".to_string(), 2_000_000_000), ("LOW".to_string(), 2_000_000_000)];
+        for preserve in [true, false] {
+            let trimmed = trim_to_budget(portfolio.clone(), &price_map, 10_000.0, 0.01, preserve);
+            let cost: f64 = trimmed.iter().map(|(t, q)| price_map[t] * *q as f64).sum();
+            assert!(cost <= 9_900.0, "preserve {}: cost {}", preserve, cost);
+            assert!(cost > 9_900.0 - 500.0, "preserve {}: cost only {}", preserve, cost);
+            if preserve {
+                assert_eq!(trimmed.len(), 3);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn request_to_submission_end_to_end() {
+        let context = json!({ "message": "Ann Lee is 30 years old with a budget of $10,000." }).to_string();
+        let served = context.clone();
+        let server = mock_server::MockServer::start(move |req| match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/request") => (200, served.clone()),
+            ("POST", "/submit") => (200, json!({ "passed": true }).to_string()),
+            _ => (404, "{}".to_string()),
+        }).await;
+        let api = ApiClient::new(server.host(), server.port(), "code");
+
+        let universe = synthetic_universe();
+        let prices: HashMap<&str, f64> = universe.iter().map(|s| (s.ticker.as_str(), s.price)).collect();
+
+        let raw = get_context(&api, "/request").await.unwrap();
+        let mut client = InvestorProfile::from_context(&raw, &CompanyLookup::default()).unwrap();
+        let config = PortfolioConfig::default();
+        complete_investment_period(&mut client, &config);
+        let points: SharedPointsStore = std::sync::Arc::new(std::sync::RwLock::new(PointsStore::default()));
+        let RequestOutcome::Ready { portfolio: PlannedPortfolio::Whole(planned), .. } = handle_request(&client, &universe, &config, &points) else {
+            panic!("expected a whole-share portfolio");
+        };
+        let refs: Vec<(&str, i32)> = planned.iter().map(|(t, q)| (t.as_str(), *q)).collect();
+        let submission_id = idempotency_key(&raw, &submission_body(&refs, None));
+        send_portfolio(&api, refs, None, &submission_id).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let submit = &requests[1];
+        assert_eq!((submit.method.as_str(), submit.path.as_str()), ("POST", "/submit"));
+        assert_eq!(submit.header("idempotency-key"), Some(submission_id.as_str()));
+        let body: Vec<Value> = serde_json::from_str(&submit.body).unwrap();
+        assert!(!body.is_empty());
+        let mut seen = HashSet::new();
+        let mut cost = 0.0;
+        for position in &body {
+            let ticker = position["ticker"].as_str().unwrap();
+            let quantity = position["quantity"].as_i64().unwrap();
+            assert!(seen.insert(ticker), "{} submitted twice", ticker);
+            assert!(quantity > 0, "{} has quantity {}", ticker, quantity);
+            cost += prices[ticker] * quantity as f64;
+        }
+        assert!(cost <= client.budget, "cost {} over budget {}", cost, client.budget);
+    }
+
+    #[test]
+    fn trace_entry_round_trips_through_json() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let raw = json!({ "message": "Ann Lee is 30 years old with a budget of $10,000." }).to_string();
+        let skipped = TraceEntry::skipped(&raw, Some(&client), 0, "no_eligible_stocks");
+        let line = serde_json::to_string(&skipped).unwrap();
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["raw_context"], raw.as_str());
+        assert_eq!(parsed["parsed_profile"]["budget"], 10_000.0);
+        assert_eq!(parsed["result"], json!({ "outcome": "skipped", "reason": "no_eligible_stocks" }));
+        assert!(parsed.get("plan").is_none() && parsed.get("stats").is_none());
+        assert_eq!(serde_json::to_value(&skipped).unwrap(), parsed);
+
+        let submitted = TraceEntry {
+            eligible_count: 2,
+            portfolio: vec![json!({ "ticker": "AAA", "quantity": 3 })],
+            allocated_cost: 360.0,
+            result: TraceOutcome::Submitted { response: "{\"passed\":true}".to_string(), evaluation: Some(json!({ "passed": true })) },
+            ..TraceEntry::skipped(&raw, Some(&client), 2, "unused")
+        };
+        let parsed: Value = serde_json::from_str(&serde_json::to_string(&submitted).unwrap()).unwrap();
+        assert_eq!(parsed["submission_id"], skipped.submission_id.as_str());
+        assert_eq!(parsed["portfolio"], json!([{ "ticker": "AAA", "quantity": 3 }]));
+        assert_eq!(parsed["result"]["outcome"], "submitted");
+        assert_eq!(parsed["result"]["evaluation"]["passed"], true);
+    }
+
+    #[test]
+    fn error_prose_is_not_read_as_tickers() {
+        let submitted = ["XYZ", "AAPL", "MSFT"];
+        assert_eq!(parse_problematic_tickers("ERROR CODE 403 HTTP JSON: rejected XYZ", &submitted), Some(vec!["XYZ".to_string()]));
+        // Uppercase prose alone names nothing that was submitted
+        assert_eq!(parse_problematic_tickers("ERROR CODE 403 FORBIDDEN", &submitted), None);
+        // A ticker-like token that wasn't submitted is ignored too
+        assert_eq!(parse_problematic_tickers("ERROR CODE 403: unknown ticker QQQQ", &submitted), None);
+
+        let api_error = "Error - something went wrong when requesting [CODE: 400]: invalid ticker type: msft of type str";
+        assert_eq!(parse_problematic_tickers(api_error, &submitted), Some(vec!["MSFT".to_string()]));
+    }
+
+    #[test]
+    fn only_ticker_rejections_are_blacklisted() {
+        let transient = [
+            "error sending request for url (http://127.0.0.1:8000/submit): operation timed out",
+            "error sending request for url (http://127.0.0.1:8000/submit): client error (Connect): tcp connect error: Connection refused",
+            "Error - something went wrong when requesting [CODE: 503]: upstream unavailable",
+            // A 5xx wins even when the body mentions tickers
+            "Error - something went wrong when requesting [CODE: 500]: invalid ticker lookup failed",
+        ];
+        for err in transient {
+            assert!(!is_ticker_validation_error(err), "{}", err);
+        }
+        let rejections = [
+            "Error - something went wrong when requesting [CODE: 400]: invalid ticker type: XYZ of type str",
+            "Error - something went wrong when requesting [CODE: 422]: Unknown ticker ['ABCD']",
+            "ticker not found: QQQQ",
+        ];
+        for err in rejections {
+            assert!(is_ticker_validation_error(err), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn reset_submit_is_retried_with_the_same_key() {
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let seen = attempts.clone();
+        let server = mock_server::MockServer::start(move |_| {
+            if seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                (mock_server::DROP_CONNECTION, String::new())
+            } else {
+                (200, json!({ "passed": true }).to_string())
+            }
+        }).await;
+        let api = ApiClient::new(server.host(), server.port(), "code");
+
+        let response = send_portfolio(&api, vec![("AAPL", 2)], None, "key-1").await.unwrap();
+        assert_eq!(response, json!({ "passed": true }).to_string());
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.header("idempotency-key") == Some("key-1")));
+        assert_eq!(requests[0].body, requests[1].body);
+    }
+
+    #[tokio::test]
+    async fn rejected_submit_is_not_retried() {
+        let server = mock_server::MockServer::start(|_| (403, json!({ "error": "forbidden" }).to_string())).await;
+        let api = ApiClient::new(server.host(), server.port(), "code");
+
+        assert!(send_portfolio(&api, vec![("AAPL", 2)], None, "key-2").await.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pipeline_plans_concurrently_and_finishes_in_order() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let served = std::sync::Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        let server = mock_server::MockServer::start(move |_| {
+            (200, json!({ "message": format!("context {}", counter.fetch_add(1, Ordering::SeqCst)) }).to_string())
+        }).await;
+        let mut contexts = spawn_context_producer(ApiClient::new(server.host(), server.port(), "code"), 1);
+        let (stop, mut shutdown) = tokio::sync::watch::channel(false);
+
+        let running = std::sync::Arc::new(AtomicUsize::new(0));
+        let peak = std::sync::Arc::new(AtomicUsize::new(0));
+        let plan = |context: String, _fetched_at: Instant| {
+            let index: usize = context.trim_end_matches("\"}").rsplit(' ').next().unwrap().parse().unwrap();
+            let (running, peak) = (running.clone(), peak.clone());
+            tokio::spawn(async move {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                // Earlier contexts take longer, so plans finish out of order
+                tokio::time::sleep(Duration::from_millis(60 - (index % 3) as u64 * 20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                index
+            })
+        };
+        let mut finished = Vec::new();
+        let finish = async |index: usize| -> Result<(), Box<dyn Error>> {
+            finished.push(index);
+            if finished.len() == 6 {
+                let _ = stop.send(true);
+            }
+            Ok(())
+        };
+
+        let handled = run_pipeline(&mut contexts, &mut shutdown, 3, plan, finish).await.unwrap();
+        // Plans already in flight at shutdown are still finished
+        assert!(handled >= 6);
+        assert_eq!(finished, (0..handled).collect::<Vec<_>>());
+        let peak = peak.load(Ordering::SeqCst);
+        assert!((2..=3).contains(&peak), "peak concurrency {}", peak);
+    }
+
+    #[test]
+    fn submitted_quantities_are_positive_and_capped() {
+        let cleaned = sanitize_quantities(vec![("ZERO", 0), ("NEG", -4), ("HUGE", i32::MAX), ("OK", 7)]).unwrap();
+        assert_eq!(cleaned, vec![("HUGE", MAX_SUBMIT_QUANTITY as i32), ("OK", 7)]);
+        // Cost math on the capped quantity no longer risks i32 overflow
+        assert!(cleaned[0].1.checked_mul(2).is_some());
+
+        let fractional = sanitize_quantities(vec![("NAN", f64::NAN), ("INF", f64::INFINITY), ("HALF", 0.5), ("BIG", 5e9)]).unwrap();
+        assert_eq!(fractional, vec![("HALF", 0.5), ("BIG", MAX_SUBMIT_QUANTITY)]);
+
+        assert!(sanitize_quantities(vec![("ZERO", 0), ("NEG", -1)]).is_err());
+        assert!(sanitize_quantities(Vec::<(&str, i32)>::new()).is_err());
+    }
+
+    #[test]
+    fn log_subscriber_initializes() {
+        tracing::subscriber::with_default(log_subscriber(), || {
+            assert!(tracing::enabled!(tracing::Level::ERROR));
+            info!("logging smoke test");
+        });
+    }
+
+    #[test]
+    fn fractional_quantities_are_submitted_as_floats() {
+        let body = submission_body(&[("AAPL", 1.25_f64)], None);
+        assert_eq!(body, json!([{ "ticker": "AAPL", "quantity": 1.25 }]));
+        assert!(body[0]["quantity"].is_f64());
+        assert!(submission_body(&[("AAPL", 2)], None)[0]["quantity"].is_i64());
+    }
+
+    #[test]
+    fn json_output_is_one_parseable_line() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let portfolio = [json!({ "ticker": "AAA", "quantity": 3 })];
+        let result = TraceOutcome::Submitted { response: "{\"points\":4}".to_string(), evaluation: Some(json!({ "points": 4 })) };
+        let line = submission_summary(&client, &portfolio, 360.0, 2.5, &result);
+        assert!(!line.contains('\n'));
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["profile"]["budget"], 10_000.0);
+        assert_eq!(parsed["portfolio"], json!([{ "ticker": "AAA", "quantity": 3 }]));
+        assert_eq!(parsed["total_cost"], 360.0);
+        assert_eq!(parsed["predicted_points"], 2.5);
+        assert_eq!(parsed["result"]["evaluation"]["points"], 4);
+    }
+
+    #[test]
+    fn concurrent_appends_keep_every_line_whole() {
+        let path = scratch_dir("appends").join("trace.jsonl");
+        let path = path.to_str().unwrap();
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        let line = json!({ "writer": writer, "i": i, "pad": "x".repeat(512) }).to_string();
+                        append_lines(path, &[line.clone(), line]).unwrap();
+                    }
+                });
+            }
+        });
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(contents.lines().count(), 8 * 50 * 2);
+        for line in contents.lines() {
+            serde_json::from_str::<Value>(line).unwrap_or_else(|e| panic!("torn line {:?}: {}", line, e));
+        }
+    }
+
+    #[test]
+    fn validation_report_records_trims_and_removals() {
+        let config = PortfolioConfig { submit_margin: 0.0, preserve_positions_on_trim: true, ..PortfolioConfig::default() };
+        let eligible = vec![priced_stock("VRPA", 100.0), priced_stock("VRPB", 50.0), priced_stock("VRPC", 20.0)];
+        let no_aliases = HashMap::new();
+
+        // 1,500 against a 1,000 budget: every position survives the trim
+        let portfolio = vec![("VRPA".to_string(), 10), ("VRPB".to_string(), 10)];
+        let (cleaned, report) = pre_submit_validate(&portfolio, &eligible, &no_aliases, &HashSet::new(), 1_000.0, &config);
+        assert!(report.removed.is_empty());
+        assert_eq!(report.resized.len(), 2);
+        for change in &report.resized {
+            let (_, qty) = cleaned.iter().find(|(t, _)| *t == change.ticker).unwrap();
+            assert_eq!((change.from, change.to), (10, *qty));
+            assert!(change.to < change.from);
+        }
+        assert_eq!(report.final_cost, cleaned.iter().map(|(t, q)| if t == "VRPA" { 100.0 } else { 50.0 } * *q as f64).sum::<f64>());
+        assert!(report.final_cost <= report.budget);
+
+        // Rejected, unknown and empty positions are removed with their reason
+        let portfolio = vec![
+            ("VRPA".to_string(), 2),
+            ("VRPB".to_string(), 2),
+            ("VRPC".to_string(), 0),
+            ("VRPX".to_string(), 1),
+        ];
+        let rejected = HashSet::from(["VRPB".to_string()]);
+        let (cleaned, report) = pre_submit_validate(&portfolio, &eligible, &no_aliases, &rejected, 1_000.0, &config);
+        assert_eq!(cleaned, vec![("VRPA".to_string(), 2)]);
+        let removed: Vec<(&str, &str)> = report.removed.iter().map(|r| (r.ticker.as_str(), r.reason)).collect();
+        assert_eq!(removed, vec![("VRPC", "non_positive_quantity"), ("VRPX", "unknown_symbol"), ("VRPB", "previously_rejected")]);
+        assert!(report.resized.is_empty());
+        assert_eq!(report.final_cost, 200.0);
+    }
+
+    #[test]
+    fn api_code_prefers_flag_then_env_then_file() {
+        let dir = scratch_dir("api_code");
+        let file = dir.join(".api_code");
+        std::fs::write(&file, "from-file\nsecond line\n").unwrap();
+        let file = file.to_str().unwrap();
+        let env = || Some("from-env".to_string());
+
+        assert_eq!(resolve_api_code(Some("from-flag"), env(), file).unwrap(), "from-flag");
+        assert_eq!(resolve_api_code(None, env(), file).unwrap(), "from-env");
+        assert_eq!(resolve_api_code(None, None, file).unwrap(), "from-file");
+        // Blank values fall through to the next source
+        assert_eq!(resolve_api_code(Some("  "), Some(String::new()), file).unwrap(), "from-file");
+
+        let missing = dir.join("missing").to_str().unwrap().to_string();
+        assert_eq!(resolve_api_code(None, env(), &missing).unwrap(), "from-env");
+        assert!(resolve_api_code(None, None, &missing).is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_returns_after_the_current_request() {
+        let (tx, mut contexts) = tokio::sync::mpsc::channel(8);
+        for i in 0..3 {
+            tx.send(Ok((format!("context {}", i), Instant::now()))).await.unwrap();
+        }
+        let (stop, mut shutdown) = tokio::sync::watch::channel(false);
+        let plan = |context: String, _fetched_at: Instant| tokio::spawn(async move { context });
+        let mut finished = Vec::new();
+        let finish = async |context: String| -> Result<(), Box<dyn Error>> {
+            // Ctrl-C arrives while the first request is being submitted
+            let _ = stop.send(true);
+            finished.push(context);
+            Ok(())
+        };
+        let handled = run_pipeline(&mut contexts, &mut shutdown, 1, plan, finish).await.unwrap();
+        assert_eq!(handled, 1);
+        assert_eq!(finished, vec!["context 0"]);
+        // The queued contexts were left untouched
+        assert_eq!(contexts.len(), 2);
+    }
+
+    #[test]
+    fn submit_margin_is_configurable_and_clamped() {
+        let margin = |config: PortfolioConfig, env: Option<&str>| config.with_submit_margin_override(env.map(str::to_string)).submit_margin;
+        let default = PortfolioConfig::default;
+        assert_eq!(margin(default(), None), default().submit_margin);
+        assert_eq!(margin(default(), Some("0.1")), 0.1);
+        assert_eq!(margin(default(), Some("0.5")), 0.2);
+        assert_eq!(margin(default(), Some("-0.05")), 0.0);
+        assert_eq!(margin(default(), Some("lots")), default().submit_margin);
+        assert_eq!(margin(PortfolioConfig { submit_margin: 0.35, ..default() }, None), 0.2);
+        // The environment wins over the config file
+        assert_eq!(margin(PortfolioConfig { submit_margin: 0.05, ..default() }, Some("0.15")), 0.15);
+
+        // A wider margin leaves more of the budget unspent
+        let config = default().with_submit_margin_override(Some("0.1".to_string()));
+        let eligible = vec![priced_stock("MRGA", 10.0)];
+        let (cleaned, report) = pre_submit_validate(&[("MRGA".to_string(), 100)], &eligible, &HashMap::new(), &HashSet::new(), 1_000.0, &config);
+        assert_eq!(cleaned, vec![("MRGA".to_string(), 90)]);
+        assert!(report.final_cost <= 900.0);
+    }
+
+    #[test]
+    fn trace_separates_intended_buffer_from_unspent_cash() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let config = PortfolioConfig::default();
+        let alloc_budget = config.alloc_budget(client.budget, client.risk_tolerance);
+        // Only part of the allocation budget was deployed
+        let total_cost = alloc_budget - 1_250.0;
+        let (intended_buffer, unspent_cash) = cash_buffer(client.budget, alloc_budget, total_cost);
+        assert!((intended_buffer - (client.budget - alloc_budget)).abs() < 1e-9);
+        assert!((unspent_cash - intended_buffer - 1_250.0).abs() < 1e-9);
+
+        let raw = json!({ "message": "Ann Lee is 30 years old with a budget of $10,000." }).to_string();
+        let entry = TraceEntry {
+            alloc_budget,
+            allocated_cost: total_cost,
+            intended_buffer,
+            unspent_cash,
+            ..TraceEntry::skipped(&raw, Some(&client), 1, "unused")
+        };
+        let parsed = serde_json::to_value(&entry).unwrap();
+        assert_eq!(parsed["intended_buffer"], intended_buffer);
+        assert_eq!(parsed["unspent_cash"], unspent_cash);
+    }
+
+    #[test]
+    fn breakdown_attributes_points_per_ticker() {
+        let eligible = vec![priced_stock("BRKA", 10.0), priced_stock("BRKB", 10.0), priced_stock("BRKC", 10.0)];
+        let portfolio = vec![("BRKA".to_string(), 1), ("BRKB".to_string(), 1), ("BRKC".to_string(), 1)];
+        let learn = |evaluation: Value| {
+            let points: SharedPointsStore = Arc::new(std::sync::RwLock::new(PointsStore::default()));
+            learn_from_evaluation(&points, &evaluation, &portfolio, &eligible, VolThresholds::default());
+            let store = points.read().unwrap();
+            ["BRKA", "BRKB", "BRKC"].map(|t| store.get_score(t))
+        };
+
+        let evaluation = json!({
+            "points": 60.0,
+            "breakdown": { "BRKA": 50.0, "BRKB": { "contribution": 10.0 }, "risk_penalty": -5.0 },
+        });
+        // BRKC has no contribution listed, so it learns nothing
+        assert_eq!(learn(evaluation), [0.5, 0.1, 0.0]);
+        assert_eq!(learn(json!({ "points": 60.0 })), [0.6, 0.6, 0.6]);
+        assert_eq!(learn(json!({ "passed": true })), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn no_history_is_flagged_and_ranked_by_market_cap() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000. Keep it to 2 names.");
+        let config = PortfolioConfig { max_sector_fraction: 1.0, ..PortfolioConfig::default() };
+        let points: SharedPointsStore = Arc::new(std::sync::RwLock::new(PointsStore::default()));
+        // As if neither cache was loaded and the price API was unreachable:
+        // no stock has a historical return
+        let universe: Vec<Stock> = [("NHSA", 1_000), ("NHSB", 50_000), ("NHSC", 5_000), ("NHSD", 900_000)].iter()
+            .map(|(ticker, cap)| Stock { market_cap: *cap, ..priced_stock(ticker, 25.0) })
+            .collect();
+        let RequestOutcome::Ready { portfolio: PlannedPortfolio::Whole(planned), notes, .. } = handle_request(&client, &universe, &config, &points) else {
+            panic!("expected a whole-share portfolio");
+        };
+        assert!(notes.no_historical_data);
+        let mut held: Vec<&str> = planned.iter().map(|(t, _)| t.as_str()).collect();
+        held.sort();
+        assert_eq!(held, vec!["NHSB", "NHSD"]);
+
+        // Without the fallback equal volatilities keep the input order
+        let config = PortfolioConfig { market_cap_fallback: false, ..config };
+        let RequestOutcome::Ready { portfolio: PlannedPortfolio::Whole(planned), .. } = handle_request(&client, &universe, &config, &points) else {
+            panic!("expected a whole-share portfolio");
+        };
+        let mut held: Vec<&str> = planned.iter().map(|(t, _)| t.as_str()).collect();
+        held.sort();
+        assert_eq!(held, vec!["NHSA", "NHSB"]);
+    }
+
+    #[test]
+    fn snapshot_has_one_entry_per_position() {
+        let path = scratch_dir("snapshot_fields").join("snapshots.jsonl");
+        let path = path.to_str().unwrap();
+        let mut stocks = vec![priced_stock("SNPA", 12.5), priced_stock("SNPB", 40.0)];
+        stocks[0].historical_return = Some(18.0);
+        let portfolio = vec![("SNPA".to_string(), 4), ("SNPB".to_string(), 2)];
+        write_snapshot(path, "sub-1", &portfolio, &stocks).unwrap();
+        write_snapshot(path, "sub-2", &portfolio[..1], &stocks).unwrap();
+
+        let lines: Vec<Value> = std::fs::read_to_string(path).unwrap()
+            .lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["submission_id"], "sub-1");
+        assert_eq!(lines[0]["positions"], json!([
+            { "ticker": "SNPA", "quantity": 4, "price": 12.5, "historical_return": 18.0, "volatility": 0.01, "sector": "Industrials" },
+            { "ticker": "SNPB", "quantity": 2, "price": 40.0, "historical_return": null, "volatility": 0.01, "sector": "Industrials" },
+        ]));
+        assert_eq!(lines[1]["positions"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn aliases_are_canonicalized_and_unknown_symbols_dropped() {
+        let dir = scratch_dir("ticker_aliases");
+        let path = dir.join("ticker_aliases.json");
+        std::fs::write(&path, r#"{ " alsold ": "alsnew", "ALSDUP": "ALSKEEP" }"#).unwrap();
+        let aliases = load_ticker_aliases(path.to_str().unwrap());
+        assert_eq!(aliases.get("ALSOLD").map(String::as_str), Some("ALSNEW"));
+        std::fs::write(&path, "not json").unwrap();
+        assert!(load_ticker_aliases(path.to_str().unwrap()).is_empty());
+
+        let config = PortfolioConfig { submit_margin: 0.0, ..PortfolioConfig::default() };
+        let eligible = vec![priced_stock("ALSNEW", 10.0), priced_stock("ALSKEEP", 20.0), priced_stock("ALSDUP", 20.0)];
+        let portfolio = vec![
+            ("ALSOLD".to_string(), 3),
+            ("ALSKEEP".to_string(), 2),
+            // Same company as ALSKEEP under its old symbol
+            ("ALSDUP".to_string(), 4),
+            ("ALSNONE".to_string(), 5),
+        ];
+        let (cleaned, report) = pre_submit_validate(&portfolio, &eligible, &aliases, &HashSet::new(), 1_000.0, &config);
+        assert_eq!(cleaned, vec![("ALSNEW".to_string(), 3), ("ALSKEEP".to_string(), 2)]);
+        let renamed: Vec<(&str, &str)> = report.renamed.iter().map(|r| (r.from.as_str(), r.to.as_str())).collect();
+        assert_eq!(renamed, vec![("ALSOLD", "ALSNEW")]);
+        let removed: Vec<(&str, &str)> = report.removed.iter().map(|r| (r.ticker.as_str(), r.reason)).collect();
+        assert_eq!(removed, vec![("ALSDUP", "duplicate_alias"), ("ALSNONE", "unknown_symbol")]);
+    }
+}
@@ -0,0 +1,139 @@
+//! Minimal HTTP/1.1 server for tests. Each request is answered by a handler
+//! and recorded so tests can assert on what the client sent. Connections are
+//! kept alive unless the client asks to close, and counted, so tests can see
+//! whether a client reuses them.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// One request received by the mock server
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: String,
+    /// Path including any query string
+    pub path: String,
+    /// Header names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Status a handler returns to reset the connection instead of replying,
+/// as a network drop after the request was sent would
+pub const DROP_CONNECTION: u16 = 0;
+
+type Handler = dyn Fn(&Recorded) -> (u16, String) + Send + Sync;
+
+pub struct MockServer {
+    port: u16,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    /// Serve on an ephemeral localhost port; `handler` returns the status
+    /// and JSON body for each request
+    pub async fn start(handler: impl Fn(&Recorded) -> (u16, String) + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (log, accepted) = (requests.clone(), connections.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let (handler, log) = (handler.clone(), log.clone());
+                tokio::spawn(async move {
+                    let _ = serve(stream, handler, log).await;
+                });
+            }
+        });
+        MockServer { port, requests, connections }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Base URL without the port, as `ApiClient::new` expects
+    pub fn host(&self) -> &'static str {
+        "http://127.0.0.1"
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// TCP connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn serve(mut stream: TcpStream, handler: Arc<Handler>, log: Arc<Mutex<Vec<Recorded>>>) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let header_end = loop {
+            if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        };
+
+        let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or("").split_whitespace();
+        let method = request_line.next().unwrap_or("").to_string();
+        let path = request_line.next().unwrap_or("").to_string();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(n, v)| (n.trim().to_ascii_lowercase(), v.trim().to_string()))
+            .collect();
+        let content_length: usize = headers.iter()
+            .find(|(n, _)| n == "content-length")
+            .and_then(|(_, v)| v.parse().ok())
+            .unwrap_or(0);
+        while buf.len() < header_end + content_length {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let body_end = buf.len().min(header_end + content_length);
+        let body = String::from_utf8_lossy(&buf[header_end..body_end]).to_string();
+        // Anything past this request belongs to the next one
+        buf.drain(..body_end);
+
+        let request = Recorded { method, path, headers, body };
+        let close = request.header("connection").is_some_and(|v| v.eq_ignore_ascii_case("close"));
+        let (status, response) = handler(&request);
+        log.lock().unwrap().push(request);
+        if status == DROP_CONNECTION {
+            // Zero linger makes the close send an RST
+            stream.set_linger(Some(std::time::Duration::ZERO))?;
+            return Ok(());
+        }
+        let reply = format!(
+            "HTTP/1.1 {} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}\r\n{}",
+            status, response.len(), if close { "Connection: close\r\n" } else { "" }, response
+        );
+        stream.write_all(reply.as_bytes()).await?;
+        if close {
+            return stream.shutdown().await;
+        }
+    }
+}
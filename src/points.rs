@@ -1,42 +1,153 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
+
+/// Points store loaded once and shared across requests/tasks
+pub type SharedPointsStore = Arc<RwLock<PointsStore>>;
+
+/// Per-allocation decay applied when the store file doesn't specify one
+pub const DEFAULT_DECAY_FACTOR: f64 = 0.995;
+
+fn default_decay_factor() -> f64 {
+    DEFAULT_DECAY_FACTOR
+}
+
+/// How `PointsStore::merge` combines a score present in both stores
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeMode {
+    /// Add the two scores
+    #[default]
+    Sum,
+    /// Keep the larger score
+    Max,
+}
 
 /// Simple persistent points store used to bias stock selection.
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PointsStore {
     pub scores: HashMap<String, f64>,
+    /// Sector-level scores so learned signal generalizes to similar stocks
+    #[serde(default)]
+    pub sector_scores: HashMap<String, f64>,
+    /// Ticker scores split by the volatility bucket ("low", "medium",
+    /// "high") the ticker was in when it scored
+    #[serde(default)]
+    pub bucket_scores: HashMap<String, HashMap<String, f64>>,
+    /// Per-allocation decay factor in (0,1], persisted with the scores so it
+    /// can be tuned without a rebuild
+    #[serde(default = "default_decay_factor")]
+    pub decay_factor: f64,
+    /// When set, ticker (and separately sector) scores are rescaled on save
+    /// so the maximum is 1.0 once it exceeds this cap
+    #[serde(default)]
+    pub max_score: Option<f64>,
+    /// RFC 3339 time a ticker score was last learned
+    #[serde(default)]
+    pub last_updated: Option<String>,
     #[serde(skip)]
     path: String,
 }
 
+impl Default for PointsStore {
+    fn default() -> Self {
+        PointsStore {
+            scores: HashMap::new(),
+            sector_scores: HashMap::new(),
+            bucket_scores: HashMap::new(),
+            decay_factor: DEFAULT_DECAY_FACTOR,
+            max_score: None,
+            last_updated: None,
+            path: String::new(),
+        }
+    }
+}
+
 impl PointsStore {
     /// Load a points store from `path`. If missing or invalid, returns an empty store.
+    /// Accepts both the current `{scores, sector_scores}` layout and the legacy
+    /// flat ticker -> score map.
     pub fn load(path: &str) -> Self {
+        let empty = PointsStore { path: path.to_string(), ..Default::default() };
         match fs::read_to_string(path) {
-            Ok(s) => match serde_json::from_str::<HashMap<String, f64>>(&s) {
-                Ok(map) => PointsStore { scores: map, path: path.to_string() },
-                Err(e) => {
-                    eprintln!("[WARN] Could not parse points file '{}': {} - starting fresh", path, e);
-                    PointsStore { scores: HashMap::new(), path: path.to_string() }
+            Ok(s) => {
+                if let Ok(mut store) = serde_json::from_str::<PointsStore>(&s) {
+                    if !(store.decay_factor > 0.0 && store.decay_factor <= 1.0) {
+                        warn!("Invalid decay_factor {} in '{}' - using {}", store.decay_factor, path, DEFAULT_DECAY_FACTOR);
+                        store.decay_factor = DEFAULT_DECAY_FACTOR;
+                    }
+                    if store.max_score.is_some_and(|cap| cap.is_nan() || cap <= 1.0) {
+                        warn!("Invalid max_score {:?} in '{}' (must exceed 1.0) - renormalization disabled", store.max_score, path);
+                        store.max_score = None;
+                    }
+                    store.path = path.to_string();
+                    return store;
+                }
+                match serde_json::from_str::<HashMap<String, f64>>(&s) {
+                    Ok(map) => PointsStore { scores: map, ..empty },
+                    Err(e) => {
+                        warn!("Could not parse points file '{}': {} - starting fresh", path, e);
+                        empty
+                    }
                 }
-            },
-            Err(_) => PointsStore { scores: HashMap::new(), path: path.to_string() },
+            }
+            Err(_) => empty,
         }
     }
 
-    /// Persist the store to disk. Errors are printed but not returned.
-    pub fn save(&self) {
-        match serde_json::to_string_pretty(&self.scores) {
+    /// Load a points store from `path` once, wrapped for shared access.
+    pub fn load_shared(path: &str) -> SharedPointsStore {
+        Arc::new(RwLock::new(Self::load(path)))
+    }
+
+    /// In-memory copy that never writes back to disk (for replays/backtests)
+    pub fn detached(&self) -> Self {
+        PointsStore { path: String::new(), ..self.clone() }
+    }
+
+    /// Persist the store to disk atomically (write a temp file, then rename
+    /// over the original). Errors are printed but not returned. Detached
+    /// stores are not persisted. Scores are renormalized first when they
+    /// exceed `max_score`.
+    pub fn save(&mut self) {
+        if self.path.is_empty() {
+            return;
+        }
+        if let Some(cap) = self.max_score {
+            renormalize(self.scores.values_mut().collect(), cap, "ticker");
+            renormalize(self.sector_scores.values_mut().collect(), cap, "sector");
+            // One factor across buckets keeps the overall sums comparable
+            renormalize(self.bucket_scores.values_mut().flat_map(|b| b.values_mut()).collect(), cap, "bucket");
+        }
+        match serde_json::to_string_pretty(self) {
             Ok(s) => {
-                if let Err(e) = fs::OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)
-                    .and_then(|mut f| f.write_all(s.as_bytes()))
+                let tmp_path = format!("{}.tmp", self.path);
+                if let Err(e) = fs::OpenOptions::new().create(true).write(true).truncate(true).open(&tmp_path)
+                    .and_then(|mut f| { f.write_all(s.as_bytes())?; f.sync_all() })
+                    .and_then(|_| fs::rename(&tmp_path, &self.path))
                 {
-                    eprintln!("[ERROR] Failed to write points file '{}': {}", self.path, e);
+                    error!("Failed to write points file '{}': {}", self.path, e);
                 }
             }
-            Err(e) => eprintln!("[ERROR] Could not serialize points store: {}", e),
+            Err(e) => error!("Could not serialize points store: {}", e),
+        }
+    }
+
+    /// Fold `other`'s ticker, sector and bucket scores into this store (e.g. a store
+    /// learned on another machine). Scores only in one store are kept as is;
+    /// `last_updated` becomes the later of the two.
+    pub fn merge(&mut self, other: &PointsStore, mode: MergeMode) {
+        merge_scores(&mut self.scores, &other.scores, mode);
+        merge_scores(&mut self.sector_scores, &other.sector_scores, mode);
+        for (bucket, scores) in &other.bucket_scores {
+            merge_scores(self.bucket_scores.entry(bucket.clone()).or_default(), scores, mode);
+        }
+        let parse = |ts: &Option<String>| ts.as_deref().and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok());
+        if parse(&other.last_updated) > parse(&self.last_updated) {
+            self.last_updated = other.last_updated.clone();
         }
     }
 
@@ -52,29 +163,329 @@ impl PointsStore {
         let mut new = old + delta;
         if new < 0.0 { new = 0.0; }
         *entry = new;
+        self.last_updated = Some(chrono::Utc::now().to_rfc3339());
 
         // Log when a negative delta was applied or the score decreased
         if delta < 0.0 || new < old {
-            eprintln!("[POINTS] Negative update for {}: delta={:.4}, old={:.4} -> new={:.4}", ticker, delta, old, new);
-
-            // Try to append to a persistent log for later analysis. Ignore failures.
-            if let Ok(mut f) = fs::OpenOptions::new().create(true).append(true).open("negative_points.log") {
-                use std::time::{SystemTime, UNIX_EPOCH};
-                if let Ok(since) = SystemTime::now().duration_since(UNIX_EPOCH) {
-                    let ts = since.as_secs();
-                    let _ = f.write_all(format!("{},{},{:.4},{:.4},{:.4}\n", ts, ticker, delta, old, new).as_bytes());
-                } else {
-                    let _ = f.write_all(format!("{}, {:.4}, {:.4}, {:.4}\n", ticker, delta, old, new).as_bytes());
-                }
+            warn!("[POINTS] Negative update for {}: delta={:.4}, old={:.4} -> new={:.4}", ticker, delta, old, new);
+
+            // Try to append to a persistent log for later analysis. Ignore
+            // failures. Detached stores (replays, backtests) leave no trace.
+            if self.path.is_empty() {
+                return;
             }
+            use std::time::{SystemTime, UNIX_EPOCH};
+            let line = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(since) => format!("{},{},{:.4},{:.4},{:.4}", since.as_secs(), ticker, delta, old, new),
+                Err(_) => format!("{}, {:.4}, {:.4}, {:.4}", ticker, delta, old, new),
+            };
+            let _ = crate::append_lines("negative_points.log", &[line]);
+        }
+    }
+
+    /// Get the score for a sector (0.0 if missing)
+    pub fn get_sector_score(&self, sector: &str) -> f64 {
+        *self.sector_scores.get(sector).unwrap_or(&0.0)
+    }
+
+    /// Add (or subtract) points for a sector. Scores are clamped to >= 0.
+    pub fn add_sector_score(&mut self, sector: &str, delta: f64) {
+        let entry = self.sector_scores.entry(sector.to_string()).or_insert(0.0);
+        *entry = (*entry + delta).max(0.0);
+    }
+
+    /// Add (or subtract) points for a ticker within a volatility bucket.
+    /// Scores are clamped to >= 0.
+    pub fn add_bucket_score(&mut self, bucket: &str, ticker: &str, delta: f64) {
+        let entry = self.bucket_scores.entry(bucket.to_string()).or_default().entry(ticker.to_string()).or_insert(0.0);
+        *entry = (*entry + delta).max(0.0);
+    }
+
+    /// The `n` highest-scoring tickers in volatility `bucket`, best first
+    /// (ties by ticker)
+    pub fn top_tickers(&self, bucket: &str, n: usize) -> Vec<(String, f64)> {
+        self.bucket_scores.get(bucket).map_or_else(Vec::new, |scores| top_n(scores, n))
+    }
+
+    /// The `n` highest-scoring tickers with scores summed across buckets.
+    /// Stores saved before buckets were tracked rank by their ticker scores.
+    pub fn top_tickers_overall(&self, n: usize) -> Vec<(String, f64)> {
+        if self.bucket_scores.is_empty() {
+            return top_n(&self.scores, n);
+        }
+        let mut totals: HashMap<String, f64> = HashMap::new();
+        for (ticker, score) in self.bucket_scores.values().flatten() {
+            *totals.entry(ticker.clone()).or_insert(0.0) += score;
         }
+        top_n(&totals, n)
     }
 
-    /// Multiply all scores by a decay factor in (0,1] to slowly forget old signals.
+    /// The `n` highest-scoring sectors, best first (ties by sector)
+    pub fn top_sectors(&self, n: usize) -> Vec<(String, f64)> {
+        top_n(&self.sector_scores, n)
+    }
+
+    /// Apply the store's configured `decay_factor` to all scores.
+    pub fn decay(&mut self) {
+        self.decay_all(self.decay_factor);
+    }
+
+    /// Multiply all ticker, sector and bucket scores by a decay factor in
+    /// (0,1] to slowly forget old signals.
     pub fn decay_all(&mut self, factor: f64) {
         if !(0.0..=1.0).contains(&factor) { return; }
-        for v in self.scores.values_mut() {
+        let buckets = self.bucket_scores.values_mut().flat_map(|b| b.values_mut());
+        for v in self.scores.values_mut().chain(self.sector_scores.values_mut()).chain(buckets) {
             *v *= factor;
         }
     }
 }
+
+/// Per-ticker score deltas for one evaluator response. If the response has a
+/// `breakdown` object (ticker -> contribution, or ticker -> `{contribution}` /
+/// `{points}`), each submitted ticker gets its own contribution / 100 (0 when
+/// missing); otherwise every ticker gets the overall `points` / 100. None when
+/// the response carries no numeric `points`.
+pub fn evaluation_deltas(response: &Value, tickers: &[&str]) -> Option<Vec<(String, f64)>> {
+    let points = response.get("points")?.as_f64()?;
+    let breakdown = response.get("breakdown").and_then(Value::as_object);
+    let deltas = tickers.iter().map(|ticker| {
+        let delta = match breakdown {
+            Some(parts) => parts.get(*ticker)
+                .and_then(|v| v.as_f64().or_else(|| v.get("contribution").or_else(|| v.get("points")).and_then(Value::as_f64)))
+                .unwrap_or(0.0) / 100.0,
+            None => points / 100.0,
+        };
+        (ticker.to_string(), delta)
+    }).collect();
+    Some(deltas)
+}
+
+fn merge_scores(into: &mut HashMap<String, f64>, from: &HashMap<String, f64>, mode: MergeMode) {
+    for (key, &score) in from {
+        into.entry(key.clone())
+            .and_modify(|s| *s = match mode {
+                MergeMode::Sum => *s + score,
+                MergeMode::Max => s.max(score),
+            })
+            .or_insert(score);
+    }
+}
+
+fn top_n(scores: &HashMap<String, f64>, n: usize) -> Vec<(String, f64)> {
+    let mut ranked: Vec<(String, f64)> = scores.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    ranked.sort_by(|(ka, a), (kb, b)| b.total_cmp(a).then_with(|| ka.cmp(kb)));
+    ranked.truncate(n);
+    ranked
+}
+
+/// Rescale `scores` so the maximum is 1.0 when it exceeds `cap`, keeping
+/// ratios between scores
+fn renormalize(scores: Vec<&mut f64>, cap: f64, kind: &str) {
+    let max = scores.iter().map(|v| **v).fold(0.0, f64::max);
+    if max <= cap || max <= 0.0 {
+        return;
+    }
+    info!("[POINTS] Renormalizing {} scores (max {:.4} > cap {:.4})", kind, max, cap);
+    for v in scores {
+        *v /= max;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_file(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("quant_proj_points_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("points_store.json")
+    }
+
+    #[test]
+    fn one_load_serves_many_reads() {
+        let path = scratch_file("shared");
+        fs::write(&path, r#"{"scores": {"AAPL": 2.5, "MSFT": 1.0}}"#).unwrap();
+        let shared = PointsStore::load_shared(path.to_str().unwrap());
+        // The file is gone, so every read below must come from the one load
+        fs::remove_file(&path).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                (0..10_000).map(|_| shared.read().unwrap().get_score("AAPL")).sum::<f64>()
+            })
+        }).collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 25_000.0);
+        }
+        assert_eq!(shared.read().unwrap().get_score("MSFT"), 1.0);
+        assert_eq!(shared.read().unwrap().get_score("NONE"), 0.0);
+
+        // Saving through the handle replaces the file in one step
+        {
+            let mut store = shared.write().unwrap();
+            store.add_score("AAPL", 1.0);
+            store.save();
+        }
+        assert_eq!(PointsStore::load(path.to_str().unwrap()).get_score("AAPL"), 3.5);
+        assert!(!path.with_extension("json.tmp").exists());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn sector_scores_accumulate_and_decay() {
+        let mut store = PointsStore::default();
+        assert_eq!(store.get_sector_score("Technology"), 0.0);
+        store.add_sector_score("Technology", 2.0);
+        store.add_sector_score("Technology", 0.5);
+        store.add_sector_score("Energy", -3.0);
+        assert_eq!(store.get_sector_score("Technology"), 2.5);
+        // Clamped at zero like ticker scores
+        assert_eq!(store.get_sector_score("Energy"), 0.0);
+
+        store.add_score("AAPL", 4.0);
+        store.decay_all(0.5);
+        assert_eq!(store.get_sector_score("Technology"), 1.25);
+        assert_eq!(store.get_score("AAPL"), 2.0);
+    }
+
+    #[test]
+    fn decay_factor_is_read_from_the_store_file() {
+        let path = scratch_file("decay");
+        let path_str = path.to_str().unwrap();
+        assert_eq!(PointsStore::load(path_str).decay_factor, DEFAULT_DECAY_FACTOR);
+
+        fs::write(&path, r#"{"scores": {"AAPL": 8.0}, "decay_factor": 0.5}"#).unwrap();
+        let mut store = PointsStore::load(path_str);
+        store.decay();
+        assert_eq!(store.get_score("AAPL"), 4.0);
+
+        // Out-of-range factors fall back to the default
+        fs::write(&path, r#"{"scores": {"AAPL": 8.0}, "decay_factor": 1.5}"#).unwrap();
+        assert_eq!(PointsStore::load(path_str).decay_factor, DEFAULT_DECAY_FACTOR);
+        fs::write(&path, r#"{"scores": {"AAPL": 8.0}, "decay_factor": 0.0}"#).unwrap();
+        assert_eq!(PointsStore::load(path_str).decay_factor, DEFAULT_DECAY_FACTOR);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    fn bucketed_store() -> PointsStore {
+        let mut store = PointsStore::default();
+        for (bucket, ticker, score) in [
+            ("low", "KO", 3.0), ("low", "JNJ", 5.0), ("low", "DUK", 1.0),
+            ("high", "NVDA", 4.0), ("high", "TSLA", 2.0), ("high", "KO", 2.5),
+        ] {
+            store.add_bucket_score(bucket, ticker, score);
+        }
+        store
+    }
+
+    #[test]
+    fn top_tickers_rank_within_a_bucket() {
+        let store = bucketed_store();
+        assert_eq!(
+            store.top_tickers("low", 10),
+            vec![("JNJ".to_string(), 5.0), ("KO".to_string(), 3.0), ("DUK".to_string(), 1.0)]
+        );
+        assert_eq!(store.top_tickers("low", 2), vec![("JNJ".to_string(), 5.0), ("KO".to_string(), 3.0)]);
+        assert_eq!(store.top_tickers("high", 1), vec![("NVDA".to_string(), 4.0)]);
+        assert!(store.top_tickers("medium", 5).is_empty());
+        assert!(store.top_tickers("low", 0).is_empty());
+    }
+
+    #[test]
+    fn top_tickers_overall_sums_across_buckets() {
+        let store = bucketed_store();
+        assert_eq!(
+            store.top_tickers_overall(3),
+            vec![("KO".to_string(), 5.5), ("JNJ".to_string(), 5.0), ("NVDA".to_string(), 4.0)]
+        );
+        assert_eq!(store.top_tickers_overall(10).len(), 5);
+
+        // Without bucket scores the flat ticker scores are ranked
+        let mut legacy = PointsStore::default();
+        legacy.add_score("AAPL", 2.0);
+        legacy.add_score("MSFT", 3.0);
+        assert_eq!(legacy.top_tickers_overall(1), vec![("MSFT".to_string(), 3.0)]);
+    }
+
+    #[test]
+    fn renormalize_caps_the_max_and_keeps_ratios() {
+        let path = scratch_file("renormalize");
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, r#"{
+            "scores": {"AAPL": 40.0, "MSFT": 20.0, "KO": 5.0},
+            "sector_scores": {"Technology": 8.0, "Energy": 2.0},
+            "bucket_scores": {"low": {"KO": 5.0}, "high": {"AAPL": 40.0}},
+            "max_score": 10.0
+        }"#).unwrap();
+        let mut store = PointsStore::load(path_str);
+        store.save();
+        let saved = PointsStore::load(path_str);
+        assert_eq!(saved.get_score("AAPL"), 1.0);
+        assert_eq!(saved.get_score("MSFT"), 0.5);
+        assert_eq!(saved.get_score("KO"), 0.125);
+        // Under the cap, so left alone
+        assert_eq!(saved.get_sector_score("Technology"), 8.0);
+        // Buckets share one factor so cross-bucket sums keep their ratios
+        assert_eq!(saved.top_tickers("high", 1), vec![("AAPL".to_string(), 1.0)]);
+        assert_eq!(saved.top_tickers("low", 1), vec![("KO".to_string(), 0.125)]);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    fn store(scores: &[(&str, f64)], sectors: &[(&str, f64)], buckets: &[(&str, &str, f64)], last_updated: &str) -> PointsStore {
+        let mut store = PointsStore {
+            scores: scores.iter().map(|(t, s)| (t.to_string(), *s)).collect(),
+            sector_scores: sectors.iter().map(|(t, s)| (t.to_string(), *s)).collect(),
+            last_updated: Some(last_updated.to_string()),
+            ..PointsStore::default()
+        };
+        for (bucket, ticker, score) in buckets {
+            store.add_bucket_score(bucket, ticker, *score);
+        }
+        store
+    }
+
+    #[test]
+    fn merge_sums_or_keeps_the_max() {
+        let ours = || store(
+            &[("AAPL", 2.0), ("MSFT", 1.0)],
+            &[("Technology", 3.0)],
+            &[("low", "KO", 1.0)],
+            "2026-01-02T00:00:00+00:00",
+        );
+        let theirs = store(
+            &[("AAPL", 5.0), ("XOM", 4.0)],
+            &[("Technology", 1.0), ("Energy", 2.0)],
+            &[("low", "KO", 3.0), ("high", "TSLA", 2.0)],
+            "2026-03-04T00:00:00+00:00",
+        );
+
+        let mut summed = ours();
+        summed.merge(&theirs, MergeMode::Sum);
+        assert_eq!(summed.get_score("AAPL"), 7.0);
+        assert_eq!(summed.get_sector_score("Technology"), 4.0);
+        assert_eq!(summed.top_tickers("low", 1), vec![("KO".to_string(), 4.0)]);
+
+        let mut maxed = ours();
+        maxed.merge(&theirs, MergeMode::Max);
+        assert_eq!(maxed.get_score("AAPL"), 5.0);
+        assert_eq!(maxed.get_sector_score("Technology"), 3.0);
+        assert_eq!(maxed.top_tickers("low", 1), vec![("KO".to_string(), 3.0)]);
+
+        // Entries in only one store carry over unchanged in either mode
+        for merged in [&summed, &maxed] {
+            assert_eq!(merged.get_score("MSFT"), 1.0);
+            assert_eq!(merged.get_score("XOM"), 4.0);
+            assert_eq!(merged.get_sector_score("Energy"), 2.0);
+            assert_eq!(merged.top_tickers("high", 1), vec![("TSLA".to_string(), 2.0)]);
+            assert_eq!(merged.last_updated.as_deref(), Some("2026-03-04T00:00:00+00:00"));
+        }
+
+        // An older store does not roll last_updated back
+        let mut newer = store(&[], &[], &[], "2026-05-01T00:00:00+00:00");
+        newer.merge(&ours(), MergeMode::Sum);
+        assert_eq!(newer.last_updated.as_deref(), Some("2026-05-01T00:00:00+00:00"));
+    }
+}
@@ -1,38 +1,460 @@
-use crate::investor::{InvestorProfile, RiskLevel};
-use crate::stocks::Stock;
-use crate::points::PointsStore;
+use crate::investor::{ExclusionStrictness, InvestorProfile, RiskLevel};
+use crate::stocks::{Stock, PriceGapBounds, PricingBasis, monthly_return_stats, monthly_returns, pairwise_correlation};
+use crate::points::{PointsStore, SharedPointsStore};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use tracing::{error, info, warn};
 
 // Learning / weighting configuration
-const RETURN_WEIGHT: f64 = 0.7; // weight given to historical return
-const POINTS_WEIGHT: f64 = 0.3; // weight given to learned points
-const POINTS_DECAY: f64 = 0.995; // per-allocation decay to slowly forget old signals
+const RETURN_WEIGHT: f64 = 0.7; // default weight given to historical return (rest goes to learned points)
 
 // Concentrated allocation settings
-// When true, allocate quantities using a rank-based quantity table
-// (e.g. 50 shares of top, 20 of second, ...). If budget doesn't allow the
-// full target quantity the value is reduced to what can be afforded.
+// When true, `AllocationStrategy::Auto` allocates quantities using a
+// rank-based quantity table (e.g. 50 shares of top, 20 of second, ...). If
+// budget doesn't allow the full target quantity the value is reduced to what
+// can be afforded.
 const CONCENTRATE_ALLOCATION: bool = true;
+// Allocation budgets below this use greedy allocation under `AllocationStrategy::Auto`
+const GREEDY_BUDGET_THRESHOLD: f64 = 5000.0;
 // Default rank quantity targets for positions (index 0 = top performer)
 const RANK_QUANTITIES: &[i32] = &[
     50, 20, 15, 10, 8, 6, 5, 4, 3, 2, // top 10
     1, 1, 1, 1, 1, // fallback for additional ranks
 ];
-// Hard cap on number of distinct positions in any portfolio
+// Default hard cap on number of distinct positions in any portfolio
 const MAX_POSITIONS: usize = 7;
-// Fraction of the provided budget that we allow the allocator to spend.
+// Default fraction of the provided budget that we allow the allocator to spend.
 // Set to 0.70 to only use 70% of the budget for purchases; the remainder
 // is intentionally left unspent as a conservative buffer.
-pub const BUDGET_SPEND_FRACTION: f64 = 0.60;
+const BUDGET_SPEND_FRACTION: f64 = 0.60;
+// Default fraction of the budget the pre-submit validator keeps unspent, since
+// the evaluator may value the portfolio using a different price snapshot
+const SUBMIT_MARGIN: f64 = 0.03;
+// Largest accepted submit margin; larger values are clamped
+const MAX_SUBMIT_MARGIN: f64 = 0.2;
+// Upper bound on the budget fraction Kelly sizing may put into a single name
+const KELLY_MAX_POSITION_FRACTION: f64 = 0.35;
+// Default investment horizon assumed when a brief gives an end year but no start year
+const DEFAULT_HORIZON_YEARS: u32 = 5;
+// Added to volatility in the risk-adjusted ranking so near-zero volatility doesn't blow up the ratio
+const RISK_ADJUST_EPSILON: f64 = 0.01;
 
-/// Calculate the total cost of a portfolio
-fn calculate_portfolio_cost(portfolio: &[(String, i32)], stocks: &[Stock]) -> f64 {
+/// Which allocation path `build_portfolio` uses to turn ranked stocks into quantities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationStrategy {
+    /// Greedy for small budgets, otherwise concentrated (or proportional
+    /// when `CONCENTRATE_ALLOCATION` is off)
+    Auto,
+    /// Buy the cheapest stocks round-robin
+    Greedy,
+    /// Greedy, but pick the cheapest stock from each distinct sector before
+    /// taking a second stock from any sector
+    SectorGreedy,
+    /// Rank-based quantity table (`RANK_QUANTITIES`)
+    Concentrated,
+    /// Split the budget proportionally to the combined return/points weights
+    Proportional,
+    /// Markowitz weights maximizing return per unit risk, estimated from the
+    /// monthly return series
+    MeanVariance,
+    /// Split the budget proportionally to market capitalization
+    MarketCap,
+    /// Rank by return per unit of volatility instead of raw return, then
+    /// allocate proportionally
+    RiskAdjusted,
+    /// Rank highest dividend yield first, then allocate proportionally;
+    /// falls back to `Auto` when no candidate has yield data
+    Income,
+}
+
+impl AllocationStrategy {
+    /// Resolve `Auto` to a concrete strategy for the given allocation budget
+    pub fn resolve(self, alloc_budget: f64) -> AllocationStrategy {
+        match self {
+            AllocationStrategy::Auto if alloc_budget < GREEDY_BUDGET_THRESHOLD => AllocationStrategy::Greedy,
+            AllocationStrategy::Auto if CONCENTRATE_ALLOCATION => AllocationStrategy::Concentrated,
+            AllocationStrategy::Auto => AllocationStrategy::Proportional,
+            other => other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AllocationStrategy::Auto => "auto",
+            AllocationStrategy::Greedy => "greedy",
+            AllocationStrategy::SectorGreedy => "sector_greedy",
+            AllocationStrategy::Concentrated => "concentrated",
+            AllocationStrategy::Proportional => "proportional",
+            AllocationStrategy::MeanVariance => "mean_variance",
+            AllocationStrategy::MarketCap => "market_cap",
+            AllocationStrategy::RiskAdjusted => "risk_adjusted",
+            AllocationStrategy::Income => "income",
+        }
+    }
+}
+
+/// Which historical return `build_portfolio` ranks and weights stocks on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnBasis {
+    /// Total return over the investment period
+    #[default]
+    Total,
+    /// Compound annual return over the investment period
+    Annualized,
+}
+
+/// One budget sleeve for tiered allocation (see `build_tiered_portfolio`).
+/// Candidates must satisfy every bound that is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Sleeve {
+    pub name: String,
+    /// Fraction of the allocation budget given to this sleeve
+    pub budget_fraction: f64,
+    #[serde(default)]
+    pub min_volatility: Option<f64>,
+    #[serde(default)]
+    pub max_volatility: Option<f64>,
+    #[serde(default)]
+    pub min_market_cap: Option<u64>,
+    #[serde(default)]
+    pub max_market_cap: Option<u64>,
+}
+
+impl Sleeve {
+    fn admits(&self, stock: &Stock) -> bool {
+        self.min_volatility.is_none_or(|v| stock.volatility >= v)
+            && self.max_volatility.is_none_or(|v| stock.volatility <= v)
+            && self.min_market_cap.is_none_or(|c| stock.market_cap >= c)
+            && self.max_market_cap.is_none_or(|c| stock.market_cap <= c)
+    }
+}
+
+/// How learned `PointsStore` scores are transformed before being normalized
+/// into weights. Frequently-submitted tickers accumulate scores orders of
+/// magnitude above rarely-submitted ones, so `Raw` can over-concentrate.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PointsTransform {
+    /// Normalize raw scores directly (legacy behaviour)
+    Raw,
+    /// Softmax over scores scaled by the maximum score; higher temperature
+    /// flattens the distribution further
+    Softmax { temperature: f64 },
+    /// Weight by rank only (best score gets n, worst gets 1)
+    Rank,
+}
+
+/// How `force_within_budget` brings an over-budget portfolio back under
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReductionPolicy {
+    /// Take shares from the position with the most shares first
+    #[default]
+    MostShares,
+    /// Take shares from the highest-priced position first
+    MostExpensive,
+    /// Scale every quantity down by the same factor, keeping the shape
+    Proportional,
+}
+
+/// Volatility boundaries between the low, medium and high bands. Conservative
+/// clients only get stocks below `low`, moderate clients below `medium`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct VolThresholds {
+    pub low: f64,
+    pub medium: f64,
+}
+
+impl Default for VolThresholds {
+    fn default() -> Self {
+        VolThresholds { low: 0.03, medium: 0.05 }
+    }
+}
+
+/// Names returned by `VolThresholds::bucket`, lowest volatility first
+pub const VOL_BUCKETS: [&str; 3] = ["low", "medium", "high"];
+
+impl VolThresholds {
+    /// Volatility band `volatility` falls in: "low", "medium" or "high"
+    pub fn bucket(&self, volatility: f64) -> &'static str {
+        if volatility < self.low {
+            VOL_BUCKETS[0]
+        } else if volatility < self.medium {
+            VOL_BUCKETS[1]
+        } else {
+            VOL_BUCKETS[2]
+        }
+    }
+}
+
+/// Caller-selectable portfolio construction settings. Any field may be
+/// overridden from `portfolio_config.json` (see `PortfolioConfig::load`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PortfolioConfig {
+    pub strategy: AllocationStrategy,
+    pub points_transform: PointsTransform,
+    /// Hard cap on number of distinct positions in any portfolio
+    pub max_positions: usize,
+    /// Weight given to historical return in the return/points blend; learned
+    /// points get the remaining `1 - return_weight`
+    pub return_weight: f64,
+    /// Fraction of the provided budget the allocator may spend
+    pub budget_spend_fraction: f64,
+    /// Share ladder for concentrated allocation (index 0 = top performer).
+    /// Loaded separately from `rank_quantities.json`.
+    #[serde(skip)]
+    pub rank_quantities: Vec<i32>,
+    /// Maximum fraction of the allocation budget any single sector may take
+    pub max_sector_fraction: f64,
+    /// Tighter caps for named sectors (e.g. `{"Technology": 0.2}`); briefs
+    /// add their own per request (see `with_sector_caps`)
+    pub sector_caps: HashMap<String, f64>,
+    /// Skip candidates whose monthly-return correlation with an already
+    /// selected holding exceeds this (None disables the check)
+    pub max_correlation: Option<f64>,
+    /// Deploy leftover cash as fractional shares (see `fractionalize`). Off by
+    /// default since the evaluator may reject non-integer quantities.
+    pub fractional_shares: bool,
+    /// Size aggressive portfolios with fractional Kelly (see `kelly_fraction`)
+    pub kelly_sizing: bool,
+    /// Multiplier applied to the full-Kelly fraction (0.5 = half-Kelly)
+    pub kelly_multiplier: f64,
+    /// Positions worth less than this fraction of the allocation budget are
+    /// dropped as dust and their cash redeployed
+    pub min_position_fraction: f64,
+    /// When set, top up portfolios whose spend/allocation-budget ratio falls
+    /// below this with single shares (see `top_up_utilization`)
+    pub target_utilization: Option<f64>,
+    /// When set, the pre-submit validator caps any single ticker's value at
+    /// this fraction of the portfolio and redeploys the excess
+    pub max_ticker_fraction: Option<f64>,
+    /// Price used to size positions; `historical_start` prices a backtest at
+    /// the start of the investment period
+    pub pricing_basis: PricingBasis,
+    /// Seed for breaking ties between equally ranked stocks, so identical
+    /// inputs always give identical portfolios (None keeps input order)
+    pub tie_break_seed: Option<u64>,
+    /// Rank on total or annualized return (needs the period length)
+    pub return_basis: ReturnBasis,
+    /// Split the allocation budget into sleeves (e.g. 70% low-vol large-cap,
+    /// 30% growth); empty allocates the whole budget as one pool
+    pub sleeves: Vec<Sleeve>,
+    /// How loosely excluded sectors match stocks; `exact` avoids dropping
+    /// names that merely contain a sector word
+    pub exclusion_strictness: ExclusionStrictness,
+    /// Bounds on any single position's share of proportional weights, applied
+    /// after normalization so one outlier cannot take the whole budget
+    pub min_position_weight: f64,
+    pub max_position_weight: f64,
+    /// Fraction of the budget the pre-submit validator keeps unspent as a
+    /// safety margin, in [0, 0.2]; `SUBMIT_MARGIN` in the environment overrides
+    pub submit_margin: f64,
+    /// Drop stocks whose peak-to-trough decline during the period exceeded
+    /// this percentage, for conservative and moderate clients (None disables)
+    pub max_drawdown: Option<f64>,
+    /// Volatility bands used by the risk-tolerance filter
+    pub vol_thresholds: VolThresholds,
+    /// Rank by market cap instead of volatility when no stock has a
+    /// historical return (e.g. no cache and the price API unreachable)
+    pub market_cap_fallback: bool,
+    /// Annual dividend income the portfolio should generate; set per request
+    /// from the brief (see `with_income_target`)
+    #[serde(skip)]
+    pub income_target: Option<f64>,
+    /// Which shares the emergency budget fix removes first
+    pub reduction_policy: ReductionPolicy,
+    /// Wrap submissions in a `{"portfolio": [...], "meta": {...}}` envelope.
+    /// Only enable this against evaluators that accept the envelope; the
+    /// default is the bare array the challenge server expects.
+    pub include_meta: bool,
+    /// Investment horizon assumed when a brief gives an end year but no
+    /// start year
+    pub default_horizon_years: u32,
+    /// Exclude stocks whose monthly series has an implausible single-month
+    /// jump (outside `price_gap_bounds`) during the investment period
+    pub filter_price_gaps: bool,
+    pub price_gap_bounds: PriceGapBounds,
+    /// The pre-submit validator shrinks every position proportionally (down
+    /// to 1 share each) before it eliminates any position to fit the budget
+    pub preserve_positions_on_trim: bool,
+    /// Tickers rejected by the evaluator are excluded for this many days,
+    /// then retried
+    pub rejected_ticker_ttl_days: i64,
+    /// Seconds allowed from fetching a context to having a portfolio ready
+    /// to submit; later work is skipped rather than submitted stale
+    pub request_deadline_secs: u64,
+    /// Contexts requested per fetch; above 1 asks the server for a batch and
+    /// falls back to single contexts if it doesn't support that
+    pub context_batch_size: usize,
+    /// Contexts planned concurrently ahead of the one being submitted;
+    /// submissions themselves stay serial and in arrival order
+    pub planning_workers: usize,
+    /// Contexts whose parsed profile matches one processed within this many
+    /// seconds are skipped as re-served duplicates
+    pub duplicate_context_window_secs: u64,
+    /// Most recent contexts remembered for the duplicate check
+    pub duplicate_context_capacity: usize,
+}
+
+impl Default for PortfolioConfig {
+    fn default() -> Self {
+        PortfolioConfig {
+            strategy: AllocationStrategy::Auto,
+            points_transform: PointsTransform::Raw,
+            max_positions: MAX_POSITIONS,
+            return_weight: RETURN_WEIGHT,
+            budget_spend_fraction: BUDGET_SPEND_FRACTION,
+            rank_quantities: RANK_QUANTITIES.to_vec(),
+            max_sector_fraction: 0.4,
+            sector_caps: HashMap::new(),
+            max_correlation: Some(0.85),
+            fractional_shares: false,
+            kelly_sizing: false,
+            kelly_multiplier: 0.5,
+            min_position_fraction: 0.01,
+            target_utilization: None,
+            max_ticker_fraction: None,
+            pricing_basis: PricingBasis::Current,
+            tie_break_seed: None,
+            return_basis: ReturnBasis::Total,
+            sleeves: Vec::new(),
+            exclusion_strictness: ExclusionStrictness::Synonym,
+            min_position_weight: 0.0,
+            max_position_weight: 1.0,
+            submit_margin: SUBMIT_MARGIN,
+            max_drawdown: None,
+            vol_thresholds: VolThresholds::default(),
+            market_cap_fallback: true,
+            income_target: None,
+            reduction_policy: ReductionPolicy::MostShares,
+            include_meta: false,
+            default_horizon_years: DEFAULT_HORIZON_YEARS,
+            filter_price_gaps: true,
+            price_gap_bounds: PriceGapBounds::default(),
+            preserve_positions_on_trim: true,
+            rejected_ticker_ttl_days: 7,
+            request_deadline_secs: 20,
+            context_batch_size: 1,
+            planning_workers: 1,
+            duplicate_context_window_secs: 600,
+            duplicate_context_capacity: 64,
+        }
+    }
+}
+
+impl PortfolioConfig {
+    /// Budget the allocator may spend: the risk level's equity fraction of
+    /// `budget_spend_fraction` of the client's budget
+    pub fn alloc_budget(&self, budget: f64, risk_level: RiskLevel) -> f64 {
+        budget * self.budget_spend_fraction * risk_level.equity_fraction()
+    }
+
+    /// Bounds for the price-gap data-quality check, if enabled
+    pub fn gap_bounds(&self) -> Option<PriceGapBounds> {
+        self.filter_price_gaps.then_some(self.price_gap_bounds)
+    }
+
+    /// Copy of this config with `caps` added to `sector_caps`, keeping the
+    /// tighter cap where a sector appears in both
+    pub fn with_sector_caps(&self, caps: &HashMap<String, f64>) -> Self {
+        let mut config = self.clone();
+        for (sector, &cap) in caps {
+            config.sector_caps.entry(sector.clone())
+                .and_modify(|c| *c = c.min(cap))
+                .or_insert(cap);
+        }
+        config
+    }
+
+    /// Copy of this config that allocates for dividend income, aiming for
+    /// `annual_income` dollars a year
+    pub fn with_income_target(&self, annual_income: f64) -> Self {
+        PortfolioConfig {
+            strategy: AllocationStrategy::Income,
+            income_target: Some(annual_income),
+            ..self.clone()
+        }
+    }
+
+    /// Load settings from `path`, falling back to defaults for missing
+    /// fields or when the file is absent/invalid.
+    pub fn load(path: &str) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str::<PortfolioConfig>(&contents) {
+                Ok(config) => {
+                    info!("[CONFIG] Loaded portfolio config from {}", path);
+                    config
+                }
+                Err(e) => {
+                    warn!("Could not parse portfolio config '{}': {} - using defaults", path, e);
+                    PortfolioConfig::default()
+                }
+            },
+            Err(_) => PortfolioConfig::default(),
+        }
+    }
+
+    /// Apply a `SUBMIT_MARGIN` environment override (if any) and clamp the
+    /// margin to `[0, MAX_SUBMIT_MARGIN]`, logging the effective value
+    pub fn with_submit_margin_override(mut self, env: Option<String>) -> Self {
+        if let Some(raw) = env {
+            match raw.trim().parse::<f64>() {
+                Ok(margin) => self.submit_margin = margin,
+                Err(_) => warn!("[CONFIG] Ignoring unparseable SUBMIT_MARGIN '{}'", raw),
+            }
+        }
+        let requested = self.submit_margin;
+        self.submit_margin = if requested.is_nan() { SUBMIT_MARGIN } else { requested.clamp(0.0, MAX_SUBMIT_MARGIN) };
+        if self.submit_margin != requested {
+            warn!("[CONFIG] Submit margin {} outside [0, {}] - using {}", requested, MAX_SUBMIT_MARGIN, self.submit_margin);
+        }
+        info!("[CONFIG] Submit margin: {:.1}%", self.submit_margin * 100.0);
+        self
+    }
+}
+
+/// Load the concentrated-allocation share ladder from `path` (a JSON array of
+/// positive integers). Falls back to the built-in `RANK_QUANTITIES` when the
+/// file is missing or invalid.
+pub fn load_rank_quantities(path: &str) -> Vec<i32> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(_) => {
+            info!("[CONFIG] Using built-in rank quantities (no {})", path);
+            return RANK_QUANTITIES.to_vec();
+        }
+    };
+
+    match serde_json::from_str::<Vec<i32>>(&contents) {
+        Ok(ladder) if !ladder.is_empty() && ladder.iter().all(|q| *q > 0) => {
+            info!("[CONFIG] Loaded rank quantities from {}: {:?}", path, ladder);
+            ladder
+        }
+        Ok(ladder) => {
+            warn!("Rank quantities in '{}' must be a non-empty list of positive integers (got {:?}) - using built-in defaults", path, ladder);
+            RANK_QUANTITIES.to_vec()
+        }
+        Err(e) => {
+            warn!("Could not parse rank quantities file '{}': {} - using built-in defaults", path, e);
+            RANK_QUANTITIES.to_vec()
+        }
+    }
+}
+
+/// Calculate the total cost of a portfolio under `basis`. Budget guards use
+/// `PricingBasis::Current` so they match the server's evaluation basis.
+fn calculate_portfolio_cost(portfolio: &[(String, i32)], stocks: &[Stock], basis: PricingBasis) -> f64 {
     portfolio.iter()
         .map(|(ticker, qty)| {
             let stock = stocks.iter().find(|s| &s.ticker == ticker);
             if let Some(s) = stock {
-                // Use current market price when calculating total cost so it
-                // matches the server's evaluation basis (submission uses current prices)
-                s.get_current_price() * (*qty as f64)
+                s.price_for(basis) * (*qty as f64)
             } else {
                 0.0
             }
@@ -43,37 +465,106 @@ fn calculate_portfolio_cost(portfolio: &[(String, i32)], stocks: &[Stock]) -> f6
 /// Validate that portfolio does not exceed budget
 /// Returns true if valid, false if over budget
 fn validate_budget(portfolio: &[(String, i32)], stocks: &[Stock], budget: f64) -> bool {
-    let total_cost = calculate_portfolio_cost(portfolio, stocks);
+    let total_cost = calculate_portfolio_cost(portfolio, stocks, PricingBasis::Current);
     let is_valid = total_cost <= budget;
     
     if !is_valid {
-        eprintln!("[ERROR] Portfolio exceeds budget!");
-        eprintln!("  Budget: ${:.2}", budget);
-        eprintln!("  Portfolio cost: ${:.2}", total_cost);
-        eprintln!("  Over by: ${:.2}", total_cost - budget);
+        error!("Portfolio exceeds budget!");
+        error!("  Budget: ${:.2}", budget);
+        error!("  Portfolio cost: ${:.2}", total_cost);
+        error!("  Over by: ${:.2}", total_cost - budget);
     }
     
     is_valid
 }
 
-/// Emergency budget fix: Remove shares until under budget
-fn force_within_budget(portfolio: &mut Vec<(String, i32)>, stocks: &[Stock], budget: f64) {
-    while calculate_portfolio_cost(portfolio, stocks) > budget {
-        // Find the position with the most shares
-        if let Some((idx, _)) = portfolio.iter().enumerate()
-            .max_by_key(|(_, (_, qty))| *qty) {
-            
-            // Reduce by 1 share
-            portfolio[idx].1 -= 1;
-            
-            // Remove position if quantity is 0
-            if portfolio[idx].1 == 0 {
-                portfolio.remove(idx);
+/// Emergency budget fix: Remove shares until under budget. Returns the
+/// number of reduction steps taken.
+fn force_within_budget(portfolio: &mut Vec<(String, i32)>, stocks: &[Stock], budget: f64, policy: ReductionPolicy) -> usize {
+    let price_of = |ticker: &str| stocks.iter().find(|s| s.ticker == ticker).map(|s| s.get_current_price()).unwrap_or(0.0);
+    let mut steps = 0;
+    let cost = calculate_portfolio_cost(portfolio, stocks, PricingBasis::Current);
+    if policy == ReductionPolicy::Proportional && cost > budget && cost > 0.0 {
+        // Rounding can leave a small overshoot; the most-shares pass
+        // below closes it
+        let factor = budget / cost;
+        for (_, qty) in portfolio.iter_mut() {
+            *qty = (*qty as f64 * factor).round() as i32;
+        }
+        portfolio.retain(|(_, qty)| *qty > 0);
+        steps += 1;
+    }
+    if policy == ReductionPolicy::MostShares && cost > budget && budget >= 0.0 {
+        // Taking shares from the largest count first levels the largest
+        // positions down to a common count. Binary search the highest level
+        // that fits instead of walking down one share at a time.
+        let prices: Vec<f64> = portfolio.iter().map(|(t, _)| price_of(t).max(0.0)).collect();
+        let cost_at = |level: i32| -> f64 {
+            portfolio.iter().zip(&prices).map(|((_, q), p)| p * (*q).min(level) as f64).sum()
+        };
+        let (mut fits, mut over) = (0, portfolio.iter().map(|(_, q)| *q).max().unwrap_or(0));
+        while over - fits > 1 {
+            let mid = fits + (over - fits) / 2;
+            if cost_at(mid) <= budget { fits = mid } else { over = mid }
+            steps += 1;
+        }
+        // Positions cut to the level take back one share each while it fits
+        let mut spent = cost_at(fits);
+        for ((_, qty), price) in portfolio.iter_mut().zip(&prices) {
+            if *qty > fits {
+                *qty = fits;
+                if spent + price <= budget {
+                    *qty += 1;
+                    spent += price;
+                }
             }
+        }
+        portfolio.retain(|(_, qty)| *qty > 0);
+    }
+    loop {
+        let cost = calculate_portfolio_cost(portfolio, stocks, PricingBasis::Current);
+        if cost <= budget {
+            break;
+        }
+        steps += 1;
+        // Find the position to trim: the most expensive, or the one with
+        // the most shares
+        let picked = if policy == ReductionPolicy::MostExpensive {
+            portfolio.iter().enumerate().max_by(|(_, (a, _)), (_, (b, _))| cmp_f64(price_of(a), price_of(b)))
         } else {
+            portfolio.iter().enumerate().max_by_key(|(_, (_, qty))| *qty)
+        };
+        let Some((idx, _)) = picked else {
             break; // Portfolio is empty
+        };
+        let price = price_of(&portfolio[idx].0);
+        if price <= 0.0 {
+            portfolio.remove(idx);
+            continue;
+        }
+
+        // Drop enough shares to close the gap in one step. When trimming by
+        // share count, go no further than the next-largest position so
+        // reductions stay spread out
+        let qty = portfolio[idx].1;
+        let floor = if policy == ReductionPolicy::MostExpensive {
+            0
+        } else {
+            portfolio.iter().enumerate()
+                .filter(|(i, _)| *i != idx)
+                .map(|(_, (_, q))| *q)
+                .max()
+                .unwrap_or(0)
+        };
+        let needed = ((cost - budget) / price).ceil() as i32;
+        portfolio[idx].1 -= needed.min(qty - floor).max(1);
+
+        // Remove position if quantity is 0
+        if portfolio[idx].1 <= 0 {
+            portfolio.remove(idx);
         }
     }
+    steps
 }
 
 /// Get the first trading year for a ticker from hardcoded database
@@ -147,12 +638,51 @@ fn is_ticker_excluded(ticker: &str) -> bool {
     EXCLUDED_TICKERS.contains(&ticker)
 }
 
+/// Optional whitelist: when this file exists only the tickers it lists
+/// (one per line) are eligible
+const UNIVERSE_PATH: &str = "universe.txt";
+
+static UNIVERSE: OnceLock<Option<HashSet<String>>> = OnceLock::new();
+
+/// Read a ticker whitelist; `None` if the file is missing or lists nothing
+fn load_universe(path: &str) -> Option<HashSet<String>> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let tickers: HashSet<String> = contents
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_ascii_uppercase())
+        .collect();
+    if tickers.is_empty() {
+        warn!("[FILTER] {} is empty; ignoring it", path);
+        return None;
+    }
+    info!("[FILTER] Restricting universe to {} tickers from {}", tickers.len(), path);
+    Some(tickers)
+}
+
+/// Check if ticker is allowed by the whitelist (always true without one)
+fn in_universe(ticker: &str, universe: Option<&HashSet<String>>) -> bool {
+    universe.is_none_or(|u| u.contains(&ticker.to_ascii_uppercase()))
+}
+
 /// Check if stock volatility matches risk tolerance
-fn matches_risk_tolerance(volatility: f64, risk_level: RiskLevel) -> bool {
+fn matches_risk_tolerance(volatility: f64, risk_level: RiskLevel, thresholds: VolThresholds) -> bool {
     match risk_level {
-        RiskLevel::Conservative => volatility < 0.03, // Low volatility only
-        RiskLevel::Moderate => volatility < 0.05,     // Medium volatility
-        RiskLevel::Aggressive => true,                // All stocks acceptable
+        RiskLevel::Conservative => volatility < thresholds.low,   // Low volatility only
+        RiskLevel::Moderate => volatility < thresholds.medium,    // Medium volatility
+        RiskLevel::Aggressive => true,                            // All stocks acceptable
+    }
+}
+
+/// Check a conservative/moderate client's stock did not fall more than
+/// `limit` percent from a peak during the period. Stocks without monthly
+/// data (no drawdown) and aggressive clients always pass.
+fn within_drawdown_limit(stock: &Stock, risk_level: RiskLevel, limit: Option<f64>) -> bool {
+    match (risk_level, limit, stock.max_drawdown) {
+        (RiskLevel::Aggressive, _, _) => true,
+        (_, Some(limit), Some(drawdown)) => drawdown <= limit,
+        _ => true,
     }
 }
 
@@ -181,95 +711,520 @@ fn was_trading_during_period(stock: &Stock, start_year: Option<u32>) -> bool {
 }
 
 /// Filter stocks based on investor profile requirements
-pub fn filter_stocks_by_profile(stocks: &[Stock], profile: &InvestorProfile) -> Vec<Stock> {
+pub fn filter_stocks_by_profile(stocks: &[Stock], profile: &InvestorProfile, config: &PortfolioConfig) -> Vec<Stock> {
+    let universe = UNIVERSE.get_or_init(|| load_universe(UNIVERSE_PATH)).as_ref();
+    filter_stocks_within(stocks, profile, config, universe)
+}
+
+/// `filter_stocks_by_profile` against an explicit whitelist
+fn filter_stocks_within(
+    stocks: &[Stock],
+    profile: &InvestorProfile,
+    config: &PortfolioConfig,
+    universe: Option<&HashSet<String>>,
+) -> Vec<Stock> {
+    let invalid_prices = stocks.iter().filter(|s| !s.has_valid_price()).count();
+    if invalid_prices > 0 {
+        warn!("[FILTER] Dropping {} stocks with a missing or invalid current price", invalid_prices);
+    }
     stocks
         .iter()
+        .filter(|s| in_universe(&s.ticker, universe))
         .filter(|s| !is_ticker_excluded(&s.ticker))
+        .filter(|s| !s.price_gap_flagged)
+        // Companies the client named explicitly
+        .filter(|s| !profile.excluded_tickers.iter().any(|t| t.eq_ignore_ascii_case(&s.ticker)))
+        // Bad cache/API data can leave a NaN or non-positive price
+        .filter(|s| s.has_valid_price())
     // Extended exclusion: checks sector and stock name with synonyms
-    .filter(|s| !profile.should_exclude_sector_extended(&s.sector, &s.name))
-        .filter(|s| matches_risk_tolerance(s.volatility, profile.risk_tolerance))
+    .filter(|s| !profile.should_exclude_sector_extended(&s.sector, &s.name, config.exclusion_strictness))
+        .filter(|s| matches_risk_tolerance(s.volatility, profile.risk_tolerance, config.vol_thresholds))
+        .filter(|s| within_drawdown_limit(s, profile.risk_tolerance, config.max_drawdown))
         .filter(|s| was_trading_during_period(s, profile.start_year))
         .cloned()
         .collect()
 }
 
-pub fn build_portfolio(stocks: &[Stock], budget: f64, risk_level: RiskLevel) -> Vec<(String, i32)> {
-    if stocks.is_empty() {
-        return Vec::new();
+/// Total ordering for f64 sorts; NaN compares greater than every number so
+/// it sorts last in ascending order instead of panicking
+pub fn cmp_f64(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
     }
-    
-    // SAFETY CHECK: Validate budget is positive
-    if budget <= 0.0 {
-        eprintln!("[ERROR] Invalid budget: ${:.2}", budget);
-        return Vec::new();
+}
+
+/// Annualized percentage return for a total percentage return over `years`:
+/// `(1 + total/100)^(1/years) - 1`, as a percentage
+pub fn annualized_return(total_pct: f64, years: f64) -> f64 {
+    let growth = 1.0 + total_pct / 100.0;
+    if growth <= 0.0 {
+        return -100.0;
     }
-    
-    // Sort by historical return if available, otherwise by inverse volatility
+    (growth.powf(1.0 / years) - 1.0) * 100.0
+}
+
+/// 64-bit FNV-1a over `parts`, each prefixed with its length so part
+/// boundaries matter. Unlike `DefaultHasher` the output is fixed across
+/// Rust releases, so it is safe for persisted or reproducible keys.
+pub fn fnv1a(parts: &[&[u8]]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash = OFFSET_BASIS;
+    for part in parts {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+/// Seeded tie-break key for a ticker, stable across runs and toolchains for
+/// the same seed
+fn tie_break_key(seed: u64, ticker: &str) -> u64 {
+    fnv1a(&[&seed.to_le_bytes(), ticker.as_bytes()])
+}
+
+/// Rank stocks by historical return (highest first), or by return per unit
+/// of volatility for `RiskAdjusted` (for `Income`, by dividend yield first);
+/// stocks without a return come last,
+/// ordered by volatility (lowest first) - or by market cap (largest first)
+/// when no stock has a return and `market_cap_fallback` is set. With a
+/// seed, ties are broken by a seeded shuffle of tickers instead of input order.
+fn rank_stocks(stocks: &[Stock], config: &PortfolioConfig) -> Vec<Stock> {
+    let tie_break_seed = config.tie_break_seed;
+    let risk_adjusted = config.strategy == AllocationStrategy::RiskAdjusted;
+    let by_market_cap = config.market_cap_fallback && stocks.iter().all(|s| s.historical_return.is_none());
+    let by_yield = config.strategy == AllocationStrategy::Income && stocks.iter().any(|s| s.dividend_yield.is_some());
+    let score = |s: &Stock, ret: f64| if risk_adjusted { ret / (s.volatility.max(0.0) + RISK_ADJUST_EPSILON) } else { ret };
     let mut sorted_stocks = stocks.to_vec();
     sorted_stocks.sort_by(|a, b| {
+        let tie_break = |ordering: Ordering| match (ordering, tie_break_seed) {
+            (Ordering::Equal, Some(seed)) => tie_break_key(seed, &a.ticker)
+                .cmp(&tie_break_key(seed, &b.ticker))
+                .then_with(|| a.ticker.cmp(&b.ticker)),
+            (ordering, _) => ordering,
+        };
+        // Income mode: yield first (highest first, unknown yields last)
+        if by_yield {
+            match (a.dividend_yield, b.dividend_yield) {
+                (Some(ya), Some(yb)) if ya != yb => return cmp_f64(yb, ya),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                _ => {}
+            }
+        }
         // If both have historical returns, sort by return (highest first)
-        match (a.historical_return, b.historical_return) {
-            (Some(ret_a), Some(ret_b)) => ret_a.partial_cmp(&ret_b).unwrap().reverse(), // Descending (highest first)
-            (Some(_), None) => std::cmp::Ordering::Less,  // Stocks with returns first
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            (None, None) => a.volatility.partial_cmp(&b.volatility).unwrap(), // Fallback to volatility (lowest first)
+        // A NaN return is treated like a missing one
+        let ret_a = a.historical_return.filter(|r| !r.is_nan());
+        let ret_b = b.historical_return.filter(|r| !r.is_nan());
+        match (ret_a, ret_b) {
+            (Some(ret_a), Some(ret_b)) => tie_break(cmp_f64(score(b, ret_b), score(a, ret_a))), // Descending (highest first)
+            (Some(_), None) => Ordering::Less,  // Stocks with returns first
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) if by_market_cap => tie_break(b.market_cap.cmp(&a.market_cap)), // No history at all: largest first
+            (None, None) => tie_break(cmp_f64(a.volatility, b.volatility)), // Fallback to volatility (lowest first)
         }
     });
+    sorted_stocks
+}
+
+/// Planned positions plus how they were chosen, for logging and traces
+#[derive(Debug, Serialize)]
+pub struct PortfolioPlan {
+    #[serde(skip)]
+    positions: Vec<(String, i32)>,
+    /// Allocation path that produced the positions ("kelly", "tiered" or
+    /// an `AllocationStrategy` name)
+    pub strategy: &'static str,
+    pub rationale: Vec<PositionRationale>,
+}
+
+/// Why one position is in the plan
+#[derive(Debug, Serialize)]
+pub struct PositionRationale {
+    pub ticker: String,
+    pub quantity: i32,
+    /// Share of the plan's cost at current prices
+    pub weight: f64,
+    /// Historical return % over the investment period, if known
+    pub expected_return: Option<f64>,
+}
+
+impl PortfolioPlan {
+    pub fn positions(&self) -> &[(String, i32)] {
+        &self.positions
+    }
+}
+
+pub fn build_portfolio(
+    stocks: &[Stock],
+    budget: f64,
+    risk_level: RiskLevel,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+    period_years: Option<f64>,
+    requested_positions: Option<usize>,
+) -> PortfolioPlan {
+    let (positions, strategy) = build_positions(stocks, budget, risk_level, config, points, period_years, requested_positions);
+    let total_cost = calculate_portfolio_cost(&positions, stocks, PricingBasis::Current);
+    let rationale = positions.iter().map(|(ticker, quantity)| {
+        let stock = stocks.iter().find(|s| &s.ticker == ticker);
+        let value = stock.map(|s| s.get_current_price() * *quantity as f64).unwrap_or(0.0);
+        PositionRationale {
+            ticker: ticker.clone(),
+            quantity: *quantity,
+            weight: if total_cost > 0.0 { value / total_cost } else { 0.0 },
+            expected_return: stock.and_then(|s| s.historical_return),
+        }
+    }).collect();
+    PortfolioPlan { positions, strategy, rationale }
+}
+
+/// Positions for `build_portfolio`, with the name of the allocation path used
+fn build_positions(
+    stocks: &[Stock],
+    budget: f64,
+    risk_level: RiskLevel,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+    period_years: Option<f64>,
+    requested_positions: Option<usize>,
+) -> (Vec<(String, i32)>, &'static str) {
+    if stocks.is_empty() {
+        return (Vec::new(), "none");
+    }
     
-    // Target number of positions based on risk tolerance
-    let target_positions = match risk_level {
-        RiskLevel::Conservative => 15, // More diversification
-        RiskLevel::Moderate => 10,
-        RiskLevel::Aggressive => 7,    // More concentrated
+    // SAFETY CHECK: Validate budget is positive
+    if budget <= 0.0 {
+        error!("Invalid budget: ${:.2}", budget);
+        return (Vec::new(), "none");
+    }
+
+    // Optionally rank and weight on annualized rather than total return
+    let annualized: Vec<Stock>;
+    let stocks = match (config.return_basis, period_years) {
+        (ReturnBasis::Annualized, Some(years)) if years > 0.0 => {
+            annualized = stocks.iter().cloned().map(|mut s| {
+                s.historical_return = s.historical_return.map(|r| annualized_return(r, years));
+                s
+            }).collect();
+            &annualized[..]
+        }
+        _ => stocks,
+    };
+    
+    // Sort by historical return (risk-adjusted if configured) if available,
+    // otherwise by inverse volatility
+    let mut sorted_stocks = rank_stocks(stocks, config);
+    
+    // Target number of positions: as requested in the brief, otherwise based
+    // on risk tolerance
+    let target_positions = match (requested_positions, risk_level) {
+        (Some(n), _) => n.clamp(1, config.max_positions.min(sorted_stocks.len()).max(1)),
+        (None, RiskLevel::Conservative) => 15, // More diversification
+        (None, RiskLevel::Moderate) => 10,
+        (None, RiskLevel::Aggressive) => 7,    // More concentrated
     };
+    // A requested count is also a hard cap for the greedy and top-up paths
+    let max_positions = if requested_positions.is_some() { target_positions } else { config.max_positions };
+
+    // Prefer less-correlated names among the top picks
+    if let Some(threshold) = config.max_correlation {
+        sorted_stocks = diversify_by_correlation(sorted_stocks, target_positions.min(config.max_positions), threshold);
+    }
     
     // Use a conservative allocation budget fraction so we only spend part of
     // the provided budget (e.g., 70%). This leaves a buffer and reduces
     // risk of budget-breaches and allows some cash to remain unspent.
-    let alloc_budget = budget * BUDGET_SPEND_FRACTION;
+    // Conservative clients additionally hold less equity overall.
+    let alloc_budget = config.alloc_budget(budget, risk_level);
 
-    // For small budgets, use greedy allocation instead of equal weight
-    let portfolio = if alloc_budget < 5000.0 {
-        build_greedy_portfolio(&sorted_stocks, alloc_budget)
+    // Auto uses greedy allocation for small budgets and performance-weighted
+    // allocation for larger ones; explicit strategies are honoured as-is
+    let mut strategy = config.strategy.resolve(alloc_budget);
+    if strategy == AllocationStrategy::Income && !sorted_stocks.iter().any(|s| s.dividend_yield.is_some()) {
+        warn!("[INCOME] No dividend yield data for any eligible stock - using standard allocation");
+        strategy = AllocationStrategy::Auto.resolve(alloc_budget);
+    }
+    let kelly = if config.kelly_sizing && matches!(risk_level, RiskLevel::Aggressive) && !matches!(strategy, AllocationStrategy::Greedy | AllocationStrategy::SectorGreedy) {
+        build_kelly_portfolio(&sorted_stocks, alloc_budget, target_positions, config)
     } else {
-        // Performance-weighted allocation for larger budgets
-        build_weighted_portfolio(&sorted_stocks, alloc_budget, target_positions)
+        None
+    };
+    let (mut portfolio, strategy_used) = match (kelly, strategy) {
+        (Some(kelly_portfolio), _) => (kelly_portfolio, "kelly"),
+        (None, _) if !config.sleeves.is_empty() => (build_tiered_portfolio(&sorted_stocks, alloc_budget, target_positions, strategy, config, points), "tiered"),
+        (None, AllocationStrategy::Greedy) => (build_greedy_portfolio(&sorted_stocks, alloc_budget, max_positions, false, config.pricing_basis, config.reduction_policy), strategy.name()),
+        (None, AllocationStrategy::SectorGreedy) => (build_greedy_portfolio(&sorted_stocks, alloc_budget, max_positions, true, config.pricing_basis, config.reduction_policy), strategy.name()),
+        (None, AllocationStrategy::MeanVariance) => (build_mean_variance_portfolio(&sorted_stocks, alloc_budget, target_positions, config), strategy.name()),
+        (None, _) => (build_weighted_portfolio(&sorted_stocks, alloc_budget, target_positions, strategy, config, points), strategy.name()),
     };
+
+    // Consolidate dust positions into the best-ranked remaining holding
+    drop_dust_positions(&mut portfolio, &sorted_stocks, alloc_budget, config.min_position_fraction);
+
+    if let Some(target) = config.target_utilization {
+        top_up_utilization(&mut portfolio, &sorted_stocks, alloc_budget, target, max_positions);
+    }
+
+    if let (AllocationStrategy::Income, Some(target)) = (strategy, config.income_target) {
+        let income: f64 = portfolio.iter()
+            .filter_map(|(ticker, qty)| {
+                let s = stocks.iter().find(|s| &s.ticker == ticker)?;
+                Some(s.get_current_price() * *qty as f64 * s.dividend_yield?)
+            })
+            .sum();
+        if income < target {
+            warn!("[INCOME] Projected dividends ${:.2}/year fall short of the ${:.2} target", income, target);
+        } else {
+            info!("[INCOME] Projected dividends ${:.2}/year (target ${:.2})", income, target);
+        }
+    }
     
-    // Defensive trim: ensure we never return more than MAX_POSITIONS distinct tickers.
+    // Defensive trim: ensure we never return more than max_positions distinct tickers.
     // This is an extra safety net in case other allocation paths produce more entries.
-    if portfolio.len() > MAX_POSITIONS {
-        eprintln!("[VALIDATOR] Trimming portfolio from {} to {} positions (MAX_POSITIONS)", portfolio.len(), MAX_POSITIONS);
-        // Sort by historical return (highest first) using the stocks metadata, then keep top MAX_POSITIONS
+    if portfolio.len() > max_positions {
+        warn!("[VALIDATOR] Trimming portfolio from {} to {} positions (max_positions)", portfolio.len(), max_positions);
+        // Sort by historical return (highest first) using the stocks metadata, then keep top max_positions
         let mut portfolio_sorted = portfolio.clone();
         portfolio_sorted.sort_by(|(t1, _), (t2, _)| {
-            let r1 = stocks.iter().find(|s| &s.ticker == t1).and_then(|s| s.historical_return).unwrap_or(0.0);
-            let r2 = stocks.iter().find(|s| &s.ticker == t2).and_then(|s| s.historical_return).unwrap_or(0.0);
-            r2.partial_cmp(&r1).unwrap_or(std::cmp::Ordering::Equal)
+            let r1 = stocks.iter().find(|s| &s.ticker == t1).and_then(|s| s.historical_return).filter(|r| !r.is_nan()).unwrap_or(0.0);
+            let r2 = stocks.iter().find(|s| &s.ticker == t2).and_then(|s| s.historical_return).filter(|r| !r.is_nan()).unwrap_or(0.0);
+            cmp_f64(r2, r1)
         });
-        let mut trimmed = portfolio_sorted.into_iter().take(MAX_POSITIONS).collect::<Vec<_>>();
+        let mut trimmed = portfolio_sorted.into_iter().take(max_positions).collect::<Vec<_>>();
         // Final safety: ensure trimmed portfolio is within budget (force trim if necessary)
         if !validate_budget(&trimmed, stocks, budget) {
-            force_within_budget(&mut trimmed, stocks, budget);
+            force_within_budget(&mut trimmed, stocks, budget, config.reduction_policy);
         }
-        return trimmed;
+        return (trimmed, strategy_used);
     }
     
     // ABSOLUTE FINAL SAFETY CHECK
-    let total_cost = calculate_portfolio_cost(&portfolio, stocks);
+    let total_cost = calculate_portfolio_cost(&portfolio, stocks, PricingBasis::Current);
     if total_cost > budget {
-        eprintln!("[CRITICAL ERROR] Portfolio cost ${:.2} exceeds budget ${:.2}!", total_cost, budget);
-        eprintln!("[CRITICAL ERROR] This should never happen - contact developer!");
+        error!("Portfolio cost ${:.2} exceeds budget ${:.2}!", total_cost, budget);
+        error!("This should never happen - contact developer!");
         let mut fixed_portfolio = portfolio;
-        force_within_budget(&mut fixed_portfolio, stocks, budget);
-        return fixed_portfolio;
+        force_within_budget(&mut fixed_portfolio, stocks, budget, config.reduction_policy);
+        return (fixed_portfolio, strategy_used);
     }
     
     // Success - log the allocation
-    println!("[BUDGET] Portfolio cost: ${:.2} / ${:.2} (${:.2} remaining)", 
+    info!("[BUDGET] Portfolio cost: ${:.2} / ${:.2} (${:.2} remaining)", 
              total_cost, budget, budget - total_cost);
     
-    portfolio
+    (portfolio, strategy_used)
+}
+
+/// Aggregate risk/return read of a portfolio, weighted by position value
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PortfolioStats {
+    /// Value-weighted historical return % over positions with a known return
+    pub expected_return: Option<f64>,
+    /// Value-weighted average of per-stock volatility (ignores correlation)
+    pub volatility: f64,
+}
+
+/// Value-weighted historical return and volatility of `portfolio` at current prices
+pub fn portfolio_stats<Q: Copy + Into<f64>>(portfolio: &[(String, Q)], stocks: &[Stock]) -> PortfolioStats {
+    let positions: Vec<(&Stock, f64)> = portfolio.iter()
+        .filter_map(|(ticker, qty)| {
+            let stock = stocks.iter().find(|s| &s.ticker == ticker)?;
+            Some((stock, stock.get_current_price() * (*qty).into()))
+        })
+        .filter(|(_, value)| *value > 0.0)
+        .collect();
+    let total: f64 = positions.iter().map(|(_, v)| v).sum();
+    let volatility = if total > 0.0 {
+        positions.iter().map(|(s, v)| s.volatility * v).sum::<f64>() / total
+    } else {
+        0.0
+    };
+    let with_return: Vec<(f64, f64)> = positions.iter()
+        .filter_map(|(s, v)| Some((s.historical_return.filter(|r| r.is_finite())?, *v)))
+        .collect();
+    let return_value: f64 = with_return.iter().map(|(_, v)| v).sum();
+    let expected_return = (return_value > 0.0)
+        .then(|| with_return.iter().map(|(r, v)| r * v).sum::<f64>() / return_value);
+    PortfolioStats { expected_return, volatility }
+}
+
+/// Averages over an eligible universe, logged per request for tuning
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct UniverseFeatures {
+    pub count: usize,
+    pub avg_vol: f64,
+    /// Mean natural log of market cap over stocks that report one
+    pub avg_logcap: Option<f64>,
+    /// Mean learned points score
+    pub avg_pts_score: f64,
+}
+
+/// Summarise `stocks` by volatility, market cap and learned points
+pub fn universe_features(stocks: &[Stock], points: &PointsStore) -> UniverseFeatures {
+    let mean = |sum: f64, n: usize| if n > 0 { sum / n as f64 } else { 0.0 };
+    let caps: Vec<f64> = stocks.iter()
+        .filter(|s| s.market_cap > 0)
+        .map(|s| (s.market_cap as f64).ln())
+        .collect();
+    UniverseFeatures {
+        count: stocks.len(),
+        avg_vol: mean(stocks.iter().map(|s| s.volatility).sum(), stocks.len()),
+        avg_logcap: (!caps.is_empty()).then(|| mean(caps.iter().sum(), caps.len())),
+        avg_pts_score: mean(stocks.iter().map(|s| points.get_score(&s.ticker)).sum(), stocks.len()),
+    }
+}
+
+/// Fraction of `budget` spent by `portfolio` at current prices
+pub fn portfolio_utilization(portfolio: &[(String, i32)], stocks: &[Stock], budget: f64) -> f64 {
+    if budget <= 0.0 {
+        return 0.0;
+    }
+    calculate_portfolio_cost(portfolio, stocks, PricingBasis::Current) / budget
+}
+
+/// While utilization is below `target`, buy one share of the cheapest
+/// affordable positive-return stock. Held stocks are always candidates; new
+/// ones only while there is room under `max_positions`. Never exceeds `budget`.
+fn top_up_utilization(portfolio: &mut Vec<(String, i32)>, stocks: &[Stock], budget: f64, target: f64, max_positions: usize) {
+    let before = portfolio_utilization(portfolio, stocks, budget);
+    let mut spent = calculate_portfolio_cost(portfolio, stocks, PricingBasis::Current);
+
+    while spent / budget < target {
+        let remaining = budget - spent;
+        let has_room = portfolio.len() < max_positions;
+        let pick = stocks.iter()
+            .filter(|s| s.historical_return.is_some_and(|r| r > 0.0))
+            .filter(|s| s.get_current_price() <= remaining)
+            .filter(|s| has_room || portfolio.iter().any(|(t, _)| t == &s.ticker))
+            .min_by(|a, b| cmp_f64(a.get_current_price(), b.get_current_price()));
+        let Some(stock) = pick else { break };
+
+        match portfolio.iter_mut().find(|(t, _)| t == &stock.ticker) {
+            Some((_, qty)) => *qty += 1,
+            None => portfolio.push((stock.ticker.clone(), 1)),
+        }
+        spent += stock.get_current_price();
+    }
+
+    let after = spent / budget;
+    if after > before {
+        info!("[BUDGET] Utilization topped up from {:.1}% to {:.1}%", before * 100.0, after * 100.0);
+    }
+}
+
+/// Reorder ranked `stocks` so the first `slots` entries avoid pairs whose
+/// monthly-return correlation exceeds `threshold`. Candidates skipped for
+/// correlation keep their relative order after the selected ones, so
+/// allocators can still fall back to them.
+fn diversify_by_correlation(stocks: Vec<Stock>, slots: usize, threshold: f64) -> Vec<Stock> {
+    let mut selected: Vec<Stock> = Vec::with_capacity(stocks.len());
+    let mut deferred: Vec<Stock> = Vec::new();
+
+    for stock in stocks {
+        if selected.len() >= slots {
+            deferred.push(stock);
+            continue;
+        }
+        let correlated_with = selected.iter().find(|chosen| {
+            pairwise_correlation(chosen, &stock).is_some_and(|c| c > threshold)
+        });
+        if let Some(chosen) = correlated_with {
+            info!("[DIVERSIFY] Skipping {} - correlation with {} above {:.2}", stock.ticker, chosen.ticker, threshold);
+            deferred.push(stock);
+        } else {
+            selected.push(stock);
+        }
+    }
+
+    selected.extend(deferred);
+    selected
+}
+
+/// Fractional-share mode: top up an integer `portfolio` with fractional
+/// shares so its cost approaches `budget`. Leftover cash is split across held
+/// positions in proportion to their value; if nothing was affordable it is
+/// split evenly across the top-ranked `candidates`. Quantities are rounded
+/// down to 4 decimal places so the total never exceeds `budget`.
+pub fn fractionalize(portfolio: &[(String, i32)], candidates: &[Stock], budget: f64, config: &PortfolioConfig) -> Vec<(String, f64)> {
+    let price_of = |ticker: &str| {
+        candidates.iter().find(|s| s.ticker == ticker).map(|s| s.get_current_price()).unwrap_or(0.0)
+    };
+
+    let mut fractional: Vec<(String, f64)> = portfolio.iter()
+        .filter(|(t, _)| price_of(t) > 0.0)
+        .map(|(t, q)| (t.clone(), *q as f64))
+        .collect();
+    if fractional.is_empty() {
+        fractional = rank_stocks(candidates, config).into_iter()
+            .filter(|s| s.get_current_price() > 0.0)
+            .take(config.max_positions)
+            .map(|s| (s.ticker, 0.0))
+            .collect();
+    }
+    if fractional.is_empty() {
+        return fractional;
+    }
+
+    let spent: f64 = fractional.iter().map(|(t, q)| price_of(t) * q).sum();
+    let leftover = budget - spent;
+    if leftover <= 0.0 {
+        return fractional;
+    }
+
+    let share_of_leftover: Vec<f64> = if spent > 0.0 {
+        fractional.iter().map(|(t, q)| price_of(t) * q / spent).collect()
+    } else {
+        vec![1.0 / fractional.len() as f64; fractional.len()]
+    };
+    for ((ticker, qty), share) in fractional.iter_mut().zip(share_of_leftover) {
+        let extra = leftover * share / price_of(ticker);
+        *qty = ((*qty + extra) * 10_000.0).floor() / 10_000.0;
+    }
+    fractional.retain(|(_, q)| *q > 0.0);
+    fractional
+}
+
+/// Remove positions whose market value is below `min_fraction` of `budget`
+/// and redeploy the freed cash into the highest-ranked remaining position.
+/// `ranked` must be in ranking order. Never pushes the portfolio over budget.
+fn drop_dust_positions(portfolio: &mut Vec<(String, i32)>, ranked: &[Stock], budget: f64, min_fraction: f64) {
+    if min_fraction <= 0.0 || portfolio.len() <= 1 {
+        return;
+    }
+    let threshold = budget * min_fraction;
+    let value_of = |ticker: &str, qty: i32| {
+        ranked.iter().find(|s| s.ticker == ticker).map(|s| s.get_current_price() * qty as f64).unwrap_or(0.0)
+    };
+
+    let mut dust: Vec<String> = portfolio.iter()
+        .filter(|(t, q)| value_of(t, *q) < threshold)
+        .map(|(t, _)| t.clone())
+        .collect();
+    if dust.len() == portfolio.len() {
+        // Keep the largest position rather than emptying the portfolio
+        if let Some((largest, _)) = portfolio.iter().max_by(|a, b| {
+            cmp_f64(value_of(&a.0, a.1), value_of(&b.0, b.1))
+        }) {
+            dust.retain(|t| t != largest);
+        }
+    }
+    if dust.is_empty() {
+        return;
+    }
+
+    portfolio.retain(|(t, _)| !dust.contains(t));
+    info!("[DUST] Dropped {} positions worth less than ${:.2}: {:?}", dust.len(), threshold, dust);
+
+    let remaining = budget - calculate_portfolio_cost(portfolio, ranked, PricingBasis::Current);
+    if let Some(top) = ranked.iter().find(|s| portfolio.iter().any(|(t, _)| *t == s.ticker)) {
+        deploy_remaining_budget(portfolio, remaining, top, budget, PricingBasis::Current);
+    }
 }
 
 /// Calculate performance-based weights for stocks
@@ -292,160 +1247,616 @@ fn calculate_performance_weights(stocks: &[&Stock]) -> Vec<f64> {
     }
 }
 
-/// Build portfolio with performance-weighted allocation
-fn build_weighted_portfolio(stocks: &[Stock], budget: f64, target_positions: usize) -> Vec<(String, i32)> {
-    // Enforce global upper bound on positions
-    let num_positions = target_positions.min(stocks.len()).min(MAX_POSITIONS);
-    let top_stocks: Vec<&Stock> = stocks.iter().take(num_positions).collect();
-    
-    if top_stocks.is_empty() {
+/// Clip normalized `weights` into `[min, max]` and redistribute the excess
+/// (or shortfall) across unclipped weights in proportion to their size, so
+/// the result still sums to 1.0. Bounds that cannot be met with this many
+/// weights are relaxed towards equal weighting.
+fn bound_weights(weights: &[f64], min: f64, max: f64) -> Vec<f64> {
+    let n = weights.len();
+    if n == 0 {
         return Vec::new();
     }
-    
-    // Load points store and apply decay
-    let mut points = PointsStore::load("points_store.json");
-    points.decay_all(POINTS_DECAY);
+    let equal = 1.0 / n as f64;
+    let (min, max) = (min.clamp(0.0, equal), max.clamp(equal, 1.0));
+    let mut bounded = weights.to_vec();
+    let mut fixed = vec![false; n];
 
-    // Base return-based weights (normalized)
-    let return_weights = calculate_performance_weights(&top_stocks);
+    // Each pass pins at least one weight to a bound, so n passes suffice
+    for _ in 0..n {
+        let fixed_sum: f64 = bounded.iter().zip(&fixed).filter(|(_, f)| **f).map(|(w, _)| w).sum();
+        let free_sum: f64 = bounded.iter().zip(&fixed).filter(|(_, f)| !**f).map(|(w, _)| w).sum();
+        let free_count = fixed.iter().filter(|f| !**f).count();
+        if free_count == 0 {
+            break;
+        }
+        for (w, f) in bounded.iter_mut().zip(&fixed) {
+            if !*f {
+                *w = if free_sum > 0.0 { *w / free_sum * (1.0 - fixed_sum) } else { (1.0 - fixed_sum) / free_count as f64 };
+            }
+        }
 
-    // Points-based weights (normalize if non-zero)
-    let points_raw: Vec<f64> = top_stocks.iter().map(|s| points.get_score(&s.ticker)).collect();
-    let points_total: f64 = points_raw.iter().sum();
-    let points_weights: Vec<f64> = if points_total > 0.0 {
-        points_raw.iter().map(|p| p / points_total).collect()
-    } else {
-        vec![1.0 / (top_stocks.len() as f64); top_stocks.len()]
+        // Pin one side per pass: capping the top weights can lift the small
+        // ones back above the floor
+        let over_max = bounded.iter().zip(&fixed).any(|(w, f)| !*f && *w > max);
+        let under_min = bounded.iter().zip(&fixed).any(|(w, f)| !*f && *w < min);
+        if !over_max && !under_min {
+            break;
+        }
+        for (w, f) in bounded.iter_mut().zip(fixed.iter_mut()) {
+            if *f { continue; }
+            if over_max && *w > max {
+                *w = max;
+                *f = true;
+            } else if !over_max && *w < min {
+                *w = min;
+                *f = true;
+            }
+        }
+    }
+    bounded
+}
+
+/// Capitalization weights summing to 1.0. Stocks with no market cap get the
+/// smallest positive cap in the set; equal weights if none has a cap.
+fn calculate_marketcap_weights(stocks: &[&Stock]) -> Vec<f64> {
+    let min_cap = stocks.iter().map(|s| s.market_cap).filter(|&c| c > 0).min();
+    let Some(min_cap) = min_cap else {
+        return vec![1.0 / stocks.len() as f64; stocks.len()];
     };
+    let caps: Vec<f64> = stocks.iter()
+        .map(|s| if s.market_cap > 0 { s.market_cap } else { min_cap } as f64)
+        .collect();
+    let total: f64 = caps.iter().sum();
+    caps.iter().map(|c| c / total).collect()
+}
 
-    // Combined score: weighted blend of historical returns and learned points
-    let mut combined: Vec<f64> = Vec::with_capacity(top_stocks.len());
-    for i in 0..top_stocks.len() {
-        let c = RETURN_WEIGHT * return_weights[i] + POINTS_WEIGHT * points_weights[i];
-        combined.push(c);
+/// Convert raw points scores into weights summing to 1.0 using `transform`.
+/// Falls back to equal weights when there is no signal.
+fn points_to_weights(raw: &[f64], transform: PointsTransform) -> Vec<f64> {
+    if raw.is_empty() {
+        return Vec::new();
+    }
+    let equal = vec![1.0 / raw.len() as f64; raw.len()];
+    let max = raw.iter().cloned().fold(0.0, f64::max);
+    if max <= 0.0 {
+        return equal;
     }
 
-    // Normalize combined to sum to 1 (defensive)
-    let combined_total: f64 = combined.iter().sum();
-    if combined_total > 0.0 {
-        for v in combined.iter_mut() { *v /= combined_total; }
+    let transformed: Vec<f64> = match transform {
+        PointsTransform::Raw => raw.iter().map(|p| p.max(0.0)).collect(),
+        PointsTransform::Softmax { temperature } => {
+            let t = if temperature > 0.0 { temperature } else { 1.0 };
+            // Scores are scaled into [0, 1] first so the softmax tempers
+            // rather than amplifies differences in magnitude
+            raw.iter().map(|p| ((p.max(0.0) / max - 1.0) / t).exp()).collect()
+        }
+        PointsTransform::Rank => {
+            let mut order: Vec<usize> = (0..raw.len()).collect();
+            order.sort_by(|&a, &b| cmp_f64(raw[a], raw[b]));
+            let mut ranks = vec![0.0; raw.len()];
+            for (rank, idx) in order.into_iter().enumerate() {
+                ranks[idx] = (rank + 1) as f64;
+            }
+            ranks
+        }
+    };
+
+    let total: f64 = transformed.iter().sum();
+    if total > 0.0 {
+        transformed.iter().map(|w| w / total).collect()
     } else {
-        let default = 1.0 / (combined.len() as f64);
-        for v in combined.iter_mut() { *v = default; }
+        equal
     }
+}
 
-    // Allocate budget.
-    // Two modes:
-    //  - Concentrated rank-quantity allocation: buy a pre-defined number of
-    //    shares for each rank (e.g. 50 for top, 20 for second). If budget does
+/// Tracks cumulative spend per sector against a cap expressed as a fraction
+/// of the allocation budget. Multi-sector stocks count toward each of their
+/// sectors in proportion to `Stock::sector_exposure`.
+struct SectorSpend {
+    limit: f64,
+    /// Tighter per-sector limits from `PortfolioConfig::sector_caps`
+    sector_limits: HashMap<String, f64>,
+    spent: HashMap<String, f64>,
+}
+
+impl SectorSpend {
+    fn new(budget: f64, max_fraction: f64, sector_caps: &HashMap<String, f64>) -> Self {
+        let sector_limits = sector_caps.iter()
+            .map(|(sector, cap)| (sector.to_lowercase(), budget * cap.min(max_fraction)))
+            .collect();
+        SectorSpend { limit: budget * max_fraction, sector_limits, spent: HashMap::new() }
+    }
+
+    fn limit_for(&self, sector: &str) -> f64 {
+        self.sector_limits.get(&sector.to_lowercase()).copied().unwrap_or(self.limit)
+    }
+
+    /// Dollars that can still be spent on `stock` without breaching any of its sectors' caps
+    fn room(&self, stock: &Stock) -> f64 {
+        stock.sector_exposure()
+            .iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .map(|(sector, weight)| (self.limit_for(sector) - self.spent.get(*sector).copied().unwrap_or(0.0)) / weight)
+            .fold(f64::INFINITY, f64::min)
+            .max(0.0)
+    }
+
+    fn record(&mut self, stock: &Stock, cost: f64) {
+        for (sector, weight) in stock.sector_exposure() {
+            *self.spent.entry(sector.to_string()).or_insert(0.0) += cost * weight;
+        }
+    }
+}
+
+/// Build portfolio with performance-weighted allocation
+fn build_weighted_portfolio(
+    stocks: &[Stock],
+    budget: f64,
+    target_positions: usize,
+    strategy: AllocationStrategy,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+) -> Vec<(String, i32)> {
+    // Enforce global upper bound on positions
+    let num_positions = target_positions.min(stocks.len()).min(config.max_positions);
+    let top_stocks: Vec<&Stock> = stocks.iter().take(num_positions).collect();
+    
+    if top_stocks.is_empty() {
+        return Vec::new();
+    }
+    
+    // Apply decay to the shared points store and read current scores
+    let points_raw: Vec<f64> = {
+        let mut store = points.write().unwrap_or_else(|e| e.into_inner());
+        store.decay();
+        top_stocks.iter().map(|s| {
+            let score = store.get_score(&s.ticker);
+            if score > 0.0 {
+                return score;
+            }
+            // Cold start: fall back to the average score of the stock's sectors
+            let sectors = s.sectors();
+            if sectors.is_empty() {
+                return 0.0;
+            }
+            sectors.iter().map(|sector| store.get_sector_score(sector)).sum::<f64>() / sectors.len() as f64
+        }).collect()
+    };
+
+    // Base return-based weights (normalized)
+    let return_weights = calculate_performance_weights(&top_stocks);
+
+    // Points-based weights (transformed per config, then normalized)
+    let points_weights = points_to_weights(&points_raw, config.points_transform);
+
+    // Combined score: weighted blend of historical returns and learned points
+    let mut combined: Vec<f64> = Vec::with_capacity(top_stocks.len());
+    for i in 0..top_stocks.len() {
+        let c = config.return_weight * return_weights[i] + (1.0 - config.return_weight) * points_weights[i];
+        combined.push(c);
+    }
+
+    // Market-cap weighting replaces the return/points blend entirely
+    if strategy == AllocationStrategy::MarketCap {
+        combined = calculate_marketcap_weights(&top_stocks);
+    }
+
+    // Normalize combined to sum to 1 (defensive)
+    let combined_total: f64 = combined.iter().sum();
+    if combined_total > 0.0 {
+        for v in combined.iter_mut() { *v /= combined_total; }
+    } else {
+        let default = 1.0 / (combined.len() as f64);
+        for v in combined.iter_mut() { *v = default; }
+    }
+    let combined = bound_weights(&combined, config.min_position_weight, config.max_position_weight);
+
+    // Allocate budget.
+    // Two modes:
+    //  - Concentrated rank-quantity allocation: buy a pre-defined number of
+    //    shares for each rank (e.g. 50 for top, 20 for second). If budget does
     //    not allow the full target quantity the count is reduced to what can
     //    be afforded.
     //  - Proportional allocation (legacy): allocate budget proportionally to
     //    combined weights and convert to quantities.
     let mut portfolio = Vec::new();
     let mut allocated = 0.0;
+    // Per-sector spend so no single sector exceeds max_sector_fraction of the budget
+    let mut sector_spend = SectorSpend::new(budget, config.max_sector_fraction, &config.sector_caps);
+
+    if strategy == AllocationStrategy::Concentrated {
+        // Walk the full ranked list so that a stock skipped for its sector
+        // cap is replaced by the next candidate
+        for stock in stocks.iter() {
+            if portfolio.len() >= num_positions { break; }
 
-    if CONCENTRATE_ALLOCATION {
-        for (i, stock) in top_stocks.iter().enumerate() {
-            let price = stock.get_current_price();
+            let price = stock.price_for(config.pricing_basis);
             if price <= 0.0 { continue; }
 
             // Determine desired quantity by rank table (fallback to 1)
-            let desired_qty = if i < RANK_QUANTITIES.len() { RANK_QUANTITIES[i] } else { 1 };
+            let desired_qty = config.rank_quantities.get(portfolio.len()).copied().unwrap_or(1);
 
             // If desired_qty is zero or negative, skip
             if desired_qty <= 0 { continue; }
 
+            let sector_room = sector_spend.room(stock);
+            if sector_room < price {
+                warn!("[SECTOR] Skipping {} - {} would exceed the {:.0}% sector cap", stock.ticker, stock.sector, config.max_sector_fraction * 100.0);
+                continue;
+            }
+
             // Cost for desired quantity
             let desired_cost = (desired_qty as f64) * price;
 
-            if allocated + desired_cost <= budget {
+            if allocated + desired_cost <= budget && desired_cost <= sector_room {
                 // We can afford full desired quantity
                 portfolio.push((stock.ticker.clone(), desired_qty));
                 allocated += desired_cost;
+                sector_spend.record(stock, desired_cost);
             } else {
                 // Try to fit as many as possible of the desired_qty
-                let remaining = (budget - allocated).max(0.0);
+                let remaining = (budget - allocated).max(0.0).min(sector_room);
                 let afford_qty = (remaining / price).floor() as i32;
                 if afford_qty > 0 {
                     let cost = (afford_qty as f64) * price;
                     portfolio.push((stock.ticker.clone(), afford_qty));
                     allocated += cost;
+                    sector_spend.record(stock, cost);
                 } else {
                     // Nothing affordable for this rank; skip to next (could be cheaper)
-                    eprintln!("[WARN] Could not afford any shares of {} at ${:.2} with ${:.2} remaining", stock.ticker, price, budget - allocated);
+                    warn!("Could not afford any shares of {} at ${:.2} with ${:.2} remaining", stock.ticker, price, budget - allocated);
                 }
             }
         }
 
         // If we ended up with no positions (extremely small budgets), fall back to greedy
         if portfolio.is_empty() {
-            eprintln!("[WARN] Concentrated allocation produced empty portfolio, falling back to greedy allocation");
-            return build_greedy_portfolio(stocks, budget);
+            warn!("Concentrated allocation produced empty portfolio, falling back to greedy allocation");
+            return build_greedy_portfolio(stocks, budget, config.max_positions, false, config.pricing_basis, config.reduction_policy);
         }
 
-        // Deploy any small remaining budget into the top performer
-        let remaining = budget - allocated;
+        // Deploy any small remaining budget into the top performer (within its sector cap)
+        let remaining = (budget - allocated).min(sector_spend.room(top_stocks[0]));
         if remaining > 0.0 {
-            deploy_remaining_budget(&mut portfolio, remaining, top_stocks[0], budget);
+            deploy_remaining_budget(&mut portfolio, remaining, top_stocks[0], budget, config.pricing_basis);
         }
     } else {
         // Proportional legacy allocation (unchanged)
-        for (i, stock) in top_stocks.iter().enumerate() {
-            // Use current price for allocation math so submitted portfolio cost
-            // matches what the evaluator will compute.
-            let purchase_price = stock.get_current_price();
-            let target_allocation = budget * combined[i];
-            let quantity = (target_allocation / purchase_price).floor() as i32;
-
-            if quantity > 0 {
-                let cost = (quantity as f64) * purchase_price;
-                if allocated + cost <= budget {
-                    portfolio.push((stock.ticker.clone(), quantity));
-                    allocated += cost;
-                } else {
-                    eprintln!("[WARN] Skipping {} - would exceed budget", stock.ticker);
-                }
-            }
-        }
+        allocated = allocate_proportional(&mut portfolio, &top_stocks, &combined, budget, &mut sector_spend, config.pricing_basis);
 
-        // Deploy remaining budget into top combined performer
-        let remaining = budget - allocated;
+        // Deploy remaining budget into top combined performer (within its
+        // sector cap and the per-position weight cap)
+        let top = top_stocks[0];
+        let top_value = portfolio.iter()
+            .find(|(t, _)| t == &top.ticker)
+            .map_or(0.0, |(_, q)| *q as f64 * top.price_for(config.pricing_basis));
+        let position_room = (budget * config.max_position_weight - top_value).max(0.0);
+        let remaining = (budget - allocated).min(sector_spend.room(top)).min(position_room);
         if remaining > 0.0 {
-            deploy_remaining_budget(&mut portfolio, remaining, top_stocks[0], budget);
+            deploy_remaining_budget(&mut portfolio, remaining, top, budget, config.pricing_basis);
         }
     }
 
     // FINAL SAFETY CHECK: Validate budget
     if !validate_budget(&portfolio, stocks, budget) {
-        eprintln!("[EMERGENCY] Force-fitting portfolio within budget...");
-        force_within_budget(&mut portfolio, stocks, budget);
+        error!("Force-fitting portfolio within budget...");
+        force_within_budget(&mut portfolio, stocks, budget, config.reduction_policy);
     }
 
     // Update points store based on realized historical returns (small learning step)
+    let mut store = points.write().unwrap_or_else(|e| e.into_inner());
     for (ticker, qty) in &portfolio {
-        if let Some(s) = top_stocks.iter().find(|st| &st.ticker == ticker) {
+        if let Some(s) = stocks.iter().find(|st| &st.ticker == ticker) {
             let ret_pct = s.historical_return.unwrap_or(0.0);
             // Convert percent-ish returns to a modest delta; scale by qty
             let delta = (ret_pct / 100.0) * (*qty as f64) * 2.0; // tunable
-            points.add_score(ticker, delta);
+            store.add_score(ticker, delta);
+            store.add_bucket_score(config.vol_thresholds.bucket(s.volatility), ticker, delta);
+            for sector in s.sectors() {
+                store.add_sector_score(sector, delta);
+            }
         }
     }
 
     // Persist updated points
-    points.save();
+    store.save();
+
+    portfolio
+}
+
+/// Allocate each configured sleeve its fraction of `budget` from the ranked
+/// stocks it admits. A stock is only ever held by the first sleeve that
+/// picks it; sleeve budget with no admissible candidates is left unspent.
+fn build_tiered_portfolio(
+    stocks: &[Stock],
+    budget: f64,
+    target_positions: usize,
+    strategy: AllocationStrategy,
+    config: &PortfolioConfig,
+    points: &SharedPointsStore,
+) -> Vec<(String, i32)> {
+    let mut portfolio: Vec<(String, i32)> = Vec::new();
+    for sleeve in &config.sleeves {
+        let fraction = sleeve.budget_fraction.clamp(0.0, 1.0);
+        let candidates: Vec<Stock> = stocks.iter()
+            .filter(|s| sleeve.admits(s) && !portfolio.iter().any(|(t, _)| t == &s.ticker))
+            .cloned()
+            .collect();
+        if candidates.is_empty() || fraction <= 0.0 {
+            warn!("[SLEEVE] {} has no candidates - {:.0}% of budget left unspent", sleeve.name, fraction * 100.0);
+            continue;
+        }
+
+        let sleeve_budget = budget * fraction;
+        let positions = ((target_positions as f64 * fraction).round() as usize).max(1);
+        let picks = match strategy {
+            AllocationStrategy::Greedy | AllocationStrategy::SectorGreedy => build_greedy_portfolio(
+                &candidates, sleeve_budget, positions, strategy == AllocationStrategy::SectorGreedy, config.pricing_basis, config.reduction_policy),
+            AllocationStrategy::Concentrated | AllocationStrategy::MarketCap => build_weighted_portfolio(&candidates, sleeve_budget, positions, strategy, config, points),
+            _ => build_weighted_portfolio(&candidates, sleeve_budget, positions, AllocationStrategy::Proportional, config, points),
+        };
+        let spent = calculate_portfolio_cost(&picks, &candidates, config.pricing_basis);
+        info!("[SLEEVE] {}: {} positions, ${:.2} of ${:.2} ({:.0}% target)",
+                 sleeve.name, picks.len(), spent, sleeve_budget, fraction * 100.0);
+        portfolio.extend(picks);
+    }
+    portfolio
+}
+
+/// Allocate `budget` across `stocks` proportionally to `weights`, converting to
+/// whole-share quantities. Returns the amount spent.
+fn allocate_proportional(
+    portfolio: &mut Vec<(String, i32)>,
+    stocks: &[&Stock],
+    weights: &[f64],
+    budget: f64,
+    sector_spend: &mut SectorSpend,
+    basis: PricingBasis,
+) -> f64 {
+    let mut allocated = 0.0;
+    for (stock, weight) in stocks.iter().zip(weights) {
+        let purchase_price = stock.price_for(basis);
+        if purchase_price <= 0.0 { continue; }
+        let target_allocation = (budget * weight).min(sector_spend.room(stock));
+        let quantity = (target_allocation / purchase_price).floor() as i32;
+
+        if quantity > 0 {
+            let cost = (quantity as f64) * purchase_price;
+            if allocated + cost <= budget {
+                portfolio.push((stock.ticker.clone(), quantity));
+                allocated += cost;
+                sector_spend.record(stock, cost);
+            } else {
+                warn!("Skipping {} - would exceed budget", stock.ticker);
+            }
+        }
+    }
+    allocated
+}
+
+/// Estimate mean monthly returns and their covariance matrix for `stocks`
+/// over the months every stock has data for. Returns None if any stock has
+/// no monthly series or there are too few common months.
+fn estimate_mean_covariance(stocks: &[&Stock]) -> Option<(Vec<f64>, Vec<Vec<f64>>)> {
+    let series: Vec<HashMap<String, f64>> = stocks
+        .iter()
+        .map(|s| monthly_returns(&s.ticker).map(|r| r.into_iter().collect()))
+        .collect::<Option<_>>()?;
+
+    let mut common: Vec<&String> = series.first()?.keys().collect();
+    common.retain(|month| series.iter().all(|m| m.contains_key(*month)));
+    common.sort();
+    if common.len() < 3 {
+        return None;
+    }
+
+    let samples: Vec<Vec<f64>> = series.iter()
+        .map(|m| common.iter().map(|month| m[*month]).collect())
+        .collect();
+    Some(mean_covariance(&samples))
+}
+
+/// Sample mean vector and covariance matrix for equally-long return series
+fn mean_covariance(samples: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = samples.len();
+    let t = samples.first().map(|s| s.len()).unwrap_or(0);
+    let means: Vec<f64> = samples.iter().map(|s| s.iter().sum::<f64>() / t.max(1) as f64).collect();
+
+    let mut cov = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in i..n {
+            let c = (0..t)
+                .map(|k| (samples[i][k] - means[i]) * (samples[j][k] - means[j]))
+                .sum::<f64>() / (t.max(2) - 1) as f64;
+            cov[i][j] = c;
+            cov[j][i] = c;
+        }
+    }
+    (means, cov)
+}
+
+/// Solve `a * x = b` by Gaussian elimination with partial pivoting
+fn solve_linear_system(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| cmp_f64(a[i][col].abs(), a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in (col + 1)..n {
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col].clone();
+            for (cell, p) in a[row].iter_mut().zip(pivot_row.iter()).skip(col) {
+                *cell -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f64 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Long-only weights proportional to `cov^-1 * target`, dropping assets that
+/// come out negative and re-solving on the remainder
+fn long_only_solve(cov: &[Vec<f64>], target: &[f64]) -> Option<Vec<f64>> {
+    let n = target.len();
+    // Small ridge keeps nearly-singular covariance matrices solvable
+    let ridge = 1e-8 * (0..n).map(|i| cov[i][i]).sum::<f64>().max(1e-12) / n as f64;
+    let mut active: Vec<usize> = (0..n).collect();
+
+    while !active.is_empty() {
+        let a: Vec<Vec<f64>> = active.iter()
+            .map(|&i| active.iter().map(|&j| cov[i][j] + if i == j { ridge } else { 0.0 }).collect())
+            .collect();
+        let b: Vec<f64> = active.iter().map(|&i| target[i]).collect();
+        let x = solve_linear_system(a, b)?;
+
+        if x.iter().all(|w| *w >= 0.0) {
+            let total: f64 = x.iter().sum();
+            if total <= 0.0 {
+                return None;
+            }
+            let mut weights = vec![0.0; n];
+            for (&i, w) in active.iter().zip(&x) {
+                weights[i] = w / total;
+            }
+            return Some(weights);
+        }
+        let dropped: HashSet<usize> = active.iter().zip(&x).filter(|(_, w)| **w < 0.0).map(|(&i, _)| i).collect();
+        active.retain(|i| !dropped.contains(i));
+    }
+    None
+}
+
+/// Markowitz weights maximizing return per unit risk (tangency portfolio,
+/// long-only). Falls back to the minimum-variance portfolio when no asset
+/// has a positive expected return.
+fn mean_variance_weights(means: &[f64], cov: &[Vec<f64>]) -> Option<Vec<f64>> {
+    if means.iter().any(|m| *m > 0.0) {
+        if let Some(w) = long_only_solve(cov, means) {
+            return Some(w);
+        }
+    }
+    long_only_solve(cov, &vec![1.0; means.len()])
+}
+
+/// Build portfolio with mean-variance (Markowitz) weights estimated from
+/// the candidates' monthly return series
+fn build_mean_variance_portfolio(
+    stocks: &[Stock],
+    budget: f64,
+    target_positions: usize,
+    config: &PortfolioConfig,
+) -> Vec<(String, i32)> {
+    let num_positions = target_positions.min(stocks.len()).min(config.max_positions);
+    let top_stocks: Vec<&Stock> = stocks.iter().take(num_positions).collect();
+
+    if top_stocks.is_empty() {
+        return Vec::new();
+    }
+
+    let weights = match estimate_mean_covariance(&top_stocks).and_then(|(means, cov)| mean_variance_weights(&means, &cov)) {
+        Some(w) => w,
+        None => {
+            warn!("Not enough monthly data for mean-variance weights, using performance weights");
+            calculate_performance_weights(&top_stocks)
+        }
+    };
+
+    let mut portfolio = Vec::new();
+    let mut sector_spend = SectorSpend::new(budget, config.max_sector_fraction, &config.sector_caps);
+    let allocated = allocate_proportional(&mut portfolio, &top_stocks, &weights, budget, &mut sector_spend, config.pricing_basis);
+
+    // Deploy remaining budget into the highest-weighted stock (within its sector cap)
+    let best = weights.iter()
+        .enumerate()
+        .max_by(|a, b| cmp_f64(*a.1, *b.1))
+        .map(|(i, _)| top_stocks[i])
+        .unwrap_or(top_stocks[0]);
+    let remaining = (budget - allocated).min(sector_spend.room(best));
+    if remaining > 0.0 {
+        deploy_remaining_budget(&mut portfolio, remaining, best, budget, config.pricing_basis);
+    }
+
+    if !validate_budget(&portfolio, stocks, budget) {
+        error!("Force-fitting portfolio within budget...");
+        force_within_budget(&mut portfolio, stocks, budget, config.reduction_policy);
+    }
 
     portfolio
 }
 
+/// Full-Kelly fraction of capital for an asset with the given mean and
+/// variance of periodic returns (continuous approximation `mean / variance`).
+/// Non-positive means or variances yield 0 (no position).
+pub fn kelly_fraction(mean: f64, variance: f64) -> f64 {
+    if mean <= 0.0 || variance <= 0.0 || !mean.is_finite() || !variance.is_finite() {
+        return 0.0;
+    }
+    mean / variance
+}
+
+/// Fractional-Kelly sizing for aggressive profiles. Each top candidate gets
+/// `kelly_multiplier * kelly_fraction` of the budget (capped per name), scaled
+/// down if the total would exceed the budget; unallocated cash is held back
+/// on purpose. Returns None when any candidate lacks monthly return data so
+/// the caller can fall back to the standard weighting.
+fn build_kelly_portfolio(
+    stocks: &[Stock],
+    budget: f64,
+    target_positions: usize,
+    config: &PortfolioConfig,
+) -> Option<Vec<(String, i32)>> {
+    let num_positions = target_positions.min(stocks.len()).min(config.max_positions);
+    let top_stocks: Vec<&Stock> = stocks.iter().take(num_positions).collect();
+    if top_stocks.is_empty() {
+        return None;
+    }
+
+    let mut fractions = Vec::with_capacity(top_stocks.len());
+    for stock in &top_stocks {
+        let Some((mean, variance)) = monthly_return_stats(&stock.ticker) else {
+            warn!("[KELLY] Missing return variance for {} - using standard weighting", stock.ticker);
+            return None;
+        };
+        let f = config.kelly_multiplier * kelly_fraction(mean, variance);
+        fractions.push(f.min(KELLY_MAX_POSITION_FRACTION));
+    }
+
+    let total: f64 = fractions.iter().sum();
+    if total <= 0.0 {
+        warn!("[KELLY] No candidate has a positive Kelly fraction - using standard weighting");
+        return None;
+    }
+    if total > 1.0 {
+        for f in fractions.iter_mut() { *f /= total; }
+    }
+    info!("[KELLY] Sizing {} positions, {:.1}% of budget invested", top_stocks.len(), fractions.iter().sum::<f64>() * 100.0);
+
+    let mut portfolio = Vec::new();
+    let mut sector_spend = SectorSpend::new(budget, config.max_sector_fraction, &config.sector_caps);
+    allocate_proportional(&mut portfolio, &top_stocks, &fractions, budget, &mut sector_spend, config.pricing_basis);
+
+    if !validate_budget(&portfolio, stocks, budget) {
+        force_within_budget(&mut portfolio, stocks, budget, config.reduction_policy);
+    }
+    Some(portfolio)
+}
+
 /// Deploy remaining budget into the best performing stock
-fn deploy_remaining_budget(portfolio: &mut Vec<(String, i32)>, remaining: f64, top_stock: &Stock, budget: f64) {
+fn deploy_remaining_budget(portfolio: &mut Vec<(String, i32)>, remaining: f64, top_stock: &Stock, budget: f64, basis: PricingBasis) {
     if remaining <= 0.0 {
         return;
     }
     
-    // Use current price when deploying remaining budget (submission uses current prices)
-    let price = top_stock.get_current_price();
+    let price = top_stock.price_for(basis);
     let extra_qty = (remaining / price).floor() as i32;
     
     if extra_qty > 0 {
@@ -463,14 +1874,16 @@ fn deploy_remaining_budget(portfolio: &mut Vec<(String, i32)>, remaining: f64, t
 }
 
 #[allow(unused_assignments)]
-fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
+/// With `sector_spread`, the kept positions take the cheapest stock of each
+/// distinct sector first, so the first pass buys one share per sector
+fn build_greedy_portfolio(stocks: &[Stock], budget: f64, max_positions: usize, sector_spread: bool, basis: PricingBasis, reduction: ReductionPolicy) -> Vec<(String, i32)> {
     let mut portfolio = Vec::new();
     let mut remaining_budget = budget;
     
     // Filter to only affordable stocks (use current market price for affordability)
     let mut affordable_stocks: Vec<&Stock> = stocks
         .iter()
-        .filter(|s| s.get_current_price() <= budget)  // Use original budget, not remaining
+        .filter(|s| s.price_for(basis) <= budget)  // Use original budget, not remaining
         .collect();
     
     if affordable_stocks.is_empty() {
@@ -479,12 +1892,22 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
     
     // Sort affordable stocks by price (cheapest first for small budgets)
     affordable_stocks.sort_by(|a, b| {
-        a.get_current_price().partial_cmp(&b.get_current_price()).unwrap()
+        cmp_f64(a.price_for(basis), b.price_for(basis))
     });
 
+    // Move the cheapest stock of each sector to the front (stable, so both
+    // groups stay cheapest first)
+    if sector_spread {
+        let mut seen_sectors = HashSet::new();
+        let (first_of_sector, rest): (Vec<&Stock>, Vec<&Stock>) = affordable_stocks
+            .into_iter()
+            .partition(|s| seen_sectors.insert(s.sector.to_lowercase()));
+        affordable_stocks = first_of_sector.into_iter().chain(rest).collect();
+    }
+
     // Enforce a hard cap on number of distinct positions for greedy allocation
-    if affordable_stocks.len() > MAX_POSITIONS {
-        affordable_stocks.truncate(MAX_POSITIONS);
+    if affordable_stocks.len() > max_positions {
+        affordable_stocks.truncate(max_positions);
     }
     
     // Greedy approach: buy as many shares as possible, diversifying when we can
@@ -493,7 +1916,7 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
     
     // First pass: buy at least 1 share of as many stocks as we can afford
     for (i, stock) in affordable_stocks.iter().enumerate() {
-        let price = stock.get_current_price();
+        let price = stock.price_for(basis);
         // SAFETY CHECK: Ensure we have enough budget
         if remaining_budget >= price && price > 0.0 {
             shares_per_stock[i] = 1;
@@ -501,7 +1924,7 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
             
             // Double check we didn't go negative
             if remaining_budget < 0.0 {
-                eprintln!("[ERROR] Budget went negative in greedy allocation!");
+                error!("Budget went negative in greedy allocation!");
                 shares_per_stock[i] = 0; // Undo
                 remaining_budget += price;
                 break;
@@ -517,7 +1940,7 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
         safety_counter += 1;
         
         let stock = affordable_stocks[stock_index];
-        let price = stock.get_current_price();
+        let price = stock.price_for(basis);
         
         // SAFETY CHECK: Verify we can afford it
         if remaining_budget >= price && price > 0.0 {
@@ -526,7 +1949,7 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
             
             // Double check we didn't go negative
             if remaining_budget < -0.01 { // Allow small floating point errors
-                eprintln!("[ERROR] Budget went negative! Rolling back last purchase.");
+                error!("Budget went negative! Rolling back last purchase.");
                 shares_per_stock[stock_index] -= 1;
                 remaining_budget += price;
                 break;
@@ -537,13 +1960,13 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
         stock_index = (stock_index + 1) % affordable_stocks.len();
         
         // Check if we can't afford anything anymore
-        if affordable_stocks.iter().all(|s| s.get_current_price() > remaining_budget) {
+        if affordable_stocks.iter().all(|s| s.price_for(basis) > remaining_budget) {
             break;
         }
     }
     
     if safety_counter >= max_iterations {
-        eprintln!("[WARN] Greedy allocation hit iteration limit - stopping");
+        warn!("Greedy allocation hit iteration limit - stopping");
     }
     
     // Build final portfolio
@@ -555,9 +1978,1032 @@ fn build_greedy_portfolio(stocks: &[Stock], budget: f64) -> Vec<(String, i32)> {
     
     // FINAL SAFETY CHECK: Validate budget
     if !validate_budget(&portfolio, stocks, budget) {
-        eprintln!("[EMERGENCY] Greedy portfolio exceeded budget - fixing...");
-        force_within_budget(&mut portfolio, stocks, budget);
+        error!("Greedy portfolio exceeded budget - fixing...");
+        force_within_budget(&mut portfolio, stocks, budget, reduction);
     }
     
     portfolio
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::investor::CompanyLookup;
+    use serde_json::json;
+
+    fn stock(ticker: &str, price: f64, sector: &str, volatility: f64) -> Stock {
+        serde_json::from_value(json!({
+            "ticker": ticker, "price": price, "sector": sector, "volatility": volatility,
+        })).unwrap()
+    }
+
+    fn profile(message: &str) -> InvestorProfile {
+        let context = json!({ "message": message }).to_string();
+        InvestorProfile::from_context(&context, &CompanyLookup::default()).unwrap()
+    }
+
+    fn no_points() -> SharedPointsStore {
+        std::sync::Arc::new(std::sync::RwLock::new(PointsStore::default()))
+    }
+
+    /// Months "2019-01".."2020-12"
+    fn months() -> Vec<String> {
+        (0..24).map(|i| format!("{}-{:02}", 2019 + i / 12, i % 12 + 1)).collect()
+    }
+
+    /// Monthly closes from `start` following returns `drift + swing * sin(t * freq + phase)`
+    fn series(start: f64, drift: f64, swing: f64, freq: f64, phase: f64) -> Vec<f64> {
+        let mut price = start;
+        (0..24).map(|t| {
+            let close = price;
+            price *= 1.0 + drift + swing * (t as f64 * freq + phase).sin();
+            close
+        }).collect()
+    }
+
+    /// Twelve stocks over six sectors with returns, caps and yields, each
+    /// with a monthly series in the shared cache
+    fn universe() -> Vec<Stock> {
+        let specs = [
+            ("UAPL", 180.0, "Technology", 0.040, 85.0, 2_800_000, 0.005),
+            ("UMSF", 320.0, "Technology", 0.030, 70.0, 2_400_000, 0.008),
+            ("UNVD", 450.0, "Technology", 0.060, 140.0, 1_100_000, 0.001),
+            ("UJNJ", 160.0, "Healthcare", 0.015, 12.0, 420_000, 0.029),
+            ("UPFE", 38.0, "Healthcare", 0.020, -8.0, 210_000, 0.041),
+            ("UXOM", 105.0, "Energy", 0.035, 25.0, 450_000, 0.033),
+            ("UCVX", 150.0, "Energy", 0.032, 18.0, 300_000, 0.037),
+            ("UDUK", 95.0, "Utilities", 0.012, 9.0, 75_000, 0.042),
+            ("UNEE", 72.0, "Utilities", 0.018, 15.0, 150_000, 0.027),
+            ("UJPM", 145.0, "Financials", 0.028, 30.0, 420_000, 0.025),
+            ("UKO", 60.0, "Consumer", 0.010, 6.0, 260_000, 0.030),
+            ("UF", 12.0, "Consumer", 0.045, 40.0, 50_000, 0.0),
+        ];
+        let months = months();
+        let dates: Vec<&str> = months.iter().map(String::as_str).collect();
+        specs.iter().enumerate().map(|(i, (ticker, price, sector, vol, ret, cap, yld))| {
+            let mut s: Stock = serde_json::from_value(json!({
+                "ticker": ticker, "price": price, "sector": sector, "volatility": vol,
+                "market_cap": cap, "first_trading_date": "1990-01-02",
+                "dividend_yield": if *yld > 0.0 { Some(yld) } else { None },
+            })).unwrap();
+            s.historical_return = Some(*ret);
+            let closes = series(*price, ret / 2400.0, *vol, 0.7 + i as f64 * 0.37, i as f64);
+            crate::stocks::insert_monthly_series(ticker, &dates, &closes);
+            s
+        }).collect()
+    }
+
+    fn cost(portfolio: &[(String, i32)], stocks: &[Stock]) -> f64 {
+        calculate_portfolio_cost(portfolio, stocks, PricingBasis::Current)
+    }
+
+    #[test]
+    fn price_gap_flagged_stocks_are_excluded() {
+        let mut jumpy = stock("JMP", 20.0, "Technology", 0.02);
+        jumpy.price_gap_flagged = true;
+        let stocks = vec![jumpy, stock("OK", 20.0, "Technology", 0.02)];
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let eligible = filter_stocks_by_profile(&stocks, &client, &PortfolioConfig::default());
+        let tickers: Vec<&str> = eligible.iter().map(|s| s.ticker.as_str()).collect();
+        assert_eq!(tickers, vec!["OK"]);
+    }
+
+    #[test]
+    fn fnv1a_is_fixed() {
+        // No input leaves the FNV-1a offset basis untouched
+        assert_eq!(fnv1a(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(tie_break_key(42, "AAPL"), tie_break_key(42, "AAPL"));
+        assert_ne!(tie_break_key(42, "AAPL"), tie_break_key(43, "AAPL"));
+        assert_ne!(fnv1a(&[b"ab", b"c"]), fnv1a(&[b"a", b"bc"]));
+    }
+
+    #[test]
+    fn seeded_ties_give_identical_portfolios() {
+        let mut stocks: Vec<Stock> = ["AAA", "BBB", "CCC", "DDD", "EEE", "FFF", "GGG", "HHH", "III"]
+            .iter()
+            .map(|t| stock(t, 25.0, "Industrials", 0.02))
+            .collect();
+        for s in &mut stocks {
+            s.historical_return = Some(12.0);
+        }
+        let config = PortfolioConfig { tie_break_seed: Some(7), ..PortfolioConfig::default() };
+        let run = |stocks: &[Stock]| {
+            let points = std::sync::Arc::new(std::sync::RwLock::new(PointsStore::default()));
+            let plan = build_portfolio(stocks, 20_000.0, RiskLevel::Aggressive, &config, &points, None, None);
+            serde_json::to_string(plan.positions()).unwrap()
+        };
+        let first = run(&stocks);
+        assert_ne!(first, "[]");
+        assert_eq!(first, run(&stocks));
+
+        // The seeded order ignores input order
+        stocks.reverse();
+        assert_eq!(run(&stocks), first);
+    }
+
+    #[test]
+    fn portfolio_cost_depends_on_pricing_basis() {
+        let mut moved = stock("MOV", 100.0, "Technology", 0.02);
+        moved.historical_start_price = Some(60.0);
+        let stocks = vec![moved, stock("FLAT", 10.0, "Utilities", 0.01)];
+        let portfolio = vec![("MOV".to_string(), 3), ("FLAT".to_string(), 2)];
+        assert_eq!(calculate_portfolio_cost(&portfolio, &stocks, PricingBasis::Current), 320.0);
+        // FLAT has no historical price and falls back to its current one
+        assert_eq!(calculate_portfolio_cost(&portfolio, &stocks, PricingBasis::HistoricalStart), 200.0);
+    }
+
+    #[test]
+    fn deep_drawdown_is_excluded_for_cautious_clients() {
+        let mut dipped = stock("DIP", 20.0, "Technology", 0.005);
+        dipped.historical_return = Some(50.0);
+        dipped.max_drawdown = Some(50.0);
+        let steady = stock("STDY", 20.0, "Technology", 0.005);
+
+        assert!(!within_drawdown_limit(&dipped, RiskLevel::Conservative, Some(30.0)));
+        assert!(!within_drawdown_limit(&dipped, RiskLevel::Moderate, Some(30.0)));
+        assert!(within_drawdown_limit(&dipped, RiskLevel::Aggressive, Some(30.0)));
+        assert!(within_drawdown_limit(&dipped, RiskLevel::Conservative, None));
+        // No monthly data means no drawdown to judge
+        assert!(within_drawdown_limit(&steady, RiskLevel::Conservative, Some(30.0)));
+    }
+
+    #[test]
+    fn mixed_sector_stock_counts_fractionally_against_caps() {
+        let mut mixed = stock("MIX", 10.0, "Technology, Consumer", 0.02);
+        mixed.sector_weights = HashMap::from([("Technology".to_string(), 0.7), ("Consumer".to_string(), 0.3)]);
+        let tech = stock("TCH", 10.0, "Technology", 0.02);
+
+        let mut spend = SectorSpend::new(1000.0, 0.5, &HashMap::new());
+        spend.record(&mixed, 400.0);
+        assert_eq!(spend.spent["Technology"], 280.0);
+        assert_eq!(spend.spent["Consumer"], 120.0);
+        // A pure tech stock only has the tech cap's remaining 220 to use
+        assert!((spend.room(&tech) - 220.0).abs() < 1e-9);
+        // The mixed stock is bound by its 70% tech share: 220 / 0.7
+        assert!((spend.room(&mixed) - 220.0 / 0.7).abs() < 1e-9);
+    }
+
+    #[test]
+    fn every_strategy_stays_within_budget() {
+        let stocks = universe();
+        let strategies = [
+            AllocationStrategy::Auto,
+            AllocationStrategy::Greedy,
+            AllocationStrategy::SectorGreedy,
+            AllocationStrategy::Concentrated,
+            AllocationStrategy::Proportional,
+            AllocationStrategy::MeanVariance,
+            AllocationStrategy::MarketCap,
+            AllocationStrategy::RiskAdjusted,
+            AllocationStrategy::Income,
+        ];
+        for strategy in strategies {
+            for kelly_sizing in [false, true] {
+                let config = PortfolioConfig { strategy, kelly_sizing, ..PortfolioConfig::default() };
+                for budget in [900.0, 3_000.0, 7_500.0, 40_000.0, 250_000.0] {
+                    for risk in [RiskLevel::Conservative, RiskLevel::Moderate, RiskLevel::Aggressive] {
+                        let plan = build_portfolio(&stocks, budget, risk, &config, &no_points(), None, None);
+                        let spent = cost(plan.positions(), &stocks);
+                        let label = format!("{} (kelly {}) at ${} {:?}", strategy.name(), kelly_sizing, budget, risk);
+                        // Tiny conservative budgets may not afford any share of the weighted picks
+                        if budget >= 7_500.0 {
+                            assert!(!plan.positions().is_empty(), "{}: empty portfolio", label);
+                        }
+                        assert!(spent <= budget, "{}: spent ${:.2}", label, spent);
+                        assert!(plan.positions().len() <= config.max_positions, "{}: {} positions", label, plan.positions().len());
+                        assert!(plan.positions().iter().all(|(_, q)| *q > 0), "{}: non-positive quantity", label);
+                        if kelly_sizing && matches!(risk, RiskLevel::Aggressive) && config.alloc_budget(budget, risk) >= GREEDY_BUDGET_THRESHOLD
+                            && !matches!(strategy, AllocationStrategy::Greedy | AllocationStrategy::SectorGreedy) {
+                            assert_eq!(plan.strategy, "kelly", "{}", label);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn softmax_tempers_skewed_points() {
+        // One frequently-submitted ticker dwarfs the rest
+        let skewed = [400.0, 4.0, 2.0, 1.0];
+        let raw = points_to_weights(&skewed, PointsTransform::Raw);
+        let softmax = points_to_weights(&skewed, PointsTransform::Softmax { temperature: 1.0 });
+        let rank = points_to_weights(&skewed, PointsTransform::Rank);
+        for weights in [&raw, &softmax, &rank] {
+            assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+        assert!(raw[0] > 0.98);
+        assert!(softmax[0] < raw[0] && softmax[0] > softmax[1]);
+        assert!(softmax[3] > raw[3] * 10.0);
+        assert_eq!(rank, vec![0.4, 0.3, 0.2, 0.1]);
+
+        // No signal at all gives equal weights
+        assert_eq!(points_to_weights(&[0.0, 0.0], PointsTransform::Softmax { temperature: 1.0 }), vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn rank_ladder_from_file_sets_top_quantities() {
+        let dir = std::env::temp_dir().join(format!("quant_proj_rank_ladder_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rank_quantities.json");
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, "[100, 50, 25]").unwrap();
+        let config = PortfolioConfig {
+            strategy: AllocationStrategy::Concentrated,
+            rank_quantities: load_rank_quantities(path),
+            max_sector_fraction: 1.0,
+            ..PortfolioConfig::default()
+        };
+        assert_eq!(config.rank_quantities, vec![100, 50, 25]);
+
+        let mut stocks: Vec<Stock> = ["LDRA", "LDRB", "LDRC"].iter().map(|t| stock(t, 10.0, "Industrials", 0.02)).collect();
+        for (s, ret) in stocks.iter_mut().zip([30.0, 20.0, 10.0]) {
+            s.historical_return = Some(ret);
+        }
+        // Exactly enough for the ladder, so nothing is left to top up
+        let portfolio = build_weighted_portfolio(&stocks, 1_750.0, 3, AllocationStrategy::Concentrated, &config, &no_points());
+        assert_eq!(portfolio, vec![("LDRA".to_string(), 100), ("LDRB".to_string(), 50), ("LDRC".to_string(), 25)]);
+
+        // Invalid ladders fall back to the built-in one
+        std::fs::write(path, "[100, 0, 25]").unwrap();
+        assert_eq!(load_rank_quantities(path), RANK_QUANTITIES.to_vec());
+        std::fs::write(path, "not json").unwrap();
+        assert_eq!(load_rank_quantities(path), RANK_QUANTITIES.to_vec());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn default_sector_cap_holds_on_single_sector_candidates() {
+        let config = PortfolioConfig::default();
+        assert_eq!(config.max_sector_fraction, 0.4);
+        let mut tech: Vec<Stock> = ["SCTA", "SCTB", "SCTC", "SCTD", "SCTE"].iter()
+            .map(|t| stock(t, 25.0, "Technology", 0.02))
+            .collect();
+        for (i, s) in tech.iter_mut().enumerate() {
+            s.historical_return = Some(50.0 - i as f64 * 5.0);
+        }
+        for strategy in [AllocationStrategy::Concentrated, AllocationStrategy::Proportional, AllocationStrategy::MarketCap] {
+            let portfolio = build_weighted_portfolio(&tech, 10_000.0, 5, strategy, &config, &no_points());
+            assert!(!portfolio.is_empty());
+            assert!(cost(&portfolio, &tech) <= 4_000.0, "{}: ${:.2} in one sector", strategy.name(), cost(&portfolio, &tech));
+        }
+
+        // A tech-heavy universe keeps every sector under the cap of the allocation budget
+        let mut heavy = tech.clone();
+        let mut energy = stock("SCTX", 40.0, "Energy", 0.02);
+        energy.historical_return = Some(5.0);
+        heavy.push(energy);
+        let plan = build_portfolio(&heavy, 20_000.0, RiskLevel::Aggressive, &config, &no_points(), None, None);
+        let alloc_budget = config.alloc_budget(20_000.0, RiskLevel::Aggressive);
+        let tech_spend: f64 = plan.positions().iter()
+            .filter(|(t, _)| t != "SCTX")
+            .map(|(t, q)| heavy.iter().find(|s| &s.ticker == t).unwrap().price * *q as f64)
+            .sum();
+        assert!(tech_spend <= alloc_budget * 0.4 + 1e-9, "tech ${:.2} of ${:.2}", tech_spend, alloc_budget);
+    }
+
+    #[test]
+    fn mean_variance_favours_the_lower_variance_asset() {
+        // Equal expected returns, uncorrelated, variances 1:4:9
+        let means = [0.01, 0.01, 0.01];
+        let cov = vec![
+            vec![0.001, 0.0, 0.0],
+            vec![0.0, 0.004, 0.0],
+            vec![0.0, 0.0, 0.009],
+        ];
+        let weights = mean_variance_weights(&means, &cov).unwrap();
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(weights[0] > weights[1] && weights[1] > weights[2], "{:?}", weights);
+        // Inverse-variance weights: 36/49, 9/49, 4/49
+        assert!((weights[0] - 36.0 / 49.0).abs() < 1e-6);
+
+        // The estimator recovers a known covariance from return samples
+        let (est_means, est_cov) = mean_covariance(&[vec![0.01, 0.03, 0.02], vec![0.02, 0.06, 0.04]]);
+        assert!((est_means[0] - 0.02).abs() < 1e-12);
+        assert!((est_cov[0][0] - 0.0001).abs() < 1e-12);
+        assert!((est_cov[0][1] - 0.0002).abs() < 1e-12);
+        assert!((est_cov[1][1] - 0.0004).abs() < 1e-12);
+    }
+
+    #[test]
+    fn near_identical_series_force_diversification() {
+        let months = months();
+        let dates: Vec<&str> = months.iter().map(String::as_str).collect();
+        let base = series(50.0, 0.01, 0.05, 1.3, 0.0);
+        let twin: Vec<f64> = base.iter().map(|p| p * 2.0 + 0.01).collect();
+        crate::stocks::insert_monthly_series("CRLA", &dates, &base);
+        crate::stocks::insert_monthly_series("CRLB", &dates, &twin);
+        crate::stocks::insert_monthly_series("CRLC", &dates, &series(50.0, 0.01, 0.05, 0.4, 2.0));
+
+        let ranked: Vec<Stock> = ["CRLA", "CRLB", "CRLC"].iter().map(|t| stock(t, 50.0, "Technology", 0.02)).collect();
+        assert!(pairwise_correlation(&ranked[0], &ranked[1]).unwrap() > 0.99);
+        assert!(pairwise_correlation(&ranked[0], &ranked[2]).unwrap() < 0.85);
+
+        let order: Vec<String> = diversify_by_correlation(ranked.clone(), 2, 0.85).into_iter().map(|s| s.ticker).collect();
+        assert_eq!(order, vec!["CRLA", "CRLC", "CRLB"]);
+        // Without monthly data nothing is skipped
+        let unknown: Vec<Stock> = ["CRLX", "CRLY"].iter().map(|t| stock(t, 50.0, "Technology", 0.02)).collect();
+        let order: Vec<String> = diversify_by_correlation(unknown, 2, 0.85).into_iter().map(|s| s.ticker).collect();
+        assert_eq!(order, vec!["CRLX", "CRLY"]);
+    }
+
+    #[test]
+    fn fractional_mode_deploys_closer_to_budget() {
+        let mut stocks = vec![stock("FRCA", 430.0, "Technology", 0.02), stock("FRCB", 610.0, "Energy", 0.02)];
+        stocks[0].historical_return = Some(20.0);
+        stocks[1].historical_return = Some(10.0);
+        let config = PortfolioConfig { fractional_shares: true, ..PortfolioConfig::default() };
+        let budget = 1_000.0;
+
+        let whole = build_greedy_portfolio(&stocks, budget, config.max_positions, false, PricingBasis::Current, ReductionPolicy::MostShares);
+        let whole_cost = cost(&whole, &stocks);
+        let fractional = fractionalize(&whole, &stocks, budget, &config);
+        let fractional_cost: f64 = fractional.iter()
+            .map(|(t, q)| stocks.iter().find(|s| &s.ticker == t).unwrap().price * q)
+            .sum();
+
+        assert!(fractional_cost <= budget);
+        assert!(budget - fractional_cost < 1.0, "${:.2} left unspent", budget - fractional_cost);
+        assert!(budget - whole_cost > 100.0, "integer mode only left ${:.2}", budget - whole_cost);
+        assert!(fractional.iter().any(|(_, q)| q.fract() > 0.0));
+
+        // Nothing affordable as whole shares: the budget is split over the top picks
+        let fractional = fractionalize(&[], &stocks, 300.0, &config);
+        assert_eq!(fractional.len(), 2);
+        assert!(fractional.iter().all(|(_, q)| *q > 0.0 && *q < 1.0));
+    }
+
+    #[test]
+    fn kelly_fraction_math() {
+        assert!((kelly_fraction(0.02, 0.01) - 2.0).abs() < 1e-12);
+        assert!((kelly_fraction(0.01, 0.04) - 0.25).abs() < 1e-12);
+        assert_eq!(kelly_fraction(-0.01, 0.04), 0.0);
+        assert_eq!(kelly_fraction(0.01, 0.0), 0.0);
+        assert_eq!(kelly_fraction(f64::NAN, 0.04), 0.0);
+        assert_eq!(kelly_fraction(0.01, f64::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn kelly_sizes_a_small_aggressive_portfolio() {
+        let months = months();
+        let dates: Vec<&str> = months.iter().map(String::as_str).collect();
+        let mut stocks = Vec::new();
+        for (i, (ticker, sector)) in [("KLYA", "Technology"), ("KLYB", "Energy"), ("KLYC", "Utilities")].iter().enumerate() {
+            crate::stocks::insert_monthly_series(ticker, &dates, &series(40.0, 0.02 - i as f64 * 0.005, 0.04, 0.9 + i as f64, i as f64));
+            let mut s = stock(ticker, 40.0, sector, 0.04);
+            s.historical_return = Some(60.0 - i as f64 * 10.0);
+            stocks.push(s);
+        }
+        let config = PortfolioConfig { kelly_sizing: true, strategy: AllocationStrategy::Proportional, ..PortfolioConfig::default() };
+        let budget = 20_000.0;
+        let alloc_budget = config.alloc_budget(budget, RiskLevel::Aggressive);
+
+        let plan = build_portfolio(&stocks, budget, RiskLevel::Aggressive, &config, &no_points(), None, None);
+        assert_eq!(plan.strategy, "kelly");
+        assert!(cost(plan.positions(), &stocks) <= alloc_budget);
+        for (ticker, qty) in plan.positions() {
+            assert!(40.0 * *qty as f64 <= alloc_budget * KELLY_MAX_POSITION_FRACTION + 1e-9, "{} over the per-name cap", ticker);
+        }
+
+        // Only aggressive clients are Kelly-sized
+        let plan = build_portfolio(&stocks, budget, RiskLevel::Moderate, &config, &no_points(), None, None);
+        assert_eq!(plan.strategy, "proportional");
+        // A candidate without monthly data falls back to the standard weighting
+        let mut missing = stocks.clone();
+        missing.push(stock("KLYX", 40.0, "Financials", 0.04));
+        missing[3].historical_return = Some(90.0);
+        let plan = build_portfolio(&missing, budget, RiskLevel::Aggressive, &config, &no_points(), None, None);
+        assert_eq!(plan.strategy, "proportional");
+    }
+
+    #[test]
+    fn dust_positions_are_consolidated() {
+        let ranked = vec![
+            stock("DSTA", 100.0, "Technology", 0.02),
+            stock("DSTB", 50.0, "Energy", 0.02),
+            stock("DSTC", 5.0, "Utilities", 0.02),
+            stock("DSTD", 3.0, "Consumer", 0.02),
+        ];
+        let budget = 10_000.0;
+        let mut portfolio = vec![
+            ("DSTA".to_string(), 60),
+            ("DSTB".to_string(), 50),
+            ("DSTC".to_string(), 2),
+            ("DSTD".to_string(), 1),
+        ];
+        drop_dust_positions(&mut portfolio, &ranked, budget, 0.01);
+        // $10 and $3 positions are under 1% of $10,000; their cash (plus
+        // what was left over) goes to the top-ranked holding
+        assert_eq!(portfolio, vec![("DSTA".to_string(), 75), ("DSTB".to_string(), 50)]);
+        assert!(cost(&portfolio, &ranked) <= budget);
+
+        // An all-dust portfolio keeps its largest position
+        let mut portfolio = vec![("DSTC".to_string(), 2), ("DSTD".to_string(), 1)];
+        drop_dust_positions(&mut portfolio, &ranked, budget, 0.01);
+        assert_eq!(portfolio[0].0, "DSTC");
+        assert_eq!(portfolio.len(), 1);
+        assert!(cost(&portfolio, &ranked) <= budget);
+    }
+
+    #[test]
+    fn max_positions_from_config_caps_every_strategy() {
+        let dir = std::env::temp_dir().join(format!("quant_proj_max_positions_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("portfolio_config.json");
+        std::fs::write(&path, r#"{"max_positions": 5, "min_position_fraction": 0.0}"#).unwrap();
+        let loaded = PortfolioConfig::load(path.to_str().unwrap());
+        assert_eq!(loaded.max_positions, 5);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Cheap stocks so greedy can afford far more than five names
+        let mut stocks = universe();
+        for s in &mut stocks {
+            s.price = 10.0;
+        }
+        for strategy in [
+            AllocationStrategy::Greedy,
+            AllocationStrategy::SectorGreedy,
+            AllocationStrategy::Concentrated,
+            AllocationStrategy::Proportional,
+            AllocationStrategy::MeanVariance,
+            AllocationStrategy::MarketCap,
+            AllocationStrategy::RiskAdjusted,
+            AllocationStrategy::Income,
+        ] {
+            let config = PortfolioConfig { strategy, ..loaded.clone() };
+            for budget in [2_000.0, 50_000.0] {
+                for risk in [RiskLevel::Conservative, RiskLevel::Aggressive] {
+                    let plan = build_portfolio(&stocks, budget, risk, &config, &no_points(), None, None);
+                    assert!(!plan.positions().is_empty());
+                    assert!(plan.positions().len() <= 5, "{} at ${}: {} positions", strategy.name(), budget, plan.positions().len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn points_decay_once_per_allocation() {
+        let mut store = PointsStore::default();
+        store.decay_factor = 0.5;
+        store.add_score("IDLE", 8.0);
+        store.add_sector_score("Utilities", 4.0);
+        let points: SharedPointsStore = std::sync::Arc::new(std::sync::RwLock::new(store));
+        let mut stocks = vec![stock("DCYA", 20.0, "Technology", 0.02), stock("DCYB", 30.0, "Energy", 0.02)];
+        for s in &mut stocks {
+            s.historical_return = Some(10.0);
+        }
+        let config = PortfolioConfig::default();
+
+        // Scores the allocation doesn't touch fade by exactly one factor per call
+        build_weighted_portfolio(&stocks, 5_000.0, 2, AllocationStrategy::Proportional, &config, &points);
+        assert_eq!(points.read().unwrap().get_score("IDLE"), 4.0);
+        assert_eq!(points.read().unwrap().get_sector_score("Utilities"), 2.0);
+        build_weighted_portfolio(&stocks, 5_000.0, 2, AllocationStrategy::Concentrated, &config, &points);
+        assert_eq!(points.read().unwrap().get_score("IDLE"), 2.0);
+    }
+
+    #[test]
+    fn nan_prices_and_returns_sort_without_panicking() {
+        let mut stocks: Vec<Stock> = ["NANA", "NANB", "NANC", "NAND"]
+            .iter()
+            .map(|t| stock(t, 20.0, "Industrials", 0.02))
+            .collect();
+        stocks[0].historical_return = Some(f64::NAN);
+        stocks[1].historical_return = Some(15.0);
+        stocks[2].historical_return = Some(5.0);
+        stocks[3].price = f64::NAN;
+        stocks[3].historical_return = Some(30.0);
+
+        let mut values = [3.0, f64::NAN, -1.0, f64::NAN, 2.0];
+        values.sort_by(|a, b| cmp_f64(*a, *b));
+        assert_eq!(&values[..3], &[-1.0, 2.0, 3.0]);
+        assert!(values[3].is_nan() && values[4].is_nan());
+
+        // A NaN return ranks like a missing one, after every real return
+        let ranked: Vec<String> = rank_stocks(&stocks[..3], &PortfolioConfig::default())
+            .into_iter().map(|s| s.ticker).collect();
+        assert_eq!(ranked, vec!["NANB", "NANC", "NANA"]);
+
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let eligible = filter_stocks_by_profile(&stocks, &client, &PortfolioConfig::default());
+        assert!(eligible.iter().all(|s| s.ticker != "NAND"));
+
+        let config = PortfolioConfig::default();
+        let first = build_portfolio(&stocks, 10_000.0, RiskLevel::Aggressive, &config, &no_points(), None, None);
+        let again = build_portfolio(&stocks, 10_000.0, RiskLevel::Aggressive, &config, &no_points(), None, None);
+        assert_eq!(first.positions(), again.positions());
+        assert!(first.positions().iter().all(|(t, _)| t != "NAND"));
+    }
+
+    #[test]
+    fn top_up_improves_utilization_within_budget() {
+        let mut stocks = vec![
+            stock("TOPA", 300.0, "Technology", 0.02),
+            stock("TOPB", 45.0, "Energy", 0.02),
+            stock("TOPC", 7.0, "Utilities", 0.02),
+        ];
+        stocks[0].historical_return = Some(12.0);
+        stocks[1].historical_return = Some(4.0);
+        // Cheapest, but a loser: never topped up
+        stocks[2].historical_return = Some(-3.0);
+
+        let mut portfolio = vec![("TOPA".to_string(), 2)];
+        let before = portfolio_utilization(&portfolio, &stocks, 1_000.0);
+        assert!((before - 0.6).abs() < 1e-9);
+        top_up_utilization(&mut portfolio, &stocks, 1_000.0, 0.95, 2);
+        let after = portfolio_utilization(&portfolio, &stocks, 1_000.0);
+        assert!(after > before && after <= 1.0, "{} -> {}", before, after);
+        assert_eq!(portfolio, vec![("TOPA".to_string(), 2), ("TOPB".to_string(), 8)]);
+
+        // Without room for a new position only held stocks are topped up
+        let mut full = vec![("TOPA".to_string(), 2)];
+        top_up_utilization(&mut full, &stocks, 1_000.0, 0.95, 1);
+        assert_eq!(full, vec![("TOPA".to_string(), 3)]);
+    }
+
+    #[test]
+    fn sector_greedy_spreads_a_cheap_universe() {
+        let mut stocks: Vec<Stock> = [
+            ("GRA", 5.0, "Technology"), ("GRB", 6.0, "Technology"), ("GRC", 7.0, "Technology"),
+            ("GRD", 8.0, "Technology"), ("GRE", 20.0, "Energy"), ("GRF", 25.0, "Utilities"),
+            ("GRG", 30.0, "Healthcare"),
+        ].iter().map(|(t, p, sector)| stock(t, *p, sector, 0.02)).collect();
+        for s in &mut stocks {
+            s.historical_return = Some(8.0);
+        }
+        let sectors = |portfolio: &[(String, i32)]| -> HashSet<String> {
+            portfolio.iter()
+                .map(|(t, _)| stocks.iter().find(|s| &s.ticker == t).unwrap().sector.clone())
+                .collect()
+        };
+
+        let plain = build_greedy_portfolio(&stocks, 3_000.0, 4, false, PricingBasis::Current, ReductionPolicy::default());
+        let spread = build_greedy_portfolio(&stocks, 3_000.0, 4, true, PricingBasis::Current, ReductionPolicy::default());
+        assert_eq!(sectors(&plain).len(), 1);
+        assert_eq!(sectors(&spread).len(), 4);
+        for portfolio in [&plain, &spread] {
+            assert!(portfolio.len() <= 4);
+            assert!(cost(portfolio, &stocks) <= 3_000.0);
+        }
+    }
+
+    #[test]
+    fn huge_over_budget_portfolio_converges_in_few_steps() {
+        let stocks = vec![
+            stock("HUGA", 1.0, "Technology", 0.02),
+            stock("HUGB", 2.0, "Energy", 0.02),
+            stock("HUGC", 100.0, "Utilities", 0.02),
+        ];
+        for policy in [ReductionPolicy::MostShares, ReductionPolicy::MostExpensive, ReductionPolicy::Proportional] {
+            // Two tied share counts made the most-shares pass alternate one
+            // share at a time
+            let mut portfolio = vec![
+                ("HUGA".to_string(), 1_000_000_000),
+                ("HUGB".to_string(), 1_000_000_000),
+                ("HUGC".to_string(), 5),
+            ];
+            let steps = force_within_budget(&mut portfolio, &stocks, 10_000.0, policy);
+            let spent = cost(&portfolio, &stocks);
+            assert!(steps <= 64, "{:?}: {} steps", policy, steps);
+            assert!(spent <= 10_000.0, "{:?}: spent {}", policy, spent);
+            assert!(spent > 10_000.0 - 100.0, "{:?}: spent only {}", policy, spent);
+        }
+    }
+
+    #[test]
+    fn annualized_basis_reorders_risk_adjusted_picks() {
+        let mut stocks = vec![stock("ANNA", 50.0, "Technology", 0.025), stock("ANNB", 50.0, "Utilities", 0.0)];
+        stocks[0].historical_return = Some(400.0);
+        stocks[1].historical_return = Some(100.0);
+        let pick = |return_basis: ReturnBasis| {
+            let config = PortfolioConfig { strategy: AllocationStrategy::RiskAdjusted, return_basis, ..PortfolioConfig::default() };
+            let plan = build_portfolio(&stocks, 10_000.0, RiskLevel::Aggressive, &config, &no_points(), Some(10.0), Some(1));
+            plan.positions().iter().map(|(t, _)| t.clone()).collect::<Vec<_>>()
+        };
+        // 400% / 0.035 beats 100% / 0.01 in total terms, but compounding over
+        // ten years (17.5% vs 7.2% a year) flips the risk-adjusted order
+        assert_eq!(annualized_return(100.0, 10.0), (2f64.powf(0.1) - 1.0) * 100.0);
+        assert_eq!(pick(ReturnBasis::Total), vec!["ANNA"]);
+        assert_eq!(pick(ReturnBasis::Annualized), vec!["ANNB"]);
+    }
+
+    #[test]
+    fn sleeve_spend_tracks_target_fractions() {
+        let stocks = universe();
+        let sleeve = |name: &str, budget_fraction: f64, min_volatility: Option<f64>, max_volatility: Option<f64>| Sleeve {
+            name: name.to_string(), budget_fraction, min_volatility, max_volatility, min_market_cap: None, max_market_cap: None,
+        };
+        let config = PortfolioConfig {
+            sleeves: vec![sleeve("stable", 0.7, None, Some(0.02)), sleeve("growth", 0.3, Some(0.03), None)],
+            // The growth sleeve's top picks are all technology; sector caps
+            // are covered elsewhere and would hide the sleeve split here
+            max_sector_fraction: 1.0,
+            ..PortfolioConfig::default()
+        };
+        let budget = 100_000.0;
+        let portfolio = build_tiered_portfolio(&stocks, budget, 10, AllocationStrategy::Proportional, &config, &no_points());
+        let spent_where = |admits: &dyn Fn(&Stock) -> bool| -> f64 {
+            portfolio.iter()
+                .map(|(t, q)| (stocks.iter().find(|s| &s.ticker == t).unwrap(), q))
+                .filter(|(s, _)| admits(s))
+                .map(|(s, q)| s.price * *q as f64)
+                .sum()
+        };
+        let stable = spent_where(&|s| s.volatility <= 0.02) / budget;
+        let growth = spent_where(&|s| s.volatility >= 0.03) / budget;
+        assert!((stable - 0.7).abs() < 0.1, "stable sleeve took {:.2}", stable);
+        assert!((growth - 0.3).abs() < 0.1, "growth sleeve took {:.2}", growth);
+        assert!(cost(&portfolio, &stocks) <= budget);
+    }
+
+    #[test]
+    fn larger_caps_get_proportionally_more_budget() {
+        let mut stocks = [
+            stock("MCPA", 10.0, "Technology", 0.02),
+            stock("MCPB", 10.0, "Energy", 0.02),
+            stock("MCPC", 10.0, "Utilities", 0.02),
+            stock("MCPD", 10.0, "Healthcare", 0.02),
+        ];
+        for (s, cap) in stocks.iter_mut().zip([600_000, 300_000, 100_000, 0]) {
+            s.market_cap = cap;
+            s.historical_return = Some(5.0);
+        }
+        let refs: Vec<&Stock> = stocks.iter().collect();
+        let weights = calculate_marketcap_weights(&refs);
+        // The missing cap counts as the smallest known one
+        assert_eq!(weights, vec![0.6 / 1.1, 0.3 / 1.1, 0.1 / 1.1, 0.1 / 1.1]);
+
+        let config = PortfolioConfig { max_sector_fraction: 1.0, ..PortfolioConfig::default() };
+        let portfolio = build_weighted_portfolio(&stocks[..3], 10_000.0, 3, AllocationStrategy::MarketCap, &config, &no_points());
+        let qty = |t: &str| portfolio.iter().find(|(p, _)| p == t).map_or(0, |(_, q)| *q);
+        assert!(qty("MCPA") > qty("MCPB") && qty("MCPB") > qty("MCPC"), "{:?}", portfolio);
+        assert!((qty("MCPA") as f64 / qty("MCPC") as f64 - 6.0).abs() < 0.5, "{:?}", portfolio);
+    }
+
+    #[test]
+    fn alloc_budget_scales_with_risk_level() {
+        let config = PortfolioConfig::default();
+        let budget = 100_000.0;
+        let alloc = |risk| config.alloc_budget(budget, risk);
+        assert!(alloc(RiskLevel::Conservative) < alloc(RiskLevel::Moderate));
+        assert!(alloc(RiskLevel::Moderate) < alloc(RiskLevel::Aggressive));
+        for risk in [RiskLevel::Conservative, RiskLevel::Moderate, RiskLevel::Aggressive] {
+            assert!((alloc(risk) - budget * config.budget_spend_fraction * risk.equity_fraction()).abs() < 1e-6);
+        }
+
+        let stocks = universe();
+        let spent = |risk| cost(build_portfolio(&stocks, budget, risk, &config, &no_points(), None, None).positions(), &stocks);
+        let conservative = spent(RiskLevel::Conservative);
+        assert!(conservative <= alloc(RiskLevel::Conservative) + 1e-6, "conservative spent ${:.2}", conservative);
+        assert!(spent(RiskLevel::Aggressive) > conservative);
+    }
+
+    #[test]
+    fn allocation_learns_into_volatility_buckets() {
+        let thresholds = VolThresholds::default();
+        assert_eq!(thresholds.bucket(0.01), "low");
+        assert_eq!(thresholds.bucket(0.03), "medium");
+        assert_eq!(thresholds.bucket(0.05), "high");
+
+        let mut stocks = [
+            stock("VBKL", 10.0, "Utilities", 0.01),
+            stock("VBKM", 10.0, "Energy", 0.04),
+            stock("VBKH", 10.0, "Technology", 0.09),
+        ];
+        for s in stocks.iter_mut() {
+            s.historical_return = Some(10.0);
+        }
+        let config = PortfolioConfig { max_sector_fraction: 1.0, ..PortfolioConfig::default() };
+        let points = no_points();
+        build_weighted_portfolio(&stocks, 3_000.0, 3, AllocationStrategy::Proportional, &config, &points);
+        let store = points.read().unwrap();
+        for (bucket, ticker) in [("low", "VBKL"), ("medium", "VBKM"), ("high", "VBKH")] {
+            let top = store.top_tickers(bucket, 5);
+            assert_eq!(top.len(), 1, "{} bucket: {:?}", bucket, top);
+            assert_eq!(top[0], (ticker.to_string(), store.get_score(ticker)));
+        }
+    }
+
+    #[test]
+    fn exact_exclusion_keeps_name_only_matches() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000. She avoids technology.");
+        let mut stocks = vec![
+            stock("EXSA", 20.0, "Technology", 0.02),
+            stock("EXSB", 20.0, "Financials", 0.02),
+            stock("EXSC", 20.0, "Software", 0.02),
+            stock("EXSD", 20.0, "Utilities", 0.02),
+        ];
+        stocks[1].name = "Fintech Holdings".to_string();
+        let kept = |strictness| -> Vec<String> {
+            let config = PortfolioConfig { exclusion_strictness: strictness, ..PortfolioConfig::default() };
+            filter_stocks_by_profile(&stocks, &client, &config).into_iter().map(|s| s.ticker).collect()
+        };
+        assert_eq!(kept(ExclusionStrictness::Exact), vec!["EXSB", "EXSC", "EXSD"]);
+        assert_eq!(kept(ExclusionStrictness::Substring), vec!["EXSB", "EXSC", "EXSD"]);
+        assert_eq!(kept(ExclusionStrictness::Synonym), vec!["EXSD"]);
+    }
+
+    #[test]
+    fn zero_price_stock_is_left_out_of_features_and_allocation() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let mut stocks = vec![
+            stock("ZPRA", 25.0, "Energy", 0.02),
+            stock("ZPRB", 0.0, "Utilities", 0.90),
+            stock("ZPRC", 40.0, "Healthcare", 0.02),
+        ];
+        for s in stocks.iter_mut() {
+            s.historical_return = Some(12.0);
+        }
+        assert!(!stocks[1].has_valid_price());
+
+        let config = PortfolioConfig::default();
+        let eligible = filter_stocks_by_profile(&stocks, &client, &config);
+        assert!(eligible.iter().all(|s| s.ticker != "ZPRB"));
+        let features = universe_features(&eligible, &PointsStore::default());
+        assert_eq!(features.count, 2);
+        // The zero-price stock's outlying volatility does not leak in
+        assert!((features.avg_vol - 0.02).abs() < 1e-12);
+
+        let plan = build_portfolio(&eligible, 10_000.0, client.risk_tolerance, &config, &no_points(), None, None);
+        assert!(!plan.positions().is_empty());
+        assert!(plan.positions().iter().all(|(t, _)| t != "ZPRB"));
+    }
+
+    #[test]
+    fn requested_holdings_set_the_position_count() {
+        let five = profile("Ann Lee is 30 years old with a budget of $100,000. Keep it to 5 names.");
+        let twenty = profile("Ann Lee is 30 years old with a budget of $100,000. She wants a diversified portfolio of about 20 stocks.");
+        assert_eq!(five.target_positions, Some(5));
+        assert_eq!(twenty.target_positions, Some(20));
+        assert_eq!(profile("Ann Lee is 30 years old with a budget of $100,000.").target_positions, None);
+
+        let stocks = universe();
+        // Proportional funds every target; concentrated may leave one out
+        let config = PortfolioConfig {
+            strategy: AllocationStrategy::Proportional,
+            max_sector_fraction: 1.0,
+            ..PortfolioConfig::default()
+        };
+        let held = |client: &InvestorProfile| {
+            build_portfolio(&stocks, client.budget, RiskLevel::Aggressive, &config, &no_points(), None, client.target_positions)
+                .positions().len()
+        };
+        assert_eq!(held(&five), 5);
+        // More than the universe holds: clamped to what is available
+        let cap = config.max_positions.min(stocks.len());
+        assert!(held(&twenty) > 5 && held(&twenty) <= cap, "{} positions", held(&twenty));
+    }
+
+    #[test]
+    fn dominant_weight_is_capped_and_the_rest_spread() {
+        // One outlier holds 91% before bounding
+        let raw = [0.91, 0.04, 0.03, 0.015, 0.005];
+        let bounded = bound_weights(&raw, 0.02, 0.30);
+        assert!((bounded.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(bounded.iter().all(|w| *w <= 0.30 + 1e-9), "{:?}", bounded);
+        assert!(bounded.iter().all(|w| *w >= 0.02 - 1e-9), "{:?}", bounded);
+        assert!((bounded[0] - 0.30).abs() < 1e-9);
+        // The excess went to the others, largest first
+        assert!(bounded[1] > raw[1] && bounded[1] >= bounded[2] && bounded[2] >= bounded[3]);
+
+        // Bounds that cannot all hold fall back towards equal weights
+        let tight = bound_weights(&[0.9, 0.1], 0.0, 0.3);
+        assert_eq!(tight, vec![0.5, 0.5]);
+
+        let mut stocks = [
+            stock("DOMA", 10.0, "Technology", 0.02),
+            stock("DOMB", 10.0, "Energy", 0.02),
+            stock("DOMC", 10.0, "Utilities", 0.02),
+            stock("DOMD", 10.0, "Healthcare", 0.02),
+        ];
+        for (s, ret) in stocks.iter_mut().zip([900.0, 10.0, 8.0, 6.0]) {
+            s.historical_return = Some(ret);
+        }
+        let config = PortfolioConfig { max_position_weight: 0.30, max_sector_fraction: 1.0, ..PortfolioConfig::default() };
+        let portfolio = build_weighted_portfolio(&stocks, 10_000.0, 4, AllocationStrategy::Proportional, &config, &no_points());
+        assert_eq!(portfolio.len(), 4);
+        let spend = |t: &str| portfolio.iter().find(|(p, _)| p == t).map_or(0.0, |(_, q)| *q as f64 * 10.0);
+        // Including the leftover cash swept into the top name
+        assert!(spend("DOMA") <= 10_000.0 * 0.30 + 1e-9, "{:?}", portfolio);
+        assert!(spend("DOMD") >= 1_000.0, "{:?}", portfolio);
+    }
+
+    #[test]
+    fn plan_rationale_matches_its_positions() {
+        let stocks = universe();
+        let config = PortfolioConfig { strategy: AllocationStrategy::Proportional, ..PortfolioConfig::default() };
+        let plan = build_portfolio(&stocks, 50_000.0, RiskLevel::Aggressive, &config, &no_points(), None, None);
+        assert_eq!(plan.strategy, "proportional");
+        assert_eq!(plan.rationale.len(), plan.positions().len());
+
+        let total = cost(plan.positions(), &stocks);
+        for (why, (ticker, qty)) in plan.rationale.iter().zip(plan.positions()) {
+            let stock = stocks.iter().find(|s| &s.ticker == ticker).unwrap();
+            assert_eq!((&why.ticker, why.quantity), (ticker, *qty));
+            assert!((why.weight - stock.price * *qty as f64 / total).abs() < 1e-9);
+            assert_eq!(why.expected_return, stock.historical_return);
+        }
+        assert!((plan.rationale.iter().map(|r| r.weight).sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn whitelist_limits_the_eligible_set() {
+        let dir = std::env::temp_dir().join(format!("quant_proj_universe_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("universe.txt");
+        std::fs::write(&path, "# focus list\nwlsa\n\nWLSC\n").unwrap();
+        let universe = load_universe(path.to_str().unwrap()).unwrap();
+        assert_eq!(universe, HashSet::from(["WLSA".to_string(), "WLSC".to_string()]));
+
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000.");
+        let stocks = [
+            stock("WLSA", 20.0, "Energy", 0.02),
+            stock("WLSB", 20.0, "Energy", 0.02),
+            stock("WLSC", 20.0, "Utilities", 0.02),
+            stock("WLSD", 20.0, "Healthcare", 0.02),
+        ];
+        let eligible = filter_stocks_within(&stocks, &client, &PortfolioConfig::default(), Some(&universe));
+        assert!(!eligible.is_empty());
+        assert!(eligible.iter().all(|s| universe.contains(&s.ticker)));
+        assert_eq!(filter_stocks_within(&stocks, &client, &PortfolioConfig::default(), None).len(), 4);
+
+        // An empty whitelist is ignored rather than excluding everything
+        std::fs::write(&path, "# nothing yet\n").unwrap();
+        assert!(load_universe(path.to_str().unwrap()).is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reduction_policies_shape_the_trim_differently() {
+        let stocks = [
+            stock("RDPC", 10.0, "Consumer", 0.02),
+            stock("RDPM", 50.0, "Energy", 0.02),
+            stock("RDPX", 200.0, "Technology", 0.02),
+        ];
+        // $4,000 of positions against a $2,000 budget
+        let over_budget = || vec![("RDPC".to_string(), 100), ("RDPM".to_string(), 20), ("RDPX".to_string(), 10)];
+        let trimmed = |policy| {
+            let mut portfolio = over_budget();
+            force_within_budget(&mut portfolio, &stocks, 2_000.0, policy);
+            assert!(cost(&portfolio, &stocks) <= 2_000.0, "{:?}: {:?}", policy, portfolio);
+            portfolio.into_iter().map(|(_, q)| q).collect::<Vec<_>>()
+        };
+
+        // Levels the largest counts down together, gutting the cheap name
+        assert_eq!(trimmed(ReductionPolicy::MostShares), vec![8, 8, 7]);
+        // Drops the expensive name and leaves the rest alone
+        assert_eq!(trimmed(ReductionPolicy::MostExpensive), vec![100, 20]);
+        // Halves everything, keeping the 10:2:1 shape
+        assert_eq!(trimmed(ReductionPolicy::Proportional), vec![50, 10, 5]);
+    }
+
+    #[test]
+    fn retuned_thresholds_reclassify_a_borderline_stock() {
+        let borderline = 0.035;
+        let default = VolThresholds::default();
+        let looser: VolThresholds = serde_json::from_value(json!({ "low": 0.04 })).unwrap();
+        assert_eq!(looser.medium, default.medium);
+
+        assert_eq!(default.bucket(borderline), "medium");
+        assert!(!matches_risk_tolerance(borderline, RiskLevel::Conservative, default));
+        assert_eq!(looser.bucket(borderline), "low");
+        assert!(matches_risk_tolerance(borderline, RiskLevel::Conservative, looser));
+
+        // The filter reads the same thresholds
+        let client = profile("Ann Lee is 70 years old with a budget of $10,000. She is very conservative.");
+        assert!(matches!(client.risk_tolerance, RiskLevel::Conservative));
+        let stocks = [stock("VTHB", 20.0, "Utilities", borderline)];
+        let eligible = |vol_thresholds| filter_stocks_within(&stocks, &client, &PortfolioConfig { vol_thresholds, ..PortfolioConfig::default() }, None).len();
+        assert_eq!(eligible(default), 0);
+        assert_eq!(eligible(looser), 1);
+    }
+
+    #[test]
+    fn parsed_sector_cap_bounds_allocation() {
+        let client = profile("Ann Lee is 30 years old with a budget of $10,000. Keep technology under 20% of the portfolio and avoid energy.");
+        assert_eq!(client.sector_caps, HashMap::from([("Technology".to_string(), 0.2)]));
+        // Capped, not banned; the excluded sector stays excluded
+        assert_eq!(client.excluded_sectors, vec!["Energy".to_string()]);
+
+        let mut stocks = vec![
+            stock("PSCA", 10.0, "Technology", 0.02),
+            stock("PSCB", 10.0, "Technology", 0.02),
+            stock("PSCC", 10.0, "Healthcare", 0.02),
+            stock("PSCD", 10.0, "Utilities", 0.02),
+            stock("PSCE", 10.0, "Financials", 0.02),
+        ];
+        for (s, ret) in stocks.iter_mut().zip([90.0, 80.0, 20.0, 15.0, 10.0]) {
+            s.historical_return = Some(ret);
+        }
+        let config = PortfolioConfig { max_sector_fraction: 1.0, ..PortfolioConfig::default() }.with_sector_caps(&client.sector_caps);
+        let portfolio = build_weighted_portfolio(&stocks, 10_000.0, 5, AllocationStrategy::Proportional, &config, &no_points());
+        let tech: f64 = portfolio.iter().filter(|(t, _)| t == "PSCA" || t == "PSCB").map(|(_, q)| *q as f64 * 10.0).sum();
+        assert!(tech > 0.0, "{:?}", portfolio);
+        assert!(tech <= 2_000.0 + 1e-9, "tech ${:.2}: {:?}", tech, portfolio);
+    }
+
+    #[test]
+    fn risk_adjusted_ranking_prefers_efficient_names() {
+        let mut stocks = vec![stock("RAJH", 30.0, "Technology", 0.12), stock("RAJL", 30.0, "Utilities", 0.01)];
+        stocks[0].historical_return = Some(60.0);
+        stocks[1].historical_return = Some(15.0);
+        let order = |strategy| -> Vec<String> {
+            let config = PortfolioConfig { strategy, ..PortfolioConfig::default() };
+            rank_stocks(&stocks, &config).into_iter().map(|s| s.ticker).collect()
+        };
+        // 60 / 0.12 = 500 per unit of volatility against 15 / 0.01 = 1,500
+        assert_eq!(order(AllocationStrategy::RiskAdjusted), vec!["RAJL", "RAJH"]);
+        assert_eq!(order(AllocationStrategy::Proportional), vec!["RAJH", "RAJL"]);
+    }
+
+    #[test]
+    fn portfolio_stats_weight_by_position_value() {
+        let mut low = stock("STSA", 10.0, "Utilities", 0.01);
+        low.historical_return = Some(5.0);
+        let mut high = stock("STSB", 20.0, "Technology", 0.04);
+        high.historical_return = Some(20.0);
+        let stocks = [low, high];
+        // $300 in STSA and $100 in STSB: weights 0.75 / 0.25
+        let portfolio = vec![("STSA".to_string(), 30u32), ("STSB".to_string(), 5u32)];
+        let stats = portfolio_stats(&portfolio, &stocks);
+        assert!((stats.expected_return.unwrap() - 8.75).abs() < 1e-9);
+        assert!((stats.volatility - 0.0175).abs() < 1e-9);
+
+        // Positions without a known return don't dilute the expected return
+        let mut stocks = stocks;
+        stocks[1].historical_return = None;
+        let stats = portfolio_stats(&portfolio, &stocks);
+        assert_eq!(stats.expected_return, Some(5.0));
+        assert!((stats.volatility - 0.0175).abs() < 1e-9);
+
+        let empty: Vec<(String, u32)> = Vec::new();
+        let stats = portfolio_stats(&empty, &stocks);
+        assert_eq!((stats.expected_return, stats.volatility), (None, 0.0));
+    }
+
+    #[test]
+    fn income_mode_prefers_higher_yield_names() {
+        let mut stocks = vec![
+            stock("INCA", 20.0, "Technology", 0.02),
+            stock("INCB", 20.0, "Utilities", 0.02),
+            stock("INCC", 20.0, "Energy", 0.02),
+            stock("INCD", 20.0, "Healthcare", 0.02),
+        ];
+        for (s, ret) in stocks.iter_mut().zip([40.0, 8.0, 12.0, 30.0]) {
+            s.historical_return = Some(ret);
+        }
+        stocks[1].dividend_yield = Some(0.06);
+        stocks[2].dividend_yield = Some(0.04);
+        stocks[3].dividend_yield = Some(0.01);
+        let config = PortfolioConfig { max_sector_fraction: 1.0, ..PortfolioConfig::default() }.with_income_target(500.0);
+
+        // Yield first; a stock without yield data ranks after every yielding one
+        let ranked: Vec<String> = rank_stocks(&stocks, &config).into_iter().map(|s| s.ticker).collect();
+        assert_eq!(ranked, vec!["INCB", "INCC", "INCD", "INCA"]);
+
+        let plan = build_portfolio(&stocks, 10_000.0, RiskLevel::Aggressive, &config, &no_points(), None, Some(2));
+        assert_eq!(plan.strategy, "income");
+        let mut held: Vec<&str> = plan.positions().iter().map(|(t, _)| t.as_str()).collect();
+        held.sort();
+        assert_eq!(held, vec!["INCB", "INCC"]);
+
+        // No yield data at all: the standard allocator picks by return
+        for s in stocks.iter_mut() {
+            s.dividend_yield = None;
+        }
+        let plan = build_portfolio(&stocks, 10_000.0, RiskLevel::Aggressive, &config, &no_points(), None, Some(2));
+        assert_ne!(plan.strategy, "income");
+        let mut held: Vec<&str> = plan.positions().iter().map(|(t, _)| t.as_str()).collect();
+        held.sort();
+        assert_eq!(held, vec!["INCA", "INCD"]);
+    }
+
+    #[test]
+    fn universe_features_of_a_known_universe() {
+        let mut stocks = vec![
+            stock("UFTA", 10.0, "Energy", 0.01),
+            stock("UFTB", 20.0, "Utilities", 0.02),
+            stock("UFTC", 30.0, "Technology", 0.06),
+        ];
+        stocks[0].market_cap = 1_000;
+        stocks[1].market_cap = 100_000;
+        let mut points = PointsStore::default();
+        points.add_score("UFTA", 3.0);
+        points.add_score("UFTC", 6.0);
+
+        let features = universe_features(&stocks, &points);
+        assert_eq!(features.count, 3);
+        assert!((features.avg_vol - 0.03).abs() < 1e-12);
+        // Only stocks with a market cap count: mean of ln(1e3) and ln(1e5) is ln(1e4)
+        assert!((features.avg_logcap.unwrap() - 10_000f64.ln()).abs() < 1e-9);
+        assert!((features.avg_pts_score - 3.0).abs() < 1e-12);
+
+        for s in stocks.iter_mut() {
+            s.market_cap = 0;
+        }
+        assert_eq!(universe_features(&stocks, &points).avg_logcap, None);
+        let empty = universe_features(&[], &points);
+        assert_eq!((empty.count, empty.avg_vol, empty.avg_pts_score), (0, 0.0, 0.0));
+    }
+}
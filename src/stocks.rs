@@ -8,13 +8,22 @@
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fs;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock, RwLock};
+use tracing::{info, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stock {
     pub ticker: String,
     pub price: f64,
+    /// Comma-separated sectors (see `sectors`). Caches from different fetch
+    /// scripts use `sector`, `sectors` or `industry`, as a string or a list.
+    #[serde(alias = "sectors", alias = "industry", deserialize_with = "deserialize_sector")]
     pub sector: String,
+    /// Optional exposure split across `sectors` (e.g. 0.7 Technology, 0.3
+    /// Consumer); see `sector_exposure`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sector_weights: HashMap<String, f64>,
     pub volatility: f64,
     #[serde(default)]
     pub name: String,
@@ -24,10 +33,32 @@ pub struct Stock {
     pub first_trading_date: Option<String>,
     #[serde(default)]
     pub last_trading_date: Option<String>,
+    /// Trailing annual dividend yield as a fraction (0.04 = 4%), if the cache has it
+    #[serde(default, alias = "yield")]
+    pub dividend_yield: Option<f64>,
     #[serde(skip)]
     pub historical_return: Option<f64>, // Actual return % during investment period
     #[serde(skip)]
     pub historical_start_price: Option<f64>, // Price at start of investment period
+    #[serde(skip)]
+    pub price_gap_flagged: bool, // Monthly series has an implausible single-month jump
+    #[serde(skip)]
+    pub max_drawdown: Option<f64>, // Worst peak-to-trough drop % during investment period (monthly data only)
+}
+
+/// Bounds on a single month-over-month return. Moves outside these usually
+/// signal a data error (unadjusted split, bad print) rather than a real move.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct PriceGapBounds {
+    pub max_gain_pct: f64,
+    pub max_loss_pct: f64,
+}
+
+impl Default for PriceGapBounds {
+    fn default() -> Self {
+        PriceGapBounds { max_gain_pct: 300.0, max_loss_pct: -80.0 }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -74,12 +105,49 @@ struct MonthlyPriceData {
     data_points: usize,
 }
 
+type PeriodCache = HashMap<String, HashMap<String, HistoricalData>>;
+type MonthlyCache = HashMap<String, MonthlyPriceData>;
+
 // Global cache for historical periods (legacy)
-static mut HISTORICAL_PERIODS_CACHE: Option<HashMap<String, HashMap<String, HistoricalData>>> = None;
+static HISTORICAL_PERIODS_CACHE: RwLock<Option<Arc<PeriodCache>>> = RwLock::new(None);
 // Global cache for monthly prices (new, faster approach)
-static mut MONTHLY_PRICES_CACHE: Option<HashMap<String, MonthlyPriceData>> = None;
+static MONTHLY_PRICES_CACHE: RwLock<Option<Arc<MonthlyCache>>> = RwLock::new(None);
+// `generated_at` of the most recently loaded cache file
+static CACHE_GENERATED_AT: RwLock<Option<chrono::NaiveDateTime>> = RwLock::new(None);
+
+/// Caches older than this trigger a staleness warning on load
+const CACHE_STALE_HOURS: i64 = 24;
+
+/// Snapshot of the loaded legacy period cache, if any
+fn periods_cache() -> Option<Arc<PeriodCache>> {
+    HISTORICAL_PERIODS_CACHE.read().ok()?.clone()
+}
+
+/// Snapshot of the loaded monthly price cache, if any
+fn monthly_cache() -> Option<Arc<MonthlyCache>> {
+    MONTHLY_PRICES_CACHE.read().ok()?.clone()
+}
+
+/// Which price portfolio sizing and cost math use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PricingBasis {
+    /// Latest cached price - what the evaluator values submissions at
+    #[default]
+    Current,
+    /// Price at the start of the investment period, for consistent backtests
+    HistoricalStart,
+}
 
 impl Stock {
+    /// Price under the given basis
+    pub fn price_for(&self, basis: PricingBasis) -> f64 {
+        match basis {
+            PricingBasis::Current => self.get_current_price(),
+            PricingBasis::HistoricalStart => self.get_purchase_price(),
+        }
+    }
+
     /// Get the price to use for portfolio quantity calculations.
     /// For backtesting competitions, use historical start price from the investment period.
     pub fn get_purchase_price(&self) -> f64 {
@@ -94,132 +162,542 @@ impl Stock {
     pub fn get_current_price(&self) -> f64 {
         self.price
     }
+
+    /// Fraction of this stock attributed to each of its sectors, summing to
+    /// 1.0. Uses `sector_weights` when present (unlisted sectors get 0),
+    /// otherwise splits equally across `sectors`.
+    pub fn sector_exposure(&self) -> Vec<(&str, f64)> {
+        let sectors = self.sectors();
+        let weights: Vec<f64> = sectors.iter()
+            .map(|s| self.sector_weights.get(*s).copied().filter(|w| w.is_finite() && *w > 0.0).unwrap_or(0.0))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            sectors.into_iter().zip(weights).map(|(s, w)| (s, w / total)).collect()
+        } else {
+            let equal = 1.0 / sectors.len().max(1) as f64;
+            sectors.into_iter().map(|s| (s, equal)).collect()
+        }
+    }
+
+    /// False when the current price is missing (0.0), negative or NaN,
+    /// e.g. after a failed price update
+    pub fn has_valid_price(&self) -> bool {
+        let price = self.get_current_price();
+        price.is_finite() && price > 0.0
+    }
+
+    /// Sectors this stock belongs to. Multi-sector entries are stored as a
+    /// comma-separated list (e.g. "Technology, Consumer").
+    pub fn sectors(&self) -> Vec<&str> {
+        self.sector
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+}
+
+/// Accept a sector as either a string or a list of strings; lists are joined
+/// into the comma-separated form `Stock::sectors` splits.
+fn deserialize_sector<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SectorField {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match SectorField::deserialize(deserializer)? {
+        SectorField::One(sector) => sector,
+        SectorField::Many(sectors) => sectors.join(", "),
+    })
+}
+
+/// Percentage return from `start` to `end`. None when either price is not a
+/// usable price (non-finite, non-positive start, or negative end).
+fn pct_return(start: f64, end: f64) -> Option<f64> {
+    if !start.is_finite() || !end.is_finite() || start <= 0.0 || end < 0.0 {
+        return None;
+    }
+    Some((end - start) / start * 100.0)
 }
 
 pub fn load_stocks_from_cache(cache_file: &str) -> Result<Vec<Stock>, Box<dyn Error>> {
-    println!("[CACHE] Loading stocks from cache: {}", cache_file);
+    info!("[CACHE] Loading stocks from cache: {}", cache_file);
     
     let contents = fs::read_to_string(cache_file)
         .map_err(|e| format!("Failed to read cache file '{}': {}. Run fetch_stocks.py first!", cache_file, e))?;
     
     let cache: StockCache = serde_json::from_str(&contents)?;
     
-    println!("[CACHE] Loaded {} stocks from cache (generated: {})", 
+    info!("[CACHE] Loaded {} stocks from cache (generated: {})", 
              cache.stocks.len(), 
              cache.metadata.generated_at);
+    record_cache_generated_at(&cache.metadata.generated_at);
     
     // Check for new monthly prices format (preferred)
     if let Some(monthly_data) = cache.monthly_prices {
-        println!("[CACHE] Using MONTHLY price format - {} stocks with monthly data", monthly_data.len());
+        info!("[CACHE] Using MONTHLY price format - {} stocks with monthly data", monthly_data.len());
         let total_datapoints: usize = monthly_data.values().map(|d| d.data_points).sum();
-        println!("[CACHE] Total monthly datapoints: {}", total_datapoints);
-        unsafe {
-            MONTHLY_PRICES_CACHE = Some(monthly_data);
+        info!("[CACHE] Total monthly datapoints: {}", total_datapoints);
+        if let Ok(mut cache) = MONTHLY_PRICES_CACHE.write() {
+            *cache = Some(Arc::new(monthly_data));
         }
+        clear_return_stats();
     } 
     // Fallback to old historical periods format
     else if let Some(periods) = cache.historical_periods {
-        println!("[CACHE] Using legacy PERIOD format - {} historical periods", periods.len());
-        println!("[WARN] Consider running 'python3 fetch_monthly_cache.py' for better accuracy!");
-        unsafe {
-            HISTORICAL_PERIODS_CACHE = Some(periods);
+        info!("[CACHE] Using legacy PERIOD format - {} historical periods", periods.len());
+        warn!("Consider running 'python3 fetch_monthly_cache.py' for better accuracy!");
+        if let Ok(mut cache) = HISTORICAL_PERIODS_CACHE.write() {
+            *cache = Some(Arc::new(periods));
         }
     } else {
-        println!("[WARN] No historical data in cache - will use API fallback");
+        warn!("No historical data in cache - will use API fallback");
     }
     
-    Ok(cache.stocks)
+    let mut stocks = dedup_stocks(cache.stocks);
+    reconcile_stale_prices(&mut stocks, &cache.metadata.generated_at, STALE_PRICE_FACTOR);
+    let report = validate_cache(&stocks);
+    report.log();
+    if report.invalid_fraction() > MAX_INVALID_CACHE_FRACTION {
+        return Err(format!(
+            "Cache '{}' has {} of {} invalid stocks (limit {:.0}%)",
+            cache_file, report.invalid_count(), report.total, MAX_INVALID_CACHE_FRACTION * 100.0
+        ).into());
+    }
+    Ok(stocks)
+}
+
+// A cached price this many times above/below the latest monthly close is treated as stale
+const STALE_PRICE_FACTOR: f64 = 5.0;
+// Monthly closes older than this (relative to the cache's generation) can't correct a price
+const RECENT_MONTHLY_WINDOW_MONTHS: u32 = 2;
+
+/// Replace cached prices that are off from the latest monthly close by more
+/// than `factor` (e.g. a failed price refresh left a years-old value) with
+/// that close, when it is recent. Returns the number of corrections.
+fn reconcile_stale_prices(stocks: &mut [Stock], generated_at: &str, factor: f64) -> usize {
+    let Some(cache) = monthly_cache() else { return 0 };
+    let generated = chrono::NaiveDateTime::parse_from_str(generated_at, "%Y-%m-%dT%H:%M:%S%.f")
+        .map(|t| t.date())
+        .unwrap_or_else(|_| chrono::Utc::now().date_naive());
+    let oldest_recent = generated
+        .checked_sub_months(chrono::Months::new(RECENT_MONTHLY_WINDOW_MONTHS))
+        .unwrap_or(generated)
+        .format("%Y-%m")
+        .to_string();
+
+    let mut corrected = 0;
+    for stock in stocks.iter_mut() {
+        let Some(data) = cache.get(&stock.ticker) else { continue };
+        let (Some(month), Some(&latest)) = (data.dates.last(), data.prices.last()) else { continue };
+        if *month < oldest_recent || !latest.is_finite() || latest <= 0.0 {
+            continue;
+        }
+        let price = stock.price;
+        let ratio = if price.is_finite() && price > 0.0 { (price / latest).max(latest / price) } else { f64::INFINITY };
+        if ratio > factor {
+            warn!("[CACHE] {} price ${:.2} is {:.1}x off its {} close ${:.2} - using the monthly close",
+                     stock.ticker, price, ratio, month, latest);
+            stock.price = latest;
+            corrected += 1;
+        }
+    }
+    if corrected > 0 {
+        info!("[CACHE] Corrected {} stale prices from monthly data", corrected);
+    }
+    corrected
+}
+
+// Refuse a cache file when more than this fraction of its stocks are malformed
+const MAX_INVALID_CACHE_FRACTION: f64 = 0.5;
+
+/// Problems found by `validate_cache`, by ticker
+#[derive(Debug, Default)]
+pub struct CacheValidationReport {
+    pub total: usize,
+    pub empty_tickers: usize,
+    pub invalid_prices: Vec<String>,
+    /// Tickers whose monthly dates are not strictly increasing
+    pub unordered_dates: Vec<String>,
+}
+
+impl CacheValidationReport {
+    /// Stocks with at least one problem
+    pub fn invalid_count(&self) -> usize {
+        let flagged: HashSet<&str> = self.invalid_prices.iter()
+            .chain(&self.unordered_dates)
+            .map(String::as_str)
+            .collect();
+        self.empty_tickers + flagged.len()
+    }
+
+    pub fn invalid_fraction(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.invalid_count() as f64 / self.total as f64
+    }
+
+    fn log(&self) {
+        if self.invalid_count() == 0 {
+            info!("[CACHE] Validated {} stocks, no problems found", self.total);
+            return;
+        }
+        warn!("[CACHE] {} of {} stocks failed validation: {} empty tickers, {} invalid prices, {} unordered monthly dates",
+                 self.invalid_count(), self.total, self.empty_tickers,
+                 self.invalid_prices.len(), self.unordered_dates.len());
+    }
+}
+
+/// Check each loaded stock has a ticker and a positive price, and that its
+/// monthly series (if loaded) has strictly increasing dates
+pub fn validate_cache(stocks: &[Stock]) -> CacheValidationReport {
+    validate_stocks(stocks, monthly_cache().as_deref())
+}
+
+fn validate_stocks(stocks: &[Stock], monthly: Option<&MonthlyCache>) -> CacheValidationReport {
+    let mut report = CacheValidationReport { total: stocks.len(), ..Default::default() };
+    for stock in stocks {
+        if stock.ticker.trim().is_empty() {
+            report.empty_tickers += 1;
+            continue;
+        }
+        if !stock.has_valid_price() {
+            report.invalid_prices.push(stock.ticker.clone());
+        }
+        let dates = monthly.and_then(|m| m.get(&stock.ticker)).map(|d| &d.dates);
+        if dates.is_some_and(|d| d.windows(2).any(|w| w[0] >= w[1])) {
+            report.unordered_dates.push(stock.ticker.clone());
+        }
+    }
+    report
+}
+
+/// How complete a cache entry is; used to pick between duplicate tickers
+fn data_completeness(stock: &Stock) -> u8 {
+    u8::from(stock.market_cap > 0) + u8::from(stock.first_trading_date.is_some())
+}
+
+/// Collapse duplicate ticker entries (e.g. from renamed-symbol merges),
+/// keeping the most complete one. Ties keep the first occurrence.
+fn dedup_stocks(stocks: Vec<Stock>) -> Vec<Stock> {
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut unique: Vec<Stock> = Vec::with_capacity(stocks.len());
+    for stock in stocks {
+        match index.get(&stock.ticker) {
+            Some(&i) => {
+                warn!("[CACHE] Duplicate ticker {} in cache", stock.ticker);
+                if data_completeness(&stock) > data_completeness(&unique[i]) {
+                    unique[i] = stock;
+                }
+            }
+            None => {
+                index.insert(stock.ticker.clone(), unique.len());
+                unique.push(stock);
+            }
+        }
+    }
+    unique
+}
+
+/// Remember when the loaded cache was generated and warn if it is stale.
+/// `generated_at` is written by the fetch scripts as a naive ISO timestamp.
+/// Returns the cache's age when the staleness warning fired.
+fn record_cache_generated_at(generated_at: &str) -> Option<chrono::Duration> {
+    let parsed = chrono::NaiveDateTime::parse_from_str(generated_at, "%Y-%m-%dT%H:%M:%S%.f");
+    let Ok(generated) = parsed else {
+        warn!("Could not parse cache generated_at '{}'", generated_at);
+        return None;
+    };
+    if let Ok(mut slot) = CACHE_GENERATED_AT.write() {
+        *slot = Some(generated);
+    }
+    let age = cache_age().filter(|age| age.num_hours() >= CACHE_STALE_HOURS)?;
+    warn!("Cache is stale: generated {} ({} hours ago, threshold {}h) - run 'python3 fetch_monthly_cache.py' to refresh",
+             generated_at, age.num_hours(), CACHE_STALE_HOURS);
+    Some(age)
+}
+
+/// Age of the loaded cache, if one has been loaded
+pub fn cache_age() -> Option<chrono::Duration> {
+    let generated = (*CACHE_GENERATED_AT.read().ok()?)?;
+    Some(chrono::Utc::now().naive_utc() - generated)
 }
 
 pub async fn prefetch_all_stocks() -> Result<Vec<Stock>, Box<dyn Error>> {
     // Try monthly cache first (preferred, faster, more accurate)
     if let Ok(stocks) = load_stocks_from_cache("stocks_cache_monthly.json") {
-        println!("[CACHE] Using monthly price cache (optimal)\n");
+        info!("[CACHE] Using monthly price cache (optimal)");
         return Ok(stocks);
     }
     
     // Fallback to legacy cache
     match load_stocks_from_cache("stocks_cache.json") {
         Ok(stocks) => {
-            println!("[CACHE] Using legacy period cache\n");
+            info!("[CACHE] Using legacy period cache");
             Ok(stocks)
         }
         Err(e) => {
-            println!("[WARN] No cache found: {}", e);
-            println!("[INFO] Run 'python3 fetch_monthly_cache.py' for best performance");
-            println!("[INFO] Or run 'python3 fetch_stocks.py' for legacy cache\n");
+            warn!("No cache found: {}", e);
+            info!("Run 'python3 fetch_monthly_cache.py' for best performance");
+            info!("Or run 'python3 fetch_stocks.py' for legacy cache");
             Err(e)
         }
     }
 }
 
-/// Get price for a specific stock on a specific date using monthly cache
-/// Uses binary search and linear interpolation for accuracy
+/// Most recent year covered by the loaded historical cache (monthly or
+/// legacy periods). Returns None when no historical data is loaded.
+pub fn latest_cached_year() -> Option<u32> {
+    if let Some(cache) = monthly_cache() {
+        return cache.values()
+            .filter_map(|d| d.dates.last())
+            .filter_map(|month| month.get(..4)?.parse::<u32>().ok())
+            .max();
+    }
+    if let Some(cache) = periods_cache() {
+        return cache.keys()
+            .filter_map(|key| parse_period_key(key))
+            .map(|(_, end)| chrono::Datelike::year(&end) as u32)
+            .max();
+    }
+    None
+}
+
+/// Add (or replace) one ticker's series in the shared monthly cache, so tests
+/// can exercise the cache-backed paths. Use tickers unique to the test.
+#[cfg(test)]
+pub fn insert_monthly_series(ticker: &str, dates: &[&str], prices: &[f64]) {
+    let mut slot = MONTHLY_PRICES_CACHE.write().unwrap_or_else(|e| e.into_inner());
+    let mut cache = slot.as_deref().cloned().unwrap_or_default();
+    cache.insert(ticker.to_string(), MonthlyPriceData {
+        dates: dates.iter().map(|d| d.to_string()).collect(),
+        prices: prices.to_vec(),
+        first_trading: String::new(),
+        last_trading: String::new(),
+        data_points: dates.len(),
+    });
+    *slot = Some(Arc::new(cache));
+    drop(slot);
+    // Only this ticker's statistics are stale; other tests' stay cached
+    if let Ok(mut stats) = return_stats_cache().write() {
+        stats.stats.remove(ticker);
+        stats.correlations.retain(|(a, b), _| a != ticker && b != ticker);
+    }
+}
+
+/// Day number (days since the common era) of the last day of a "YYYY-MM"
+/// month. The monthly cache stores month-end closes (see
+/// fetch_monthly_cache.py), so this is when each observation was taken.
+fn month_end_day(month: &str) -> Option<i32> {
+    use chrono::Datelike;
+    let first = chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()?;
+    let next = first.checked_add_months(chrono::Months::new(1))?;
+    Some(next.num_days_from_ce() - 1)
+}
+
+/// Get price for a specific stock on a specific date using monthly cache.
+/// Each monthly observation is dated to its month's last day, and the
+/// target's actual day is interpolated linearly between the observations
+/// either side of it (binary search over the sorted months), so month
+/// lengths and the target's day-of-month are both respected.
 fn get_monthly_price(ticker: &str, target_date: &str) -> Option<f64> {
-    let target_month = &target_date[..7]; // Extract "YYYY-MM"
+    let cache = monthly_cache()?;
+    monthly_price_at(cache.get(ticker)?, target_date)
+}
+
+/// Interpolated price of one monthly series on `target_date` ("YYYY-MM-DD")
+fn monthly_price_at(stock_data: &MonthlyPriceData, target_date: &str) -> Option<f64> {
+    use chrono::Datelike;
     let target = chrono::NaiveDate::parse_from_str(target_date, "%Y-%m-%d").ok()?;
-    
-    unsafe {
-        let cache = MONTHLY_PRICES_CACHE.as_ref()?;
-        let stock_data = cache.get(ticker)?;
-        
-        // Binary search for the month
-        match stock_data.dates.binary_search_by(|month| month.as_str().cmp(target_month)) {
-            // Exact month match
-            Ok(idx) => Some(stock_data.prices[idx]),
-            
-            // Month not found - interpolate between adjacent months
-            Err(idx) => {
-                if idx == 0 {
-                    // Before first data point
-                    Some(stock_data.prices[0])
-                } else if idx >= stock_data.dates.len() {
-                    // After last data point
-                    Some(*stock_data.prices.last()?)
-                } else {
-                    // Interpolate between months
-                    let before_month = &stock_data.dates[idx - 1];
-                    let after_month = &stock_data.dates[idx];
-                    
-                    let before_date = chrono::NaiveDate::parse_from_str(&format!("{}-01", before_month), "%Y-%m-%d").ok()?;
-                    let after_date = chrono::NaiveDate::parse_from_str(&format!("{}-01", after_month), "%Y-%m-%d").ok()?;
-                    
-                    let total_days = (after_date - before_date).num_days() as f64;
-                    let target_days = (target - before_date).num_days() as f64;
-                    let ratio = (target_days / total_days).clamp(0.0, 1.0);
-                    
-                    let interpolated = linear_interpolate(
-                        stock_data.prices[idx - 1],
-                        stock_data.prices[idx],
-                        ratio
-                    );
-                    
-                    Some(interpolated)
-                }
-            }
-        }
+    let target_day = target.num_days_from_ce();
+
+    // First observation taken on or after the target day
+    let idx = stock_data.dates.partition_point(|month| month_end_day(month).is_some_and(|day| day < target_day));
+    if idx == 0 {
+        // Before first data point
+        return stock_data.prices.first().copied();
+    }
+    if idx >= stock_data.dates.len() {
+        // After last data point
+        return stock_data.prices.last().copied();
+    }
+
+    let before_day = month_end_day(&stock_data.dates[idx - 1])?;
+    let after_day = month_end_day(&stock_data.dates[idx])?;
+    let ratio = ((target_day - before_day) as f64 / (after_day - before_day) as f64).clamp(0.0, 1.0);
+    Some(linear_interpolate(stock_data.prices[idx - 1], stock_data.prices[idx], ratio))
+}
+
+/// Month-over-month returns (as fractions, e.g. 0.05 = +5%) from the monthly
+/// cache, keyed by the month each return ends in ("YYYY-MM"). Returns None
+/// when the ticker has no monthly series.
+pub fn monthly_returns(ticker: &str) -> Option<Vec<(String, f64)>> {
+    let cache = monthly_cache()?;
+    let data = cache.get(ticker)?;
+
+    let returns = data.dates.windows(2)
+        .zip(data.prices.windows(2))
+        .filter(|(_, prices)| prices[0] > 0.0)
+        .map(|(months, prices)| (months[1].clone(), prices[1] / prices[0] - 1.0))
+        .collect();
+    Some(returns)
+}
+
+/// Monthly-return statistics derived from the monthly cache, computed on
+/// first use and kept until the cache file is reloaded
+#[derive(Default)]
+struct ReturnStatsCache {
+    /// ticker -> (mean, sample variance) of monthly returns
+    stats: HashMap<String, Option<(f64, f64)>>,
+    /// (ticker, ticker) in sorted order -> correlation
+    correlations: HashMap<(String, String), Option<f64>>,
+}
+
+static RETURN_STATS_CACHE: OnceLock<RwLock<ReturnStatsCache>> = OnceLock::new();
+
+fn return_stats_cache() -> &'static RwLock<ReturnStatsCache> {
+    RETURN_STATS_CACHE.get_or_init(Default::default)
+}
+
+/// Tickers whose statistics were computed rather than served from the cache,
+/// one entry per computation
+#[cfg(test)]
+static STATS_COMPUTED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+/// Drop derived statistics; called whenever the monthly cache is replaced
+fn clear_return_stats() {
+    if let Ok(mut cache) = return_stats_cache().write() {
+        *cache = ReturnStatsCache::default();
+    }
+}
+
+/// Mean and sample variance of a stock's monthly returns. None with fewer
+/// than 3 monthly returns.
+pub fn monthly_return_stats(ticker: &str) -> Option<(f64, f64)> {
+    if let Some(cached) = return_stats_cache().read().ok().and_then(|c| c.stats.get(ticker).copied()) {
+        return cached;
+    }
+    let returns: Vec<f64> = monthly_returns(ticker).unwrap_or_default().into_iter().map(|(_, r)| r).collect();
+    let stats = (returns.len() >= 3).then(|| {
+        let n = returns.len() as f64;
+        let mean = returns.iter().sum::<f64>() / n;
+        let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        (mean, variance)
+    });
+    #[cfg(test)]
+    STATS_COMPUTED.lock().unwrap_or_else(|e| e.into_inner()).push(ticker.to_string());
+    if let Ok(mut cache) = return_stats_cache().write() {
+        cache.stats.insert(ticker.to_string(), stats);
+    }
+    stats
+}
+
+/// Pearson correlation of two stocks' monthly returns over the months both
+/// have data for. Returns None without monthly data for either stock or
+/// with fewer than 3 overlapping months. Results are cached per pair.
+pub fn pairwise_correlation(a: &Stock, b: &Stock) -> Option<f64> {
+    let key = if a.ticker <= b.ticker {
+        (a.ticker.clone(), b.ticker.clone())
+    } else {
+        (b.ticker.clone(), a.ticker.clone())
+    };
+    if let Some(cached) = return_stats_cache().read().ok().and_then(|c| c.correlations.get(&key).copied()) {
+        return cached;
+    }
+    let correlation = compute_correlation(a, b);
+    if let Ok(mut cache) = return_stats_cache().write() {
+        cache.correlations.insert(key, correlation);
+    }
+    correlation
+}
+
+fn compute_correlation(a: &Stock, b: &Stock) -> Option<f64> {
+    let returns_a: HashMap<String, f64> = monthly_returns(&a.ticker)?.into_iter().collect();
+    let pairs: Vec<(f64, f64)> = monthly_returns(&b.ticker)?
+        .into_iter()
+        .filter_map(|(month, rb)| returns_a.get(&month).map(|ra| (*ra, rb)))
+        .collect();
+    if pairs.len() < 3 {
+        return None;
+    }
+
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let cov: f64 = pairs.iter().map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+    let var_a: f64 = pairs.iter().map(|(x, _)| (x - mean_a).powi(2)).sum();
+    let var_b: f64 = pairs.iter().map(|(_, y)| (y - mean_b).powi(2)).sum();
+    if var_a <= 0.0 || var_b <= 0.0 {
+        return None;
+    }
+    Some(cov / (var_a.sqrt() * var_b.sqrt()))
+}
+
+/// Check whether any month-over-month return between `start_month` and
+/// `end_month` (inclusive, "YYYY-MM") falls outside `bounds`
+fn has_extreme_monthly_gap(data: &MonthlyPriceData, start_month: &str, end_month: &str, bounds: PriceGapBounds) -> bool {
+    data.dates.windows(2)
+        .zip(data.prices.windows(2))
+        .filter(|(months, _)| months[1].as_str() >= start_month && months[0].as_str() <= end_month)
+        .any(|(_, prices)| match pct_return(prices[0], prices[1]) {
+            Some(change_pct) => change_pct > bounds.max_gain_pct || change_pct < bounds.max_loss_pct,
+            None => false,
+        })
+}
+
+/// Largest peak-to-trough decline, as a positive percentage, over the
+/// monthly closes between `start_month` and `end_month` (inclusive, "YYYY-MM")
+fn max_drawdown(data: &MonthlyPriceData, start_month: &str, end_month: &str) -> Option<f64> {
+    let mut peak: Option<f64> = None;
+    let mut worst: Option<f64> = None;
+    let in_period = data.dates.iter()
+        .zip(&data.prices)
+        .filter(|(month, price)| month.as_str() >= start_month && month.as_str() <= end_month && price.is_finite() && **price > 0.0);
+    for (_, &price) in in_period {
+        let high = peak.map_or(price, |p| p.max(price));
+        peak = Some(high);
+        let drawdown = (high - price) / high * 100.0;
+        worst = Some(worst.map_or(drawdown, |w: f64| w.max(drawdown)));
     }
+    worst
 }
 
 /// Fetch historical returns using monthly price cache (NEW, FASTER METHOD)
-fn fetch_from_monthly_cache(stocks: &mut [Stock], start_date: &str, end_date: &str) -> Result<bool, Box<dyn Error>> {
-    unsafe {
-        if MONTHLY_PRICES_CACHE.is_none() {
-            return Ok(false);
-        }
+fn fetch_from_monthly_cache(
+    stocks: &mut [Stock],
+    start_date: &str,
+    end_date: &str,
+    gap_bounds: Option<PriceGapBounds>,
+) -> Result<bool, Box<dyn Error>> {
+    if monthly_cache().is_none() {
+        return Ok(false);
     }
     
-    println!("[CACHE] Using monthly price data for period {} to {}", start_date, end_date);
+    info!("[CACHE] Using monthly price data for period {} to {}", start_date, end_date);
     
     let mut hits = 0;
     let mut misses = 0;
+    let mut flagged = 0;
+    let monthly = monthly_cache();
     
     for stock in stocks.iter_mut() {
+        let series = monthly.as_ref().and_then(|c| c.get(&stock.ticker));
+        // Data-quality check: flag series with implausible single-month jumps
+        if let (Some(bounds), Some(data)) = (gap_bounds, series) {
+            if has_extreme_monthly_gap(data, &start_date[..7], &end_date[..7], bounds) {
+                stock.price_gap_flagged = true;
+                flagged += 1;
+            }
+        }
+        stock.max_drawdown = series.and_then(|data| max_drawdown(data, &start_date[..7], &end_date[..7]));
+
         if let (Some(start_price), Some(end_price)) = 
             (get_monthly_price(&stock.ticker, start_date), get_monthly_price(&stock.ticker, end_date)) {
             
-            if start_price > 0.0 {
-                let return_pct = ((end_price - start_price) / start_price) * 100.0;
+            if let Some(return_pct) = pct_return(start_price, end_price) {
                 stock.historical_return = Some(return_pct);
                 stock.historical_start_price = Some(start_price);
                 hits += 1;
@@ -231,7 +709,10 @@ fn fetch_from_monthly_cache(stocks: &mut [Stock], start_date: &str, end_date: &s
         }
     }
     
-    println!("[CACHE] Monthly lookup: {} hits, {} misses", hits, misses);
+    info!("[CACHE] Monthly lookup: {} hits, {} misses", hits, misses);
+    if flagged > 0 {
+        info!("[QUALITY] Flagged {} stocks with extreme month-over-month price gaps", flagged);
+    }
     
     Ok(hits > 0)
 }
@@ -253,32 +734,30 @@ fn parse_period_key(period_key: &str) -> Option<(chrono::NaiveDate, chrono::Naiv
 fn find_surrounding_periods(target_date: &str) -> Option<(String, String)> {
     let target = chrono::NaiveDate::parse_from_str(target_date, "%Y-%m-%d").ok()?;
     
-    unsafe {
-        let cache = HISTORICAL_PERIODS_CACHE.as_ref()?;
-        
-        let mut before_period: Option<(String, chrono::NaiveDate)> = None;
-        let mut after_period: Option<(String, chrono::NaiveDate)> = None;
+    let cache = periods_cache()?;
+    
+    let mut before_period: Option<(String, chrono::NaiveDate)> = None;
+    let mut after_period: Option<(String, chrono::NaiveDate)> = None;
+    
+    for period_key in cache.keys() {
+        let (p_start, _p_end) = parse_period_key(period_key)?;
         
-        for period_key in cache.keys() {
-            let (p_start, _p_end) = parse_period_key(period_key)?;
-            
-            if p_start <= target {
-                // This period starts before or at target - candidate for "before"
-                if before_period.is_none() || p_start > before_period.as_ref()?.1 {
-                    before_period = Some((period_key.clone(), p_start));
-                }
-            } else {
-                // This period starts after target - candidate for "after"
-                if after_period.is_none() || p_start < after_period.as_ref()?.1 {
-                    after_period = Some((period_key.clone(), p_start));
-                }
+        if p_start <= target {
+            // This period starts before or at target - candidate for "before"
+            if before_period.is_none() || p_start > before_period.as_ref()?.1 {
+                before_period = Some((period_key.clone(), p_start));
+            }
+        } else {
+            // This period starts after target - candidate for "after"
+            if after_period.is_none() || p_start < after_period.as_ref()?.1 {
+                after_period = Some((period_key.clone(), p_start));
             }
         }
-        
-        match (before_period, after_period) {
-            (Some((before_key, _)), Some((after_key, _))) => Some((before_key, after_key)),
-            _ => None,
-        }
+    }
+    
+    match (before_period, after_period) {
+        (Some((before_key, _)), Some((after_key, _))) => Some((before_key, after_key)),
+        _ => None,
     }
 }
 
@@ -293,24 +772,22 @@ fn interpolate_price(ticker: &str, target_date: &str, before_period: &str, after
     let (before_date, _) = parse_period_key(before_period)?;
     let (after_date, _) = parse_period_key(after_period)?;
     
-    unsafe {
-        let cache = HISTORICAL_PERIODS_CACHE.as_ref()?;
-        let before_data = cache.get(before_period)?.get(ticker)?;
-        let after_data = cache.get(after_period)?.get(ticker)?;
-        
-        // Calculate interpolation ratio based on time position
-        let total_days = (after_date - before_date).num_days() as f64;
-        let target_days = (target - before_date).num_days() as f64;
-        let ratio = target_days / total_days;
-        
-        let interpolated = linear_interpolate(
-            before_data.start_price,
-            after_data.start_price,
-            ratio
-        );
-        
-        Some(interpolated)
-    }
+    let cache = periods_cache()?;
+    let before_data = cache.get(before_period)?.get(ticker)?;
+    let after_data = cache.get(after_period)?.get(ticker)?;
+    
+    // Calculate interpolation ratio based on time position
+    let total_days = (after_date - before_date).num_days() as f64;
+    let target_days = (target - before_date).num_days() as f64;
+    let ratio = target_days / total_days;
+    
+    let interpolated = linear_interpolate(
+        before_data.start_price,
+        after_data.start_price,
+        ratio
+    );
+    
+    Some(interpolated)
 }
 
 /// Find the best matching historical period for the given date range
@@ -319,34 +796,32 @@ fn find_matching_period(start_date: &str, end_date: &str) -> Option<String> {
     let exact_key = format!("{}_{}", start_date, end_date);
     let start = chrono::NaiveDate::parse_from_str(start_date, "%Y-%m-%d").ok()?;
     
-    unsafe {
-        let cache = HISTORICAL_PERIODS_CACHE.as_ref()?;
+    let cache = periods_cache()?;
+    
+    // Priority 1: Exact match
+    if cache.contains_key(&exact_key) {
+        return Some(exact_key);
+    }
+    
+    let mut best_match: Option<(String, i64)> = None;
+    
+    // Priority 2: Period containing start date, Priority 3: Closest period
+    for period_key in cache.keys() {
+        let (p_start, p_end) = parse_period_key(period_key)?;
         
-        // Priority 1: Exact match
-        if cache.contains_key(&exact_key) {
-            return Some(exact_key);
+        // Check if period contains the start date
+        if p_start <= start && p_end >= start {
+            return Some(period_key.clone());
         }
         
-        let mut best_match: Option<(String, i64)> = None;
-        
-        // Priority 2: Period containing start date, Priority 3: Closest period
-        for period_key in cache.keys() {
-            let (p_start, p_end) = parse_period_key(period_key)?;
-            
-            // Check if period contains the start date
-            if p_start <= start && p_end >= start {
-                return Some(period_key.clone());
-            }
-            
-            // Track closest period by distance to start date
-            let distance = (start - p_start).num_days().abs();
-            if best_match.is_none() || distance < best_match.as_ref()?.1 {
-                best_match = Some((period_key.clone(), distance));
-            }
+        // Track closest period by distance to start date
+        let distance = (start - p_start).num_days().abs();
+        if best_match.is_none() || distance < best_match.as_ref()?.1 {
+            best_match = Some((period_key.clone(), distance));
         }
-        
-        best_match.map(|(key, _)| key)
     }
+    
+    best_match.map(|(key, _)| key)
 }
 
 /// Apply cached historical data to stocks from a specific period
@@ -354,17 +829,15 @@ fn apply_cached_period_data(stocks: &mut [Stock], period_key: &str) -> (usize, u
     let mut hits = 0;
     let mut misses = 0;
     
-    unsafe {
-        if let Some(ref cache) = HISTORICAL_PERIODS_CACHE {
-            if let Some(period_data) = cache.get(period_key) {
-                for stock in stocks.iter_mut() {
-                    if let Some(hist_data) = period_data.get(&stock.ticker) {
-                        stock.historical_return = Some(hist_data.return_pct);
-                        stock.historical_start_price = Some(hist_data.start_price);
-                        hits += 1;
-                    } else {
-                        misses += 1;
-                    }
+    if let Some(cache) = periods_cache() {
+        if let Some(period_data) = cache.get(period_key) {
+            for stock in stocks.iter_mut() {
+                if let Some(hist_data) = period_data.get(&stock.ticker) {
+                    stock.historical_return = Some(hist_data.return_pct);
+                    stock.historical_start_price = Some(hist_data.start_price);
+                    hits += 1;
+                } else {
+                    misses += 1;
                 }
             }
         }
@@ -385,12 +858,18 @@ fn apply_interpolation_refinement(stocks: &mut [Stock], start_date: &str, before
         if let Some(interpolated_price) = interpolate_price(&stock.ticker, start_date, before_period, after_period) {
             // Recalculate return with more accurate interpolated start price
             if let (Some(original_start), Some(original_return)) = (stock.historical_start_price, stock.historical_return) {
+                // A zero/negative start price would turn the recomputed return
+                // into -100% or inf/NaN; keep the original values instead
+                let usable = |p: f64| p.is_finite() && p > 0.0;
+                if !usable(original_start) || !usable(interpolated_price) {
+                    continue;
+                }
                 let end_price = original_start * (1.0 + original_return / 100.0);
-                let new_return = ((end_price - interpolated_price) / interpolated_price) * 100.0;
-                
-                stock.historical_start_price = Some(interpolated_price);
-                stock.historical_return = Some(new_return);
-                refined_count += 1;
+                if let Some(new_return) = pct_return(interpolated_price, end_price) {
+                    stock.historical_start_price = Some(interpolated_price);
+                    stock.historical_return = Some(new_return);
+                    refined_count += 1;
+                }
             }
         }
     }
@@ -405,17 +884,17 @@ fn fetch_from_cache(stocks: &mut [Stock], start_date: &str, end_date: &str) -> R
         None => return Ok(false), // No cache available
     };
     
-    println!("[CACHE] Using cached historical period: {}", period_key);
+    info!("[CACHE] Using cached historical period: {}", period_key);
     
     let (hits, misses) = apply_cached_period_data(stocks, &period_key);
-    println!("[CACHE] Loaded from cached period: {} hits, {} misses", hits, misses);
+    info!("[CACHE] Loaded from cached period: {} hits, {} misses", hits, misses);
     
     // Try interpolation for better accuracy
     if let Some((before_period, after_period)) = find_surrounding_periods(start_date) {
-        println!("[INTERP] Refining with interpolation between {} and {}", before_period, after_period);
+        info!("[INTERP] Refining with interpolation between {} and {}", before_period, after_period);
         let refined = apply_interpolation_refinement(stocks, start_date, &before_period, &after_period);
         if refined > 0 {
-            println!("[INTERP] Interpolated {} stock prices for better accuracy", refined);
+            info!("[INTERP] Interpolated {} stock prices for better accuracy", refined);
         }
     }
     
@@ -423,15 +902,33 @@ fn fetch_from_cache(stocks: &mut [Stock], start_date: &str, end_date: &str) -> R
     Ok(hits > misses)
 }
 
+/// Fill historical returns from the local caches only (monthly, then legacy
+/// periods). Returns false when neither cache covers the period; never
+/// touches the network.
+pub fn fetch_historical_returns_offline(
+    stocks: &mut [Stock],
+    start_date: &str,
+    end_date: &str,
+    gap_bounds: Option<PriceGapBounds>,
+) -> Result<bool, Box<dyn Error>> {
+    if fetch_from_monthly_cache(stocks, start_date, end_date, gap_bounds)? {
+        return Ok(true);
+    }
+    fetch_from_cache(stocks, start_date, end_date)
+}
+
 /// Fetch historical returns for stocks during a specific date range
 /// First tries monthly cache (fast, accurate), then period cache, then API fallback
+/// When `gap_bounds` is set, stocks whose monthly series jumps outside the
+/// bounds during the period are flagged via `Stock::price_gap_flagged`.
 pub async fn fetch_historical_returns(
     stocks: &mut [Stock], 
     start_date: &str,  // Format: YYYY-MM-DD
-    end_date: &str     // Format: YYYY-MM-DD
+    end_date: &str,    // Format: YYYY-MM-DD
+    gap_bounds: Option<PriceGapBounds>,
 ) -> Result<(), Box<dyn Error>> {
     // Priority 1: Try monthly price cache (NEW, FAST, ACCURATE)
-    if fetch_from_monthly_cache(stocks, start_date, end_date)? {
+    if fetch_from_monthly_cache(stocks, start_date, end_date, gap_bounds)? {
         return Ok(());
     }
     
@@ -441,18 +938,33 @@ pub async fn fetch_historical_returns(
     }
     
     // Priority 3: Fallback to Yahoo Finance API (slow)
-    println!("[WARN] Falling back to API for historical data...");
-    println!("[WARN] This will be VERY SLOW (~10 seconds per stock)");
-    println!("[WARN] RECOMMENDATION: Run 'python3 fetch_monthly_cache.py' to generate cache!");
+    warn!("Falling back to API for historical data...");
+    warn!("This will be VERY SLOW (~10 seconds per stock)");
+    warn!("RECOMMENDATION: Run 'python3 fetch_monthly_cache.py' to generate cache!");
     
     fetch_from_yahoo_api(stocks, start_date, end_date).await
 }
 
-/// Fetch historical data from Yahoo Finance API (fallback when cache unavailable)
-async fn fetch_from_yahoo_api(stocks: &mut [Stock], start_date: &str, end_date: &str) -> Result<(), Box<dyn Error>> {
+static YAHOO_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Yahoo Finance chart endpoint; the ticker is appended as a path segment
+pub const YAHOO_CHART_URL: &str = "https://query1.finance.yahoo.com/v8/finance/chart";
+
+/// Shared Yahoo Finance HTTP client, built on first use so connections are
+/// pooled across requests
+fn yahoo_client() -> Result<&'static reqwest::Client, Box<dyn Error>> {
+    if let Some(client) = YAHOO_CLIENT.get() {
+        return Ok(client);
+    }
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(10))
         .build()?;
+    Ok(YAHOO_CLIENT.get_or_init(|| client))
+}
+
+/// Fetch historical data from Yahoo Finance API (fallback when cache unavailable)
+async fn fetch_from_yahoo_api(stocks: &mut [Stock], start_date: &str, end_date: &str) -> Result<(), Box<dyn Error>> {
+    let client = yahoo_client()?;
     
     let start_timestamp = chrono::NaiveDate::parse_from_str(start_date, "%Y-%m-%d")?
         .and_hms_opt(0, 0, 0).unwrap()
@@ -469,19 +981,19 @@ async fn fetch_from_yahoo_api(stocks: &mut [Stock], start_date: &str, end_date:
         .collect();
     
     let total = stocks_to_fetch.len();
-    println!("[API] Fetching data for {} stocks via API...", total);
+    info!("[API] Fetching data for {} stocks via API...", total);
     
     let mut success = 0;
     let mut failed = 0;
     
     for (i, stock) in stocks_to_fetch.into_iter().enumerate() {
         if i % 10 == 0 {
-            println!("   Progress: {}/{} stocks...", i, total);
+            info!("   Progress: {}/{} stocks...", i, total);
         }
         
         let url = format!(
-            "https://query1.finance.yahoo.com/v8/finance/chart/{}?period1={}&period2={}&interval=1d",
-            stock.ticker, start_timestamp, end_timestamp
+            "{}/{}?period1={}&period2={}&interval=1d",
+            YAHOO_CHART_URL, stock.ticker, start_timestamp, end_timestamp
         );
         
         if let Ok(resp) = client.get(&url).send().await {
@@ -489,8 +1001,7 @@ async fn fetch_from_yahoo_api(stocks: &mut [Stock], start_date: &str, end_date:
                 if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
                     if let Some(closes) = extract_close_prices(&json) {
                         if let (Some(start_price), Some(end_price)) = (closes.first(), closes.last()) {
-                            if *start_price > 0.0 {
-                                let return_pct = ((end_price - start_price) / start_price) * 100.0;
+                            if let Some(return_pct) = pct_return(*start_price, *end_price) {
                                 stock.historical_return = Some(return_pct);
                                 stock.historical_start_price = Some(*start_price);
                                 success += 1;
@@ -510,10 +1021,136 @@ async fn fetch_from_yahoo_api(stocks: &mut [Stock], start_date: &str, end_date:
         }
     }
     
-    println!("[API] Fetch complete: {} success, {} failed", success, failed);
+    info!("[API] Fetch complete: {} success, {} failed", success, failed);
     Ok(())
 }
 
+/// Batching and timeouts for `update_monthly_cache` (defaults come from the
+/// `update-cache` subcommand's flags)
+#[derive(Debug, Clone)]
+pub struct MonthlyUpdateOptions {
+    /// Tickers requested per batch
+    pub batch_size: usize,
+    /// Pause between batches, to stay under the provider's rate limit
+    pub batch_delay: std::time::Duration,
+    /// Requests in flight at once within a batch
+    pub concurrency: usize,
+    pub request_timeout: std::time::Duration,
+    /// Chart endpoint to fetch monthly closes from (`YAHOO_CHART_URL`)
+    pub chart_url: String,
+}
+
+/// Bring the monthly cache in `cache_file` up to date. Each ticker only
+/// fetches from its last cached month (whose close may have been taken
+/// mid-month) to the present; those months are replaced or appended and the
+/// file is rewritten. Returns the number of months appended.
+pub async fn update_monthly_cache(cache_file: &str, options: MonthlyUpdateOptions) -> Result<usize, Box<dyn Error>> {
+    let contents = fs::read_to_string(cache_file)?;
+    let mut cache: serde_json::Value = serde_json::from_str(&contents)?;
+    let Some(monthly) = cache.get_mut("monthly_prices").and_then(|m| m.as_object_mut()) else {
+        return Err(format!("'{}' has no monthly_prices section - run fetch_monthly_cache.py", cache_file).into());
+    };
+    let client = yahoo_client()?;
+    let now = chrono::Utc::now();
+    let current_month = now.format("%Y-%m").to_string();
+
+    // Tickers behind the current month, with the month to refetch from
+    let stale: Vec<(String, String)> = monthly.iter()
+        .filter_map(|(ticker, entry)| {
+            let last_month = entry["dates"].as_array()?.last()?.as_str()?;
+            (last_month < current_month.as_str()).then(|| (ticker.clone(), last_month.to_string()))
+        })
+        .collect();
+
+    let limit = Arc::new(tokio::sync::Semaphore::new(options.concurrency.max(1)));
+    let mut fetched: HashMap<String, Vec<(String, f64)>> = HashMap::new();
+    for (i, batch) in stale.chunks(options.batch_size.max(1)).enumerate() {
+        if i > 0 {
+            tokio::time::sleep(options.batch_delay).await;
+        }
+        let mut tasks = tokio::task::JoinSet::new();
+        for (ticker, last_month) in batch {
+            let Ok(from) = chrono::NaiveDate::parse_from_str(&format!("{}-01", last_month), "%Y-%m-%d") else { continue };
+            let url = format!(
+                "{}/{}?period1={}&period2={}&interval=1mo",
+                options.chart_url, ticker, from.and_time(chrono::NaiveTime::MIN).and_utc().timestamp(), now.timestamp()
+            );
+            let (ticker, limit, timeout) = (ticker.clone(), limit.clone(), options.request_timeout);
+            tasks.spawn(async move {
+                let _permit = limit.acquire_owned().await.ok()?;
+                let resp = client.get(&url).timeout(timeout).send().await.ok()?;
+                let closes = extract_monthly_closes(&resp.json::<serde_json::Value>().await.ok()?)?;
+                Some((ticker, closes))
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(Some((ticker, closes))) = result {
+                fetched.insert(ticker, closes);
+            }
+        }
+    }
+
+    let mut appended = 0;
+    for (ticker, closes) in &fetched {
+        let Some(entry) = monthly.get_mut(ticker) else { continue };
+        let Ok(mut data) = serde_json::from_value::<MonthlyPriceData>(entry.clone()) else { continue };
+        appended += merge_monthly_closes(&mut data, closes);
+        entry["dates"] = serde_json::json!(data.dates);
+        entry["prices"] = serde_json::json!(data.prices);
+        entry["data_points"] = serde_json::json!(data.dates.len());
+        entry["last_trading"] = serde_json::json!(now.format("%Y-%m-%d").to_string());
+    }
+
+    let failed = stale.len() - fetched.len();
+    info!("[CACHE] Incremental update: {} tickers updated, {} months appended, {} failed", fetched.len(), appended, failed);
+    if fetched.is_empty() {
+        // Leave the file (and its generated_at) alone so staleness stays visible
+        if failed > 0 {
+            return Err(format!("all {} price requests failed - is the network reachable?", failed).into());
+        }
+        return Ok(0);
+    }
+    cache["metadata"]["generated_at"] = serde_json::json!(now.naive_utc().format("%Y-%m-%dT%H:%M:%S%.f").to_string());
+    let tmp_path = format!("{}.tmp", cache_file);
+    fs::write(&tmp_path, serde_json::to_string_pretty(&cache)?)?;
+    fs::rename(&tmp_path, cache_file)?;
+    Ok(appended)
+}
+
+/// Merge freshly fetched closes into a cached series: the last cached month
+/// is overwritten (its close may have been taken mid-month), later months are
+/// appended and earlier ones ignored. Returns the number of months appended.
+fn merge_monthly_closes(data: &mut MonthlyPriceData, closes: &[(String, f64)]) -> usize {
+    let Some(last_month) = data.dates.last().cloned() else { return 0 };
+    let mut appended = 0;
+    for (month, close) in closes.iter().filter(|(month, _)| *month >= last_month) {
+        match data.dates.iter().position(|d| d == month) {
+            Some(i) => data.prices[i] = *close,
+            None => {
+                data.dates.push(month.clone());
+                data.prices.push(*close);
+                appended += 1;
+            }
+        }
+    }
+    appended
+}
+
+/// (month "YYYY-MM", close) pairs from a Yahoo chart response at a monthly interval
+fn extract_monthly_closes(json: &serde_json::Value) -> Option<Vec<(String, f64)>> {
+    let result = json["chart"]["result"].as_array()?.first()?;
+    let timestamps = result["timestamp"].as_array()?;
+    let closes = result["indicators"]["quote"].as_array()?.first()?["close"].as_array()?;
+    let pairs = timestamps.iter()
+        .zip(closes)
+        .filter_map(|(ts, close)| {
+            let month = chrono::DateTime::from_timestamp(ts.as_i64()?, 0)?.format("%Y-%m").to_string();
+            Some((month, close.as_f64().filter(|c| c.is_finite() && *c > 0.0)?))
+        })
+        .collect();
+    Some(pairs)
+}
+
 /// Extract close prices from Yahoo Finance API response
 fn extract_close_prices(json: &serde_json::Value) -> Option<Vec<f64>> {
     let result = json["chart"]["result"].as_array()?.first()?;
@@ -525,3 +1162,389 @@ fn extract_close_prices(json: &serde_json::Value) -> Option<Vec<f64>> {
         .collect::<Vec<f64>>()
         .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monthly(dates: &[&str], prices: &[f64]) -> MonthlyPriceData {
+        MonthlyPriceData {
+            dates: dates.iter().map(|d| d.to_string()).collect(),
+            prices: prices.to_vec(),
+            first_trading: String::new(),
+            last_trading: String::new(),
+            data_points: dates.len(),
+        }
+    }
+
+    fn stock(ticker: &str, price: f64, sector: &str) -> Stock {
+        serde_json::from_value(serde_json::json!({
+            "ticker": ticker, "price": price, "sector": sector, "volatility": 0.02,
+        })).unwrap()
+    }
+
+    #[test]
+    fn extreme_monthly_jump_is_flagged() {
+        let dates = ["2020-01", "2020-02", "2020-03", "2020-04"];
+        let jump = monthly(&dates, &[10.0, 10.5, 52.5, 53.0]);
+        assert!(has_extreme_monthly_gap(&jump, "2020-01", "2020-04", PriceGapBounds::default()));
+        // Outside the investment period the jump is ignored
+        assert!(!has_extreme_monthly_gap(&jump, "2020-04", "2020-04", PriceGapBounds::default()));
+
+        let steady = monthly(&dates, &[10.0, 11.0, 12.0, 11.5]);
+        assert!(!has_extreme_monthly_gap(&steady, "2020-01", "2020-04", PriceGapBounds::default()));
+    }
+
+    #[test]
+    fn pct_return_rejects_unusable_prices() {
+        assert_eq!(pct_return(50.0, 75.0), Some(50.0));
+        assert_eq!(pct_return(80.0, 0.0), Some(-100.0));
+        assert_eq!(pct_return(0.0, 10.0), None);
+        assert_eq!(pct_return(-5.0, 10.0), None);
+        assert_eq!(pct_return(10.0, -1.0), None);
+        assert_eq!(pct_return(f64::NAN, 10.0), None);
+        assert_eq!(pct_return(10.0, f64::INFINITY), None);
+    }
+
+    #[test]
+    fn month_end_day_is_last_calendar_day() {
+        use chrono::Datelike;
+        let day = |d: &str| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap().num_days_from_ce();
+        assert_eq!(month_end_day("2020-02"), Some(day("2020-02-29")));
+        assert_eq!(month_end_day("2021-02"), Some(day("2021-02-28")));
+        assert_eq!(month_end_day("2021-04"), Some(day("2021-04-30")));
+        assert_eq!(month_end_day("2021-12"), Some(day("2021-12-31")));
+        assert_eq!(month_end_day("2021-13"), None);
+    }
+
+    #[test]
+    fn monthly_price_interpolates_a_daily_series() {
+        // A daily series rising one dollar a day, sampled at month-end closes;
+        // interpolating between those closes must recover the daily price
+        let base = chrono::NaiveDate::from_ymd_opt(2019, 12, 31).unwrap();
+        let daily = |date: chrono::NaiveDate| 100.0 + (date - base).num_days() as f64;
+        let months = ["2019-12", "2020-01", "2020-02", "2020-03"];
+        let closes: Vec<f64> = months.iter()
+            .map(|m| daily(chrono::NaiveDate::from_num_days_from_ce_opt(month_end_day(m).unwrap()).unwrap()))
+            .collect();
+        let data = monthly(&months, &closes);
+
+        for date in ["2020-01-01", "2020-01-15", "2020-02-10", "2020-02-29", "2020-03-17"] {
+            let expected = daily(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap());
+            let actual = monthly_price_at(&data, date).unwrap();
+            assert!((actual - expected).abs() < 1e-9, "{}: {} != {}", date, actual, expected);
+        }
+        // Outside the series the nearest close is used
+        assert_eq!(monthly_price_at(&data, "2019-06-30"), Some(100.0));
+        assert_eq!(monthly_price_at(&data, "2021-01-01"), closes.last().copied());
+        assert_eq!(monthly_price_at(&data, "2020-02-30"), None);
+    }
+
+    #[test]
+    fn duplicate_ticker_keeps_the_more_complete_entry() {
+        let sparse = stock("DUP", 10.0, "Energy");
+        let mut rich = stock("DUP", 11.0, "Energy");
+        rich.market_cap = 5_000_000;
+        rich.first_trading_date = Some("2001-01-02".to_string());
+        let other = stock("ONE", 20.0, "Utilities");
+
+        let unique = dedup_stocks(vec![sparse.clone(), other, rich]);
+        let tickers: Vec<&str> = unique.iter().map(|s| s.ticker.as_str()).collect();
+        assert_eq!(tickers, vec!["DUP", "ONE"]);
+        assert_eq!(unique[0].price, 11.0);
+
+        // Equally complete duplicates keep the first occurrence
+        let unique = dedup_stocks(vec![sparse, stock("DUP", 12.0, "Energy")]);
+        assert_eq!(unique.len(), 1);
+        assert_eq!(unique[0].price, 10.0);
+    }
+
+    #[test]
+    fn broken_cache_entries_are_reported() {
+        let mut stocks = vec![
+            stock("GOOD", 10.0, "Energy"),
+            stock(" ", 10.0, "Energy"),
+            stock("ZERO", 0.0, "Energy"),
+            stock("NAN", 10.0, "Energy"),
+            stock("SHUF", 10.0, "Energy"),
+        ];
+        stocks[3].price = f64::NAN;
+        let mut monthly_data = MonthlyCache::new();
+        monthly_data.insert("GOOD".to_string(), monthly(&["2020-01", "2020-02"], &[9.0, 10.0]));
+        monthly_data.insert("SHUF".to_string(), monthly(&["2020-02", "2020-01"], &[9.0, 10.0]));
+        monthly_data.insert("ZERO".to_string(), monthly(&["2020-01", "2020-01"], &[9.0, 10.0]));
+
+        let report = validate_stocks(&stocks, Some(&monthly_data));
+        assert_eq!(report.total, 5);
+        assert_eq!(report.empty_tickers, 1);
+        assert_eq!(report.invalid_prices, vec!["ZERO", "NAN"]);
+        assert_eq!(report.unordered_dates, vec!["ZERO", "SHUF"]);
+        // ZERO is counted once despite two problems
+        assert_eq!(report.invalid_count(), 4);
+        assert!(report.invalid_fraction() > MAX_INVALID_CACHE_FRACTION);
+
+        assert_eq!(validate_stocks(&stocks[..1], None).invalid_count(), 0);
+    }
+
+    #[test]
+    fn drawdown_catches_a_mid_period_dip() {
+        let data = monthly(&["2020-01", "2020-02", "2020-03", "2020-04"], &[100.0, 140.0, 70.0, 150.0]);
+        // Ends up 50% but fell by half from its February peak
+        assert_eq!(pct_return(100.0, 150.0), Some(50.0));
+        assert_eq!(max_drawdown(&data, "2020-01", "2020-04"), Some(50.0));
+        // Only months inside the period count
+        assert_eq!(max_drawdown(&data, "2020-03", "2020-04"), Some(0.0));
+        assert_eq!(max_drawdown(&data, "2021-01", "2021-12"), None);
+    }
+
+    /// Yahoo monthly chart response with one bar per (month, close)
+    fn chart(bars: &[(&str, Option<f64>)]) -> serde_json::Value {
+        let timestamps: Vec<i64> = bars.iter()
+            .map(|(month, _)| {
+                let first = chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").unwrap();
+                first.and_time(chrono::NaiveTime::MIN).and_utc().timestamp()
+            })
+            .collect();
+        let closes: Vec<Option<f64>> = bars.iter().map(|(_, close)| *close).collect();
+        serde_json::json!({ "chart": { "result": [{
+            "timestamp": timestamps,
+            "indicators": { "quote": [{ "close": closes }] },
+        }] } })
+    }
+
+    #[test]
+    fn monthly_closes_are_read_from_a_chart_response() {
+        let json = chart(&[("2024-01", Some(10.0)), ("2024-02", None), ("2024-03", Some(12.5))]);
+        let closes = extract_monthly_closes(&json).unwrap();
+        assert_eq!(closes, vec![("2024-01".to_string(), 10.0), ("2024-03".to_string(), 12.5)]);
+        assert_eq!(extract_monthly_closes(&serde_json::json!({ "chart": { "result": [] } })), None);
+    }
+
+    #[test]
+    fn fetched_closes_replace_the_last_month_and_append_the_rest() {
+        let mut data = monthly(&["2024-01", "2024-02"], &[10.0, 11.0]);
+        let closes = [
+            ("2024-01".to_string(), 99.0),
+            ("2024-02".to_string(), 11.5),
+            ("2024-03".to_string(), 12.0),
+        ];
+        assert_eq!(merge_monthly_closes(&mut data, &closes), 1);
+        assert_eq!(data.dates, vec!["2024-01", "2024-02", "2024-03"]);
+        // Months before the last cached one are left alone
+        assert_eq!(data.prices, vec![10.0, 11.5, 12.0]);
+    }
+
+    #[tokio::test]
+    async fn update_fetches_only_missing_months() {
+        let month = |back: u32| {
+            let first = chrono::Datelike::with_day(&chrono::Utc::now().date_naive(), 1).unwrap();
+            first.checked_sub_months(chrono::Months::new(back)).unwrap().format("%Y-%m").to_string()
+        };
+        let (m3, m2, m1, m0) = (month(3), month(2), month(1), month(0));
+        let response = chart(&[(&m2, Some(12.0)), (&m1, Some(13.0)), (&m0, Some(14.0))]).to_string();
+        let server = crate::mock_server::MockServer::start(move |_| (200, response.clone())).await;
+
+        let dir = std::env::temp_dir().join(format!("quant_proj_monthly_update_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache_file = dir.join("monthly.json");
+        let entry = |dates: &[&str], prices: &[f64]| serde_json::json!({
+            "dates": dates, "prices": prices, "first_trading": "", "last_trading": "", "data_points": dates.len(),
+        });
+        let cache = serde_json::json!({ "metadata": {}, "monthly_prices": {
+            "OLDA": entry(&[&m3, &m2], &[10.0, 11.0]),
+            "OLDB": entry(&[&m3, &m2], &[20.0, 21.0]),
+            "FRESH": entry(&[&m1, &m0], &[30.0, 31.0]),
+        } });
+        std::fs::write(&cache_file, cache.to_string()).unwrap();
+
+        let options = MonthlyUpdateOptions {
+            batch_size: 1,
+            batch_delay: std::time::Duration::ZERO,
+            concurrency: 2,
+            request_timeout: std::time::Duration::from_secs(5),
+            chart_url: format!("{}:{}", server.host(), server.port()),
+        };
+        let appended = update_monthly_cache(cache_file.to_str().unwrap(), options).await.unwrap();
+        assert_eq!(appended, 4);
+
+        // One request per stale ticker, starting at its last cached month
+        let from = chrono::NaiveDate::parse_from_str(&format!("{}-01", m2), "%Y-%m-%d").unwrap()
+            .and_time(chrono::NaiveTime::MIN).and_utc().timestamp();
+        let mut paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        paths.sort();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].starts_with(&format!("/OLDA?period1={}&", from)), "{}", paths[0]);
+        assert!(paths[1].starts_with(&format!("/OLDB?period1={}&", from)), "{}", paths[1]);
+
+        let updated: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&cache_file).unwrap()).unwrap();
+        let monthly = &updated["monthly_prices"];
+        assert_eq!(monthly["OLDA"]["dates"], serde_json::json!([m3, m2, m1, m0]));
+        assert_eq!(monthly["OLDA"]["prices"], serde_json::json!([10.0, 12.0, 13.0, 14.0]));
+        assert_eq!(monthly["FRESH"]["prices"], serde_json::json!([30.0, 31.0]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sector_exposure_uses_weights_or_splits_equally() {
+        let mut weighted = stock("MIX", 10.0, "Technology, Consumer");
+        weighted.sector_weights = HashMap::from([("Technology".to_string(), 7.0), ("Consumer".to_string(), 3.0)]);
+        assert_eq!(weighted.sector_exposure(), vec![("Technology", 0.7), ("Consumer", 0.3)]);
+
+        // Sectors without a weight get none of the exposure
+        weighted.sector_weights.remove("Consumer");
+        assert_eq!(weighted.sector_exposure(), vec![("Technology", 1.0), ("Consumer", 0.0)]);
+
+        let split: Stock = serde_json::from_value(serde_json::json!({
+            "ticker": "SPLT", "price": 10.0, "sector": ["Energy", "Utilities"], "volatility": 0.02,
+        })).unwrap();
+        assert_eq!(split.sector_exposure(), vec![("Energy", 0.5), ("Utilities", 0.5)]);
+    }
+
+    #[test]
+    fn stale_cache_timestamp_warns() {
+        let fresh = chrono::Utc::now().naive_utc().format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+        assert!(record_cache_generated_at(&fresh).is_none());
+        assert!(cache_age().unwrap().num_hours() < CACHE_STALE_HOURS);
+
+        let stale = (chrono::Utc::now().naive_utc() - chrono::Duration::days(3)).format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+        let age = record_cache_generated_at(&stale).unwrap();
+        assert!(age.num_hours() >= 72);
+        assert!(record_cache_generated_at("yesterday").is_none());
+    }
+
+    #[test]
+    fn sector_keys_and_lists_all_populate_sectors() {
+        let parse = |key: &str, value: serde_json::Value| -> Stock {
+            serde_json::from_value(serde_json::json!({
+                "ticker": "AAA", "price": 10.0, key: value, "volatility": 0.02,
+            })).unwrap()
+        };
+        for key in ["sector", "sectors", "industry"] {
+            assert_eq!(parse(key, "Energy".into()).sectors(), vec!["Energy"], "{} as a string", key);
+            assert_eq!(
+                parse(key, serde_json::json!(["Technology", "Healthcare"])).sectors(),
+                vec!["Technology", "Healthcare"],
+                "{} as a list", key
+            );
+        }
+    }
+
+    #[test]
+    fn return_stats_are_computed_once_per_series() {
+        let computed = || STATS_COMPUTED.lock().unwrap().iter().filter(|t| *t == "STCA").count();
+        let dates = ["2020-01", "2020-02", "2020-03", "2020-04", "2020-05"];
+        insert_monthly_series("STCA", &dates, &[10.0, 11.0, 10.5, 12.0, 12.5]);
+        let first = monthly_return_stats("STCA").unwrap();
+        assert_eq!(monthly_return_stats("STCA"), Some(first));
+        assert_eq!(computed(), 1);
+
+        // Replacing the series invalidates its statistics
+        insert_monthly_series("STCA", &dates, &[10.0, 10.0, 10.0, 10.0, 10.0]);
+        assert_eq!(monthly_return_stats("STCA"), Some((0.0, 0.0)));
+        assert_eq!(computed(), 2);
+    }
+
+    /// Put `ticker`'s start price for `period_key` into the legacy periods cache
+    fn insert_period_start(period_key: &str, ticker: &str, start_price: f64) {
+        let mut slot = HISTORICAL_PERIODS_CACHE.write().unwrap_or_else(|e| e.into_inner());
+        let mut cache = slot.as_deref().cloned().unwrap_or_default();
+        cache.entry(period_key.to_string()).or_default().insert(ticker.to_string(), HistoricalData {
+            start_price,
+            end_price: 0.0,
+            return_pct: 0.0,
+        });
+        *slot = Some(Arc::new(cache));
+    }
+
+    #[test]
+    fn non_positive_start_prices_skip_refinement() {
+        let (before, after) = ("2019-01-01_2020-12-31", "2019-07-01_2020-12-31");
+        for (ticker, start) in [("ZSPA", 0.0), ("ZSPB", 0.0), ("ZSPC", 10.0), ("ZSPD", 20.0)] {
+            insert_period_start(before, ticker, start);
+            insert_period_start(after, ticker, start);
+        }
+        let mut stocks = [
+            // Zero start price in the stock itself
+            stock("ZSPA", 15.0, "Energy"),
+            // Zero interpolated start price
+            stock("ZSPB", 15.0, "Energy"),
+            stock("ZSPC", 15.0, "Energy"),
+            stock("ZSPD", 15.0, "Energy"),
+        ];
+        for (s, start) in stocks.iter_mut().zip([0.0, 10.0, -5.0, 10.0]) {
+            s.historical_start_price = Some(start);
+            s.historical_return = Some(50.0);
+        }
+        let refined = apply_interpolation_refinement(&mut stocks, "2019-04-01", before, after);
+        assert_eq!(refined, 1);
+        for s in &stocks[..3] {
+            assert_eq!(s.historical_return, Some(50.0), "{} was refined", s.ticker);
+        }
+        // 10 * 1.5 = 15 against an interpolated start of 20
+        assert_eq!(stocks[3].historical_start_price, Some(20.0));
+        assert_eq!(stocks[3].historical_return, Some(-25.0));
+        assert!(stocks.iter().all(|s| s.historical_return.is_some_and(f64::is_finite)));
+    }
+
+    #[tokio::test]
+    async fn update_reaches_every_ticker_across_batch_boundaries() {
+        let month = |back: u32| {
+            let first = chrono::Datelike::with_day(&chrono::Utc::now().date_naive(), 1).unwrap();
+            first.checked_sub_months(chrono::Months::new(back)).unwrap().format("%Y-%m").to_string()
+        };
+        let (m1, m0) = (month(1), month(0));
+        let response = chart(&[(&m1, Some(5.0)), (&m0, Some(6.0))]).to_string();
+        let server = crate::mock_server::MockServer::start(move |_| (200, response.clone())).await;
+
+        let dir = std::env::temp_dir().join(format!("quant_proj_monthly_batches_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache_file = dir.join("monthly.json");
+        let tickers = ["BATA", "BATB", "BATC", "BATD", "BATE"];
+        let monthly: serde_json::Map<String, serde_json::Value> = tickers.iter()
+            .map(|t| (t.to_string(), serde_json::json!({
+                "dates": [m1], "prices": [4.0], "first_trading": "", "last_trading": "", "data_points": 1,
+            })))
+            .collect();
+        std::fs::write(&cache_file, serde_json::json!({ "metadata": {}, "monthly_prices": monthly }).to_string()).unwrap();
+
+        // Five tickers in batches of two leave a short final batch
+        let options = MonthlyUpdateOptions {
+            batch_size: 2,
+            batch_delay: std::time::Duration::ZERO,
+            concurrency: 1,
+            request_timeout: std::time::Duration::from_secs(5),
+            chart_url: format!("{}:{}", server.host(), server.port()),
+        };
+        let appended = update_monthly_cache(cache_file.to_str().unwrap(), options).await.unwrap();
+        assert_eq!(appended, tickers.len());
+        assert_eq!(server.requests().len(), tickers.len());
+
+        let updated: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&cache_file).unwrap()).unwrap();
+        for ticker in tickers {
+            assert_eq!(updated["monthly_prices"][ticker]["prices"], serde_json::json!([5.0, 6.0]), "{}", ticker);
+        }
+        assert!(!dir.join("monthly.json.tmp").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stale_price_is_corrected_from_a_recent_monthly_close() {
+        insert_monthly_series("STLA", &["2024-04", "2024-05"], &[48.0, 50.0]);
+        insert_monthly_series("STLB", &["2024-04", "2024-05"], &[48.0, 50.0]);
+        insert_monthly_series("STLC", &["2021-01", "2021-02"], &[48.0, 50.0]);
+        let mut stocks = vec![
+            stock("STLA", 400.0, "Energy"),
+            stock("STLB", 60.0, "Energy"),
+            // Its monthly series is too old to trust over the cached price
+            stock("STLC", 400.0, "Energy"),
+        ];
+        assert_eq!(reconcile_stale_prices(&mut stocks, "2024-05-20T09:00:00.0", 5.0), 1);
+        assert_eq!(stocks[0].price, 50.0);
+        assert_eq!(stocks[1].price, 60.0);
+        assert_eq!(stocks[2].price, 400.0);
+
+        // A tighter factor also catches the smaller deviation
+        assert_eq!(reconcile_stale_prices(&mut stocks, "2024-05-20T09:00:00.0", 1.1), 1);
+        assert_eq!(stocks[1].price, 50.0);
+    }
+}